@@ -0,0 +1,79 @@
+// Instructions per second of each execution engine on the same loop
+//
+//     cargo bench --bench engines
+
+use std::time::Instant;
+
+use toast_interpreter::{CPUState, DecodeCache, Engine, RunOutcome, Translation};
+
+// Two million iterations of a loop that stores a running xor into a ring
+// buffer at 0x400 and reads it back. The constant load and the loop test are
+// fused by the bytecode.
+const PROGRAM: [u32; 17] = [
+    0x001E82B7, // lui t0, 0x1e8
+    0x48028293, // addi t0, t0, 0x480
+    0x00000513, // addi a0, zero, 0
+    0x40000413, // addi s0, zero, 0x400
+    0x00350593, // addi a1, a0, 3
+    0x00B54533, // xor a0, a0, a1
+    0x00259613, // slli a2, a1, 2
+    0x3FC67613, // andi a2, a2, 0x3fc
+    0x00860633, // add a2, a2, s0
+    0x00A62023, // sw a0, 0(a2)
+    0x00062683, // lw a3, 0(a2)
+    0x0056C533, // xor a0, a3, t0
+    0xFFF28293, // addi t0, t0, -1
+    0x00503333, // sltu t1, zero, t0
+    0xFC031CE3, // bne t1, zero, -40
+    0x00A00893, // addi a7, zero, 10
+    0x00000073, // ecall
+];
+
+// Builds the engine for a program of the given length
+type MakeEngine = fn(&mut CPUState, u32) -> Engine;
+
+/// Runs the program to completion and returns the final state and the
+/// instructions per second.
+fn measure(name: &str, engine: MakeEngine) -> (CPUState, f64) {
+    let program: Vec<u8> = PROGRAM.iter().flat_map(|word| word.to_le_bytes()).collect();
+    let mut cpu_state = CPUState::new();
+    cpu_state.load(&program).unwrap();
+    cpu_state.engine = engine(&mut cpu_state, program.len() as u32);
+    let start = Instant::now();
+    let result = cpu_state.run(u64::MAX);
+    let elapsed = start.elapsed().as_secs_f64();
+    assert_eq!(result.outcome, RunOutcome::Exited(0));
+    let rate = result.instructions as f64 / elapsed;
+    println!(
+        "{:<12} {:>10} instructions in {:>7.3}s, {:>7.2} MIPS",
+        name,
+        result.instructions,
+        elapsed,
+        rate / 1e6
+    );
+    (cpu_state, rate)
+}
+
+fn main() {
+    let (interpreted, interpreter) = measure("interpreter", |_, _| Engine::Interpreter);
+    let engines: [(&str, MakeEngine); 2] = [
+        ("decode cache", |_, _| {
+            Engine::DecodeCache(DecodeCache::new())
+        }),
+        ("bytecode", |cpu_state, len| {
+            Engine::Bytecode(Translation::new(&mut cpu_state.bus, 0, len))
+        }),
+    ];
+    let mut speedups = vec![];
+    for (name, engine) in engines {
+        let (cpu_state, rate) = measure(name, engine);
+        assert_eq!(cpu_state.registers, interpreted.registers, "{}", name);
+        if let Engine::Bytecode(translation) = &cpu_state.engine {
+            println!("{:<12} {:?}", "", translation.fusion_counts());
+        }
+        speedups.push((name, rate / interpreter));
+    }
+    for (name, speedup) in speedups {
+        println!("{} speedup {:.2}x", name, speedup);
+    }
+}
@@ -0,0 +1,129 @@
+// A 16 bit accumulator machine generated by the same `vm!` macro as the
+// RV32IMF decoder, printing the first ten Fibonacci numbers.
+//
+// Every instruction is a 4 bit opcode followed by a 12 bit address or signed
+// immediate. Run with `cargo run --example accumulator`.
+
+use virtual_machine::vm;
+
+const MEMORY_WORDS: usize = 1 << 12;
+
+struct IllegalInstruction(u16);
+
+vm! {
+    /// One accumulator, a program counter and 4K words of memory holding both
+    /// code and data.
+    machine Accumulator: u16 {
+        acc: u16,
+        pc: u16,
+        memory: [u16; MEMORY_WORDS],
+        halted: bool,
+        /// Values written by `out`.
+        output: Vec<u16>,
+    }
+
+    fields {
+        opcode: u16 = 12..16,
+        address: u16 = 0..12,
+        imm: i16 = signed(0..12),
+    }
+
+    #[jump_table]
+    fn execute(&mut self, word: u16) -> Result<(), IllegalInstruction> {
+        load: opcode == 0x0 => {
+            self.acc = self.memory[address as usize];
+            Ok(())
+        },
+        store: opcode == 0x1 => {
+            self.memory[address as usize] = self.acc;
+            Ok(())
+        },
+        add: opcode == 0x2 => {
+            self.acc = self.acc.wrapping_add(self.memory[address as usize]);
+            Ok(())
+        },
+        sub: opcode == 0x3 => {
+            self.acc = self.acc.wrapping_sub(self.memory[address as usize]);
+            Ok(())
+        },
+        loadi: opcode == 0x4 => {
+            self.acc = imm as u16;
+            Ok(())
+        },
+        addi: opcode == 0x5 => {
+            self.acc = self.acc.wrapping_add(imm as u16);
+            Ok(())
+        },
+        jump: opcode == 0x6 => {
+            self.pc = address;
+            Ok(())
+        },
+        jz: opcode == 0x7 => {
+            if self.acc == 0 {
+                self.pc = address;
+            }
+            Ok(())
+        },
+        jn: opcode == 0x8 => {
+            if (self.acc as i16) < 0 {
+                self.pc = address;
+            }
+            Ok(())
+        },
+        out: word == 0xE000 => {
+            self.output.push(self.acc);
+            Ok(())
+        },
+        halt: word == 0xF000 => {
+            self.halted = true;
+            Ok(())
+        },
+        _ => Err(IllegalInstruction(word)),
+    }
+}
+
+impl Accumulator {
+    fn run(&mut self) -> Result<(), IllegalInstruction> {
+        while !self.halted {
+            let word = self.memory[self.pc as usize];
+            self.pc = (self.pc + 1) % MEMORY_WORDS as u16;
+            self.execute(word)?;
+        }
+        Ok(())
+    }
+}
+
+fn instruction(opcode: u16, operand: i16) -> u16 {
+    (opcode << 12) | (operand as u16 & 0xFFF)
+}
+
+fn main() {
+    const A: i16 = 0x100;
+    const B: i16 = 0x101;
+    const COUNT: i16 = 0x102;
+    const SUM: i16 = 0x103;
+    let program = [
+        instruction(0x0, A), // loop: load a
+        0xE000,              // out
+        instruction(0x2, B), // add b
+        instruction(0x1, SUM),
+        instruction(0x0, B), // a = b
+        instruction(0x1, A),
+        instruction(0x0, SUM), // b = a + b
+        instruction(0x1, B),
+        instruction(0x0, COUNT),
+        instruction(0x5, 1),
+        instruction(0x1, COUNT),
+        instruction(0x8, 0), // jn loop
+        0xF000,              // halt
+    ];
+
+    let mut machine = Accumulator::new();
+    machine.memory[..program.len()].copy_from_slice(&program);
+    machine.memory[B as usize] = 1;
+    machine.memory[COUNT as usize] = (-10i16) as u16;
+    match machine.run() {
+        Ok(()) => println!("{:?}", machine.output),
+        Err(IllegalInstruction(word)) => eprintln!("illegal instruction {word:#06x}"),
+    }
+}
@@ -1,279 +1,232 @@
 mod asm;
-use lazy_static::lazy_static;
 use std::collections::HashMap;
-use std::fmt::Binary;
-use std::hash::Hash;
-use std::num::ParseIntError;
 
-use crate::assembler::ast::{ASTInstruction, ASTLabel, ASTWord, Line, LineList};
-use crate::riscv_spec::{
-    BRACKET_INSTRUCTIONS, B_TYPE_INSTRUCTIONS, FUNCT_3_BITS, FUNCT_7_BITS, I_TYPE_INSTRUCTIONS,
-    J_TYPE_INSTRUCTIONS, OPCODE_BITS, REGISTER_BITS, R_TYPE_INSTRUCTIONS, S_TYPE_INSTRUCTIONS,
-    U_TYPE_INSTRUCTIONS,
-};
+use crate::abi::{FLOAT_REGISTER_NAMES, REGISTER_NAMES};
+use crate::assembler::ast::{ASTInstruction, Line, LineList};
+use crate::csr::csr_name;
+use crate::instruction::{Encoding, Operand, DYNAMIC_ROUNDING, ENCODINGS};
 
-fn int<'a>(string: &'a str, base: u32) -> i64 {
-    print!("{}", string);
+fn int(string: &str, base: u32) -> i64 {
     i64::from_str_radix(string, base).unwrap()
 }
 
+// Instructions are emitted little-endian, matching the RISC-V memory layout
 fn int_to_4_byte_vec(integer: i64) -> Vec<u8> {
-    return vec![
-        (integer >> 24 & 0xFF) as u8,
-        (integer >> 16 & 0xFF) as u8,
-        (integer >> 8 & 0xFF) as u8,
+    vec![
         (integer & 0xFF) as u8,
-    ];
+        (integer >> 8 & 0xFF) as u8,
+        (integer >> 16 & 0xFF) as u8,
+        (integer >> 24 & 0xFF) as u8,
+    ]
 }
 
-fn generate_bytes_r_type(tokens: &Vec<String>) -> Vec<u8> {
-    let instruction = tokens[0].to_lowercase();
-    let rd = &tokens[1];
-    let rs1 = &tokens[2];
-    let rs2 = &tokens[3];
-
-    let binary = (int(FUNCT_7_BITS[&instruction], 2) & 0b1111111) << 25
-        | (int(REGISTER_BITS[rs2], 2) & 0b11111) << 20
-        | (int(REGISTER_BITS[rs1], 2) & 0b11111) << 15
-        | (int(FUNCT_3_BITS[&instruction], 2) & 0b111) << 12
-        | (int(REGISTER_BITS[rd], 2) & 0b11111) << 7
-        | (int(OPCODE_BITS[&instruction], 2) & 0b1111111);
-
-    int_to_4_byte_vec(binary)
+// Registers are written by number, x5 or f5, or by ABI name
+fn register(token: &str) -> i64 {
+    let number = token
+        .strip_prefix('x')
+        .or_else(|| token.strip_prefix('f'))
+        .and_then(|number| number.parse::<usize>().ok())
+        .filter(|&number| number < 32);
+    let named = || {
+        REGISTER_NAMES
+            .iter()
+            .chain(&FLOAT_REGISTER_NAMES)
+            .position(|&name| name == token)
+            .map(|position| position % 32)
+    };
+    match number.or_else(named) {
+        Some(number) => number as i64,
+        None if token == "fp" => 8,
+        None => panic!("unknown register {}", token),
+    }
 }
 
-fn generate_bytes_i_type(tokens: &Vec<String>) -> Vec<u8> {
-    let instruction = tokens[0].to_lowercase();
-    let opcode = int(OPCODE_BITS[&instruction], 2);
-    let funct_3 = int(FUNCT_3_BITS[&instruction], 2);
-    let rd = int(REGISTER_BITS[&tokens[1]], 2);
-    let imm;
-    let rs1;
-
-    if BRACKET_INSTRUCTIONS.contains(&instruction.as_str()) {
-        // println!("{}",tokens[2]);
-        // let rs1_string = REGISTER_BITS[tokens[2].split("(").nth(1).unwrap()];
-        // rs1 = int(&rs1_string[..rs1_string.len() - 1], 2) & 0xFFFFFFFF;
-        // // TODO handle none base 10
-        // imm = int(tokens[2].split("(").nth(0).unwrap(), 10);
-        rs1 = int(REGISTER_BITS[&tokens[3]], 2);
-        // TODO handle none base 10
-        imm = int(&tokens[2], 10) & 0xFFFFFFFF;
+fn immediate(token: &str) -> i64 {
+    let (negative, digits) = match token.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, token),
+    };
+    let value = match digits.strip_prefix("0x") {
+        Some(hex) => int(hex, 16),
+        None => int(digits, 10),
+    };
+    if negative {
+        -value
     } else {
-        // println!("{:?}",tokens);
-        rs1 = int(REGISTER_BITS[&tokens[2]], 2);
-        // TODO handle none base 10
-        imm = int(&tokens[3], 10) & 0xFFFFFFFF;
+        value
     }
-
-    let binary = (imm & 0xFFF) << 20
-        | ((rs1 & 0b11111) << 15)
-        | ((funct_3 & 0b111) << 12)
-        | ((rd & 0b11111) << 7)
-        | (opcode & 0b1111111);
-
-    int_to_4_byte_vec(binary)
 }
 
-fn generate_bytes_s_type(tokens: &Vec<String>) -> Vec<u8> {
-    let instruction = tokens[0].to_lowercase();
-    let opcode = int(OPCODE_BITS[&instruction], 2);
-    let funct_3 = int(FUNCT_3_BITS[&instruction], 2);
-    let rs2 = int(REGISTER_BITS[&tokens[1]], 2);
-    // TODO deal with none base 10 cases
-    let imm = int(&tokens[2], 10);
-    let rs1 = int(REGISTER_BITS[&tokens[3]], 2);
-
-    let binary = (((imm >> 5) & 0xFF) << 25)
-        | ((rs2 & 0b11111) << 12)
-        | ((rs1 & 0b11111) << 15)
-        | ((funct_3 & 0b111) << 12)
-        | (((imm) & 0x1F) << 7)
-        | (opcode & 0b1111111);
-
-    int_to_4_byte_vec(binary)
+fn rounding_mode(token: &str) -> i64 {
+    match token {
+        "rne" => 0,
+        "rtz" => 1,
+        "rdn" => 2,
+        "rup" => 3,
+        "rmm" => 4,
+        "dyn" => DYNAMIC_ROUNDING as i64,
+        _ => immediate(token),
+    }
 }
 
-fn generate_bytes_u_type(tokens: &Vec<String>) -> Vec<u8> {
-    let instruction = tokens[0].to_lowercase();
-    let opcode = int(OPCODE_BITS[&instruction], 2);
-    let rd = int(REGISTER_BITS[&tokens[1]], 2);
-    // TODO deal with none base 10 cases
-    let imm = int(&tokens[2], 10);
-
-    let binary = ((imm & 0xFFFFF) << 12) | ((rd & 0b11111) << 7) | (opcode & 0b1111111);
-
-    int_to_4_byte_vec(binary)
+fn csr(token: &str) -> i64 {
+    match (0..1 << 12).find(|&csr| csr_name(csr) == Some(token)) {
+        Some(csr) => csr as i64,
+        None => immediate(token),
+    }
 }
 
-fn generate_bytes_b_type(tokens: &Vec<String>) -> Vec<u8> {
-    let instruction = tokens[0].to_lowercase();
-    let opcode = int(OPCODE_BITS[&instruction], 2);
-    let funct_3 = int(FUNCT_3_BITS[&instruction], 2);
-    let rs1 = int(REGISTER_BITS[&tokens[1].to_lowercase()], 2);
-    let rs2 = int(REGISTER_BITS[&tokens[2].to_lowercase()], 2);
-    // TODO deal with none base 10 cases
-    let imm = int(&tokens[3], 10);
-
-    let mut binary = (opcode & 0b1111111)
-        | (rs1 & 0b11111) << 15
-        | (rs2 & 0b11111) << 20
-        | (funct_3 & 0b111) << 12;
-
-    binary |= ((imm >> 12) & 0b1) << 31;
-    binary |= ((imm >> 5) & 0b111111) << 25;
-    binary |= ((imm >> 11) & 0b1) << 7;
-    binary |= ((imm >> 1) & 0b1111) << 8;
-    int_to_4_byte_vec(binary)
+// The i, o, r and w bits of a fence, or a number
+fn fence_set(token: &str) -> i64 {
+    if !token.is_empty() && token.chars().all(|c| "iorw".contains(c)) {
+        return "iorw"
+            .chars()
+            .enumerate()
+            .filter(|&(_, c)| token.contains(c))
+            .map(|(i, _)| 0b1000 >> i)
+            .sum();
+    }
+    immediate(token)
 }
 
-fn generate_bytes_j_type(tokens: &Vec<String>) -> Vec<u8> {
-    let instruction = tokens[0].to_lowercase();
-    let opcode = int(OPCODE_BITS[&instruction], 2);
-    println!("{}",tokens[1]);
-    let rd = int(REGISTER_BITS[&tokens[1]], 2);
-    // TODO deal with none base 10 cases
-    let imm = int(&tokens[2], 10);
-
-    let mut binary = ((rd & 0b11111) << 7) | (opcode & 0b1111111);
-
-    binary |= (((imm >> 12) & 0b11111111) << 12)
-        | (((imm >> 11) & 0b1) << 20)
-        | (((imm >> 1) & 0b1111111111) << 21)
-        | (((imm >> 20) & 0b1) << 31);
+// Operands are parsed by the field they are written to. A label is the
+// offset from the instruction at `pc` in the branch and jump fields and its
+// address elsewhere
+fn operand(token: &str, operand: &Operand, pc: i64, labels: &HashMap<String, i64>) -> i64 {
+    if let Some(&address) = labels.get(token) {
+        return match operand.name {
+            "imm_b" | "imm_j" => address - pc,
+            _ => address,
+        };
+    }
+    match operand.name {
+        "rd" | "rs1" | "rs2" | "rs3" => register(token),
+        "rm" => rounding_mode(token),
+        "csr" => csr(token),
+        "pred" | "succ" => fence_set(token),
+        _ => immediate(token),
+    }
+}
 
-    int_to_4_byte_vec(binary)
+fn encoding(mnemonic: &str) -> &'static Encoding {
+    ENCODINGS
+        .iter()
+        .find(|encoding| encoding.mnemonic == mnemonic)
+        .unwrap_or_else(|| panic!("unknown instruction {}", mnemonic))
 }
 
-fn remove_labels_from_tokens(tokens: &mut Vec<String>, labels: &HashMap<String, i64>) {
-    for item in tokens.iter_mut() {
-        // println!("{}",item);
-        if let Some(replacement) = labels.get(item) {
-            // println!("{}",item);
-            *item = (*replacement).to_string();
-        }
+// Encodes the instruction at `pc` from the table generated with the decoder,
+// so every instruction the interpreter decodes can be assembled
+fn encode(tokens: &[String], pc: i64, labels: &HashMap<String, i64>) -> Vec<u8> {
+    let mnemonic = tokens[0].to_lowercase();
+    let encoding = encoding(&mnemonic);
+    let operands = encoding.operands;
+    // the rounding mode may be left out to use the one in frm
+    let rounds = matches!(operands.last(), Some(operand) if operand.name == "rm");
+    let given = tokens.len() - 1;
+    if given != operands.len() && !(rounds && given + 1 == operands.len()) {
+        panic!(
+            "{} takes {} operands, found {}",
+            mnemonic,
+            operands.len(),
+            given
+        );
+    }
+    let mut values: Vec<i64> = tokens[1..]
+        .iter()
+        .zip(operands)
+        .map(|(token, field)| operand(token, field, pc, labels))
+        .collect();
+    if given < operands.len() {
+        values.push(DYNAMIC_ROUNDING as i64);
     }
+    let mut word = encoding.bits;
+    for (value, field) in values.into_iter().zip(operands) {
+        word = field
+            .insert(word, value)
+            .unwrap_or_else(|| panic!("{} does not fit in {} of {}", value, field.name, mnemonic));
+    }
+    int_to_4_byte_vec(word as i64)
 }
 
-lazy_static! {
-    static ref INSTRUCTION_METHOD_MAP: HashMap<String, fn(&Vec<String>) -> Vec<u8>> = {
-        let mut map: HashMap<String, fn(&Vec<String>) -> Vec<u8>> = HashMap::new();
-        let pairs: [(&Vec<&str>, fn(&Vec<String>) -> Vec<u8>); 6] = [
-            (&Vec::from(R_TYPE_INSTRUCTIONS), generate_bytes_r_type),
-            (&Vec::from(I_TYPE_INSTRUCTIONS), generate_bytes_i_type),
-            (&Vec::from(S_TYPE_INSTRUCTIONS), generate_bytes_s_type),
-            (&Vec::from(U_TYPE_INSTRUCTIONS), generate_bytes_u_type),
-            (&Vec::from(B_TYPE_INSTRUCTIONS), generate_bytes_b_type),
-            (&Vec::from(J_TYPE_INSTRUCTIONS), generate_bytes_j_type),
-        ];
-        for (instruction_list, method) in pairs {
-            for opcode in instruction_list {
-                let opcode = *opcode;
-                map.insert((*opcode).to_string(), method);
-            }
-        }
-        map
-    };
+// The bytes a line assembles to
+fn line_size(line: &Line) -> i64 {
+    match line {
+        Line::ASTInstruction(_) => 4,
+        Line::ASTWord(word) => 4 * word.tokens.len() as i64,
+        Line::ASTLabel(label) => line_size(&label.labelled_line),
+    }
 }
 
-fn generate_bytes_line(line: Line, labels: &HashMap<String, i64>) -> Vec<u8> {
-    // TODO
-    // Create map of opcode to function
-    // Create function for each type of instruction
-    //
+fn generate_bytes_line(line: Line, pc: i64, labels: &HashMap<String, i64>) -> Vec<u8> {
     match line {
-        Line::ASTInstruction(mut ast_instruction) => {
-            println!("{:?}", (*ast_instruction).tokens);
-            let instruction: String = (*ast_instruction).tokens[0].to_string();
-            if instruction.to_lowercase() == "ecall" {
-                return int_to_4_byte_vec(int("00000073", 16));
-            }
-            let method = INSTRUCTION_METHOD_MAP[&instruction];
-            remove_labels_from_tokens(&mut (*ast_instruction).tokens, labels);
-            return method(&(*ast_instruction).tokens);
-        }
-
-        Line::ASTWord(word) => {
-            // TODO
-        }
-
-        Line::ASTLabel(_) => {}
+        Line::ASTInstruction(ast_instruction) => encode(&ast_instruction.tokens, pc, labels),
+        // each value is a 32 bit word, a label is its address
+        Line::ASTWord(word) => word
+            .tokens
+            .iter()
+            .flat_map(|token| {
+                let value = labels
+                    .get(token)
+                    .copied()
+                    .unwrap_or_else(|| immediate(token));
+                int_to_4_byte_vec(value)
+            })
+            .collect(),
+        Line::ASTLabel(_) => vec![],
     }
-    vec![]
 }
 
-fn pseudo_parse_line(line: Line) -> Vec<Box<Line>> {
-    // println!("{:?}",line);
+fn instruction(tokens: &[&str]) -> Line {
+    Line::ASTInstruction(Box::new(ASTInstruction::new(
+        tokens.iter().map(|token| token.to_string()).collect(),
+    )))
+}
+
+fn pseudo_parse_line(line: Line) -> Vec<Line> {
     match line {
         Line::ASTInstruction(ast_instruction) => match ast_instruction.tokens[0].as_str() {
             "li" => {
-                let rd = ast_instruction.tokens[1].clone();
-                let imm = ast_instruction.tokens[2].parse::<i32>().unwrap();
-                if (imm < 2048) && (imm >= -2048) {
-                    return vec![Box::new(Line::ASTInstruction(Box::new(
-                        ASTInstruction::new(vec![
-                            "addi".to_string(),
-                            rd,
-                            "x0".to_string(),
-                            imm.to_string(),
-                        ]),
-                    )))];
-                } else {
-                    let imm_hi = imm >> 12;
-                    let imm_lo = imm & 0xFF;
-                    return vec![
-                        Box::new(Line::ASTInstruction(Box::new(ASTInstruction::new(vec![
-                            "lui".to_string(),
-                            rd.clone(),
-                            imm_hi.to_string(),
-                        ])))),
-                        Box::new(Line::ASTInstruction(Box::new(ASTInstruction::new(vec![
-                            "addi".to_string(),
-                            rd.clone(),
-                            rd.clone(),
-                            imm_lo.to_string(),
-                        ])))),
-                    ];
+                let rd = ast_instruction.tokens[1].as_str();
+                let imm = immediate(&ast_instruction.tokens[2]) as i32;
+                if (-2048..2048).contains(&imm) {
+                    return vec![instruction(&["addi", rd, "x0", &imm.to_string()])];
                 }
-                vec![]
+                // addi sign-extends the low 12 bits, so the upper part is
+                // rounded to make up for a negative low part
+                let imm_lo = imm << 20 >> 20;
+                let imm_hi = (imm.wrapping_sub(imm_lo) >> 12) & 0xFFFFF;
+                vec![
+                    instruction(&["lui", rd, &imm_hi.to_string()]),
+                    instruction(&["addi", rd, rd, &imm_lo.to_string()]),
+                ]
             }
 
-            "j" => {
-                return vec![Box::new(Line::ASTInstruction(Box::new(
-                    ASTInstruction::new(vec![
-                        "addi".to_string(),
-                        "x0".to_string(),
-                    ]),
-                )))];
-            }
+            "j" => vec![instruction(&["jal", "x0", &ast_instruction.tokens[1]])],
 
-            _ => vec![Box::new(Line::ASTInstruction(ast_instruction))],
+            _ => vec![Line::ASTInstruction(ast_instruction)],
         },
 
         Line::ASTLabel(ast_label) => {
             let mut ast_label = ast_label;
             let mut sub_lines = pseudo_parse_line(*ast_label.labelled_line);
-            // println!("{:?}", sub_lines);
-            ast_label.labelled_line = Box::new((*sub_lines[0]).clone());
-            sub_lines.remove(0);
-            sub_lines.insert(0, Box::new(Line::ASTLabel(ast_label)));
+            ast_label.labelled_line = Box::new(sub_lines.remove(0));
+            sub_lines.insert(0, Line::ASTLabel(ast_label));
             sub_lines
         }
 
         Line::ASTWord(ast_word) => {
-            vec![Box::new(Line::ASTWord(ast_word))]
+            vec![Line::ASTWord(ast_word)]
         }
     }
 }
 
-fn pseudo_parse_lines(lines: LineList) -> LineList {
-    let mut new_lines: Vec<Box<Line>> = vec![];
-
-    for line in lines.lines {
-        new_lines.append(&mut pseudo_parse_line(*line))
-    }
-
-    LineList::new(new_lines)
+fn pseudo_parse_lines(lines: LineList) -> Vec<Line> {
+    lines
+        .lines
+        .into_iter()
+        .flat_map(|line| pseudo_parse_line(*line))
+        .collect()
 }
 
 fn pre_process(program_text: &str) -> String {
@@ -290,17 +243,14 @@ fn pre_process(program_text: &str) -> String {
         .filter(|line| !line.trim().is_empty())
         .collect::<Vec<&str>>()
         .join("\n");
-    text = text + "\n";
-    text = text.replace("\n", ";");
-    text = text.replace(",", " ");
-    let text = String::from("{") + &text + "}";
-    text
+    text += "\n";
+    text = text.replace('\n', ";");
+    text = text.replace(',', " ");
+    String::from("{") + &text + "}"
 }
 
-pub fn assemble(program_text: &String) -> Vec<u8> {
-    // TODO better passing of li instructions to support labels
-
-    let processed_program_text = pre_process(&program_text);
+pub fn assemble(program_text: &str) -> Vec<u8> {
+    let processed_program_text = pre_process(program_text);
 
     let program_ast: LineList = asm::ProgramParser::new()
         .parse(&processed_program_text)
@@ -308,23 +258,22 @@ pub fn assemble(program_text: &String) -> Vec<u8> {
 
     let pre_processed_ast = pseudo_parse_lines(program_ast);
 
+    // labels are the address of the line they name, counted from 0
     let mut labels: HashMap<String, i64> = HashMap::new();
-    let mut cleaned_lines: Vec<Box<Line>> = vec![];
-    for (index, line) in pre_processed_ast.lines.iter().enumerate() {
-        let mut line = *(*line).clone();
+    let mut cleaned_lines: Vec<Line> = vec![];
+    let mut address = 0;
+    for mut line in pre_processed_ast {
         while let Line::ASTLabel(label) = line {
-            labels.insert((*label.label).to_string(), (index * 4) as i64);
+            labels.insert(label.label, address);
             line = *label.labelled_line;
         }
-        cleaned_lines.push(Box::new(line));
+        address += line_size(&line);
+        cleaned_lines.push(line);
     }
-    let cleaned_lines = LineList::new(cleaned_lines);
-    // println!("{:?}", &cleaned_lines);
-    // println!("{:?}", &labels);
     let mut binary: Vec<u8> = vec![];
-    for line in cleaned_lines.lines {
-        binary.append(&mut generate_bytes_line(*line, &labels));
+    for line in cleaned_lines {
+        let pc = binary.len() as i64;
+        binary.append(&mut generate_bytes_line(line, pc, &labels));
     }
-    print!("{:?}", &binary);
     binary
 }
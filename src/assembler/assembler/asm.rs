@@ -1,5 +1,5 @@
 // auto-generated: "lalrpop 0.20.2"
-// sha3: 83ef0e2604e133f6baca3372db7184b47c94e3ab28f8a9d9521f2b8e9d106cc7
+// sha3: 890dfae1be8370884876838a42891ac7e0add8f9cf30c5f9587cb6482274bf07
 use std::str::FromStr;
 use crate::assembler::ast::{Line, ASTInstruction, ASTLabel, ASTWord, LineList};
 use crate::assembler::utils::append;
@@ -173,7 +173,7 @@ mod __parse__Line {
         r###"";""###,
         r###""{""###,
         r###""}""###,
-        r###"r#"[-_a-zA-Z_$0-9]+"#"###,
+        r###"r#"[-_a-zA-Z_$0-9.]+"#"###,
     ];
     fn __expected_tokens(__state: i8) -> alloc::vec::Vec<alloc::string::String> {
         __TERMINAL.iter().enumerate().filter_map(|(index, terminal)| {
@@ -201,13 +201,13 @@ mod __parse__Line {
         }).collect()
     }
     struct __StateMachine<'input>
-    where
+    where 
     {
         input: &'input str,
         __phantom: core::marker::PhantomData<(&'input ())>,
     }
     impl<'input> __state_machine::ParserDefinition for __StateMachine<'input>
-    where
+    where 
     {
         type Location = usize;
         type Error = &'static str;
@@ -980,7 +980,7 @@ mod __parse__Line {
         _: core::marker::PhantomData<(&'input ())>,
     ) -> (usize, usize)
     {
-        // Token = r#"[-_a-zA-Z_$0-9]+"# => ActionFn(20);
+        // Token = r#"[-_a-zA-Z_$0-9.]+"# => ActionFn(20);
         let __sym0 = __pop_Variant0(__symbols);
         let __start = __sym0.0;
         let __end = __sym0.2;
@@ -1179,7 +1179,7 @@ mod __parse__Line12 {
         r###"";""###,
         r###""{""###,
         r###""}""###,
-        r###"r#"[-_a-zA-Z_$0-9]+"#"###,
+        r###"r#"[-_a-zA-Z_$0-9.]+"#"###,
     ];
     fn __expected_tokens(__state: i8) -> alloc::vec::Vec<alloc::string::String> {
         __TERMINAL.iter().enumerate().filter_map(|(index, terminal)| {
@@ -1207,13 +1207,13 @@ mod __parse__Line12 {
         }).collect()
     }
     struct __StateMachine<'input>
-    where
+    where 
     {
         input: &'input str,
         __phantom: core::marker::PhantomData<(&'input ())>,
     }
     impl<'input> __state_machine::ParserDefinition for __StateMachine<'input>
-    where
+    where 
     {
         type Location = usize;
         type Error = &'static str;
@@ -1986,7 +1986,7 @@ mod __parse__Line12 {
         _: core::marker::PhantomData<(&'input ())>,
     ) -> (usize, usize)
     {
-        // Token = r#"[-_a-zA-Z_$0-9]+"# => ActionFn(20);
+        // Token = r#"[-_a-zA-Z_$0-9.]+"# => ActionFn(20);
         let __sym0 = __pop_Variant0(__symbols);
         let __start = __sym0.0;
         let __end = __sym0.2;
@@ -2298,7 +2298,7 @@ mod __parse__Lines {
         r###"";""###,
         r###""{""###,
         r###""}""###,
-        r###"r#"[-_a-zA-Z_$0-9]+"#"###,
+        r###"r#"[-_a-zA-Z_$0-9.]+"#"###,
     ];
     fn __expected_tokens(__state: i8) -> alloc::vec::Vec<alloc::string::String> {
         __TERMINAL.iter().enumerate().filter_map(|(index, terminal)| {
@@ -2326,13 +2326,13 @@ mod __parse__Lines {
         }).collect()
     }
     struct __StateMachine<'input>
-    where
+    where 
     {
         input: &'input str,
         __phantom: core::marker::PhantomData<(&'input ())>,
     }
     impl<'input> __state_machine::ParserDefinition for __StateMachine<'input>
-    where
+    where 
     {
         type Location = usize;
         type Error = &'static str;
@@ -3105,7 +3105,7 @@ mod __parse__Lines {
         _: core::marker::PhantomData<(&'input ())>,
     ) -> (usize, usize)
     {
-        // Token = r#"[-_a-zA-Z_$0-9]+"# => ActionFn(20);
+        // Token = r#"[-_a-zA-Z_$0-9.]+"# => ActionFn(20);
         let __sym0 = __pop_Variant0(__symbols);
         let __start = __sym0.0;
         let __end = __sym0.2;
@@ -3430,7 +3430,7 @@ mod __parse__Program {
         r###"";""###,
         r###""{""###,
         r###""}""###,
-        r###"r#"[-_a-zA-Z_$0-9]+"#"###,
+        r###"r#"[-_a-zA-Z_$0-9.]+"#"###,
     ];
     fn __expected_tokens(__state: i8) -> alloc::vec::Vec<alloc::string::String> {
         __TERMINAL.iter().enumerate().filter_map(|(index, terminal)| {
@@ -3458,13 +3458,13 @@ mod __parse__Program {
         }).collect()
     }
     struct __StateMachine<'input>
-    where
+    where 
     {
         input: &'input str,
         __phantom: core::marker::PhantomData<(&'input ())>,
     }
     impl<'input> __state_machine::ParserDefinition for __StateMachine<'input>
-    where
+    where 
     {
         type Location = usize;
         type Error = &'static str;
@@ -4237,7 +4237,7 @@ mod __parse__Program {
         _: core::marker::PhantomData<(&'input ())>,
     ) -> (usize, usize)
     {
-        // Token = r#"[-_a-zA-Z_$0-9]+"# => ActionFn(20);
+        // Token = r#"[-_a-zA-Z_$0-9.]+"# => ActionFn(20);
         let __sym0 = __pop_Variant0(__symbols);
         let __start = __sym0.0;
         let __end = __sym0.2;
@@ -4389,7 +4389,7 @@ mod __intern_token {
     extern crate alloc;
     pub fn new_builder() -> __lalrpop_util::lexer::MatcherBuilder {
         let __strs: &[(&str, bool)] = &[
-            ("[\\$\\-0-9A-Z_a-z]+", false),
+            ("[\\$\\-\\.0-9A-Z_a-z]+", false),
             ("\\(", false),
             ("\\)", false),
             ("(?:\\.word)", false),
@@ -0,0 +1,92 @@
+// Runs a riscv-tests or riscv-arch-test ELF and reports its result
+//
+// Console output the program makes through HTIF is copied to stdout.
+//
+// cargo run --bin compliance -- program.elf [--signature FILE] [--max-cycles N]
+//
+// With --signature the words between `begin_signature` and `end_signature`
+// are written to FILE in the format of the reference signatures.
+
+use std::env;
+use std::fs;
+use std::process::ExitCode;
+
+use toast_interpreter::compliance::{self, format_signature, TestOutcome};
+use toast_interpreter::{Elf, Htif};
+
+const MAX_CYCLES: u64 = 10_000_000;
+
+struct Options {
+    program: String,
+    signature: Option<String>,
+    max_cycles: u64,
+}
+
+fn parse_options(mut arguments: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut program = None;
+    let mut signature = None;
+    let mut max_cycles = MAX_CYCLES;
+    while let Some(argument) = arguments.next() {
+        match argument.as_str() {
+            "--signature" => {
+                signature = Some(arguments.next().ok_or("--signature needs a file")?);
+            }
+            "--max-cycles" => {
+                let value = arguments.next().ok_or("--max-cycles needs a number")?;
+                max_cycles = value
+                    .parse()
+                    .map_err(|_| format!("invalid cycle count `{}`", value))?;
+            }
+            _ if program.is_none() => program = Some(argument),
+            _ => return Err(format!("unexpected argument `{}`", argument)),
+        }
+    }
+    Ok(Options {
+        program: program.ok_or("no program given")?,
+        signature,
+        max_cycles,
+    })
+}
+
+fn run(options: &Options) -> Result<TestOutcome, String> {
+    let bytes =
+        fs::read(&options.program).map_err(|error| format!("{}: {}", options.program, error))?;
+    let elf = Elf::parse(&bytes).map_err(|error| error.to_string())?;
+    let mut cpu_state = compliance::load(&elf).map_err(|error| error.to_string())?;
+    let outcome = compliance::run_test(&mut cpu_state, options.max_cycles);
+    if let Some(htif) = cpu_state.bus.device::<Htif>() {
+        print!("{}", String::from_utf8_lossy(htif.output()));
+    }
+    if let Some(path) = &options.signature {
+        let words =
+            compliance::signature(&mut cpu_state, &elf).map_err(|error| error.to_string())?;
+        fs::write(path, format_signature(&words))
+            .map_err(|error| format!("{}: {}", path, error))?;
+    }
+    Ok(outcome)
+}
+
+fn main() -> ExitCode {
+    let options = match parse_options(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("{}", error);
+            eprintln!("usage: compliance program.elf [--signature FILE] [--max-cycles N]");
+            return ExitCode::from(2);
+        }
+    };
+    match run(&options) {
+        Ok(TestOutcome::Passed) => {
+            println!("{}: passed", options.program);
+            ExitCode::SUCCESS
+        }
+        Ok(outcome) => {
+            println!("{}: {:?}", options.program, outcome);
+            ExitCode::FAILURE
+        }
+        Err(error) => {
+            eprintln!("{}", error);
+            ExitCode::from(2)
+        }
+    }
+}
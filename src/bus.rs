@@ -0,0 +1,262 @@
+// Memory mapped bus connecting the hart to RAM, ROM and peripherals
+
+use std::any::Any;
+use std::collections::HashSet;
+
+use crate::trace::{AccessKind, MemoryAccess};
+
+/// A peripheral or memory that can be mapped onto the `Bus`.
+///
+/// Offsets are relative to the base address the device is mapped at and
+/// accesses are 1, 2 or 4 bytes wide. Returning `None` from `read` or `write`
+/// raises an access fault in the guest. Devices are `Clone` so that a hart
+/// can be snapshotted along with its bus.
+pub trait Device: Any + DeviceClone {
+    /// Size in bytes of the address range the device occupies.
+    fn size(&self) -> u32;
+
+    fn read(&mut self, offset: u32, size: usize) -> Option<u32>;
+
+    fn write(&mut self, offset: u32, size: usize, value: u32) -> Option<()>;
+
+    /// Called once per cycle, returns the mip bits the device is asserting.
+    fn tick(&mut self) -> u32 {
+        0
+    }
+
+    /// Whether guest stores can succeed, checked by system calls before they
+    /// consume input they would have nowhere to store.
+    fn writable(&self) -> bool {
+        true
+    }
+
+    /// Initialises the device contents, used by program loaders so that
+    /// read-only devices can still be filled.
+    fn load(&mut self, offset: u32, data: &[u8]) -> Option<()> {
+        for (i, byte) in data.iter().enumerate() {
+            self.write(offset + i as u32, 1, *byte as u32)?;
+        }
+        Some(())
+    }
+}
+
+/// Boxes a copy of a device, implemented for every `Device` that is `Clone`.
+pub trait DeviceClone {
+    fn clone_device(&self) -> Box<dyn Device>;
+}
+
+impl<D: Device + Clone> DeviceClone for D {
+    fn clone_device(&self) -> Box<dyn Device> {
+        Box::new(self.clone())
+    }
+}
+
+/// log2 of the size of the blocks writes to cached code are tracked in, small
+/// so that data next to code does not keep invalidating it.
+pub(crate) const CODE_BLOCK_SHIFT: u32 = 6;
+
+struct Mapping {
+    base: u32,
+    device: Box<dyn Device>,
+}
+
+impl Clone for Mapping {
+    fn clone(&self) -> Self {
+        Mapping {
+            base: self.base,
+            device: self.device.clone_device(),
+        }
+    }
+}
+
+impl Mapping {
+    fn offset(&self, address: u32, size: usize) -> Option<u32> {
+        let offset = address.checked_sub(self.base)?;
+        if offset as u64 + size as u64 > self.device.size() as u64 {
+            return None;
+        }
+        Some(offset)
+    }
+}
+
+#[derive(Default, Clone)]
+pub struct Bus {
+    mappings: Vec<Mapping>,
+    asserted_interrupts: u32,
+    /// Data accesses made while an instruction is being traced.
+    recorded_accesses: Option<Vec<MemoryAccess>>,
+    /// Blocks the decode cache holds instructions from.
+    code_blocks: HashSet<u32>,
+    /// Code blocks written since the decode cache last checked.
+    written_code_blocks: Vec<u32>,
+}
+
+impl Bus {
+    pub fn new() -> Self {
+        Bus {
+            mappings: vec![],
+            asserted_interrupts: 0,
+            recorded_accesses: None,
+            code_blocks: HashSet::new(),
+            written_code_blocks: vec![],
+        }
+    }
+
+    /// Maps `device` at `base`, panics if it overlaps an existing mapping.
+    pub fn map<D: Device>(&mut self, base: u32, device: D) {
+        let end = base as u64 + device.size() as u64;
+        for mapping in &self.mappings {
+            let mapping_end = mapping.base as u64 + mapping.device.size() as u64;
+            assert!(
+                end <= mapping.base as u64 || base as u64 >= mapping_end,
+                "device at {:#010x} overlaps the device at {:#010x}",
+                base,
+                mapping.base
+            );
+        }
+        self.mappings.push(Mapping {
+            base,
+            device: Box::new(device),
+        });
+    }
+
+    /// Maps `device` at `base` in front of whatever is mapped there already,
+    /// accesses to its range reach it instead. Used for registers that live
+    /// at addresses a program links into RAM, such as HTIF's `tohost`.
+    pub fn map_over<D: Device>(&mut self, base: u32, device: D) {
+        self.mappings.insert(
+            0,
+            Mapping {
+                base,
+                device: Box::new(device),
+            },
+        );
+    }
+
+    /// The first mapped device of type `D`.
+    pub fn device<D: Device>(&self) -> Option<&D> {
+        self.mappings
+            .iter()
+            .find_map(|mapping| (mapping.device.as_ref() as &dyn Any).downcast_ref::<D>())
+    }
+
+    pub fn device_mut<D: Device>(&mut self) -> Option<&mut D> {
+        self.mappings
+            .iter_mut()
+            .find_map(|mapping| (mapping.device.as_mut() as &mut dyn Any).downcast_mut::<D>())
+    }
+
+    fn find(&mut self, address: u32, size: usize) -> Option<(&mut Mapping, u32)> {
+        self.mappings.iter_mut().find_map(|mapping| {
+            let offset = mapping.offset(address, size)?;
+            Some((mapping, offset))
+        })
+    }
+
+    pub fn read(&mut self, address: u32, size: usize) -> Option<u32> {
+        let value = self.fetch(address, size)?;
+        self.record(AccessKind::Read, address, size, value);
+        Some(value)
+    }
+
+    pub fn write(&mut self, address: u32, size: usize, value: u32) -> Option<()> {
+        let (mapping, offset) = self.find(address, size)?;
+        mapping.device.write(offset, size, value)?;
+        self.record(AccessKind::Write, address, size, value);
+        self.note_write(address, size);
+        Some(())
+    }
+
+    /// A read that is never recorded, used for instruction fetch.
+    pub(crate) fn fetch(&mut self, address: u32, size: usize) -> Option<u32> {
+        let (mapping, offset) = self.find(address, size)?;
+        mapping.device.read(offset, size)
+    }
+
+    fn record(&mut self, kind: AccessKind, address: u32, size: usize, value: u32) {
+        if let Some(accesses) = &mut self.recorded_accesses {
+            accesses.push(MemoryAccess {
+                kind,
+                address,
+                size,
+                value,
+            });
+        }
+    }
+
+    pub(crate) fn start_recording(&mut self) {
+        self.recorded_accesses = Some(vec![]);
+    }
+
+    pub(crate) fn stop_recording(&mut self) -> Vec<MemoryAccess> {
+        self.recorded_accesses.take().unwrap_or_default()
+    }
+
+    /// How many of the `len` bytes from `address` guest stores can reach,
+    /// counting up to the first byte that is unmapped or read-only. The
+    /// devices themselves are not accessed.
+    pub fn writable_len(&self, address: u32, len: usize) -> usize {
+        let end = address as u64 + len as u64;
+        let mut position = address as u64;
+        while position < end {
+            let Some(mapping) = self.mappings.iter().find(|mapping| {
+                let base = mapping.base as u64;
+                base <= position && position < base + mapping.device.size() as u64
+            }) else {
+                break;
+            };
+            if !mapping.device.writable() {
+                break;
+            }
+            position = (mapping.base as u64 + mapping.device.size() as u64).min(end);
+        }
+        (position - address as u64) as usize
+    }
+
+    /// Copies `data` into the device mapped at `address`, bypassing write
+    /// protection.
+    pub fn load(&mut self, address: u32, data: &[u8]) -> Option<()> {
+        let (mapping, offset) = self.find(address, data.len())?;
+        mapping.device.load(offset, data)?;
+        self.note_write(address, data.len());
+        Some(())
+    }
+
+    /// Starts reporting writes to the block containing `address`.
+    pub(crate) fn watch_code(&mut self, address: u32) {
+        self.code_blocks.insert(address >> CODE_BLOCK_SHIFT);
+    }
+
+    /// The watched blocks written since the last call, they are no longer
+    /// watched.
+    pub(crate) fn take_written_code_blocks(&mut self) -> Option<Vec<u32>> {
+        if self.written_code_blocks.is_empty() {
+            return None;
+        }
+        Some(std::mem::take(&mut self.written_code_blocks))
+    }
+
+    fn note_write(&mut self, address: u32, size: usize) {
+        if self.code_blocks.is_empty() || size == 0 {
+            return;
+        }
+        let first = address >> CODE_BLOCK_SHIFT;
+        let last = ((address as u64 + size as u64 - 1) >> CODE_BLOCK_SHIFT) as u32;
+        for block in first..=last {
+            if self.code_blocks.remove(&block) {
+                self.written_code_blocks.push(block);
+            }
+        }
+    }
+
+    /// Ticks every device and returns the union of the mip bits they assert
+    /// along with the bits that were asserted on the previous tick.
+    pub(crate) fn tick(&mut self) -> (u32, u32) {
+        let previous = self.asserted_interrupts;
+        self.asserted_interrupts = self
+            .mappings
+            .iter_mut()
+            .fold(0, |bits, mapping| bits | mapping.device.tick());
+        (previous, self.asserted_interrupts)
+    }
+}
@@ -0,0 +1,347 @@
+// Translation of RISC-V machine code into a bytecode that is cheaper to
+// interpret
+//
+// RISC-V spreads the operation over the opcode, funct3 and funct7 fields and
+// scatters immediates across the word. In the bytecode every instruction is
+// an `Op` whose first byte selects the exact operation, followed by register
+// numbers as bytes and the immediate as a contiguous, already sign-extended
+// i32, so executing one is a single match with no bit manipulation.
+//
+// Every guest word becomes exactly one op, so the pc map is arithmetic: the op
+// for `pc` is at index `(pc - base) / 4`. Words that do not decode, and the
+// floating point and system instructions, translate to `Opcode::Interpret`
+// which runs the word through the interpreter.
+//
+// Within a basic block common pairs are marked as superinstructions, which
+// `CPUState::run` executes in one step. The second instruction of a pair keeps
+// its own op, so a jump into the middle of a pair runs it on its own.
+
+use crate::bus::CODE_BLOCK_SHIFT;
+use crate::instruction::{BranchOp, ImmediateOp, LoadOp, RegisterOp, ShiftOp, StoreOp};
+use crate::{
+    chain_cycle, execute_auipc, execute_branch, execute_immediate, execute_instruction,
+    execute_jal, execute_jalr, execute_load, execute_lui, execute_register, execute_shift,
+    execute_store, Bus, CPUState, Instruction, Trap,
+};
+
+// Declares `Opcode` with a variant for each listed op, the conversions from
+// the op enums and `execute_op`, which runs `$body` with `$operation` bound to
+// the op as a constant.
+macro_rules! opcodes {
+    ($($op_type:ident [$($variant:ident),*] => |$cpu_state:ident, $op:ident, $operation:ident| $body:expr;)*) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        #[repr(u8)]
+        enum Opcode {
+            $($($variant,)*)*
+            Jal,
+            Jalr,
+            Lui,
+            Auipc,
+            Interpret,
+        }
+
+        $(impl From<$op_type> for Opcode {
+            fn from(operation: $op_type) -> Self {
+                match operation {
+                    $($op_type::$variant => Opcode::$variant,)*
+                }
+            }
+        })*
+
+        fn execute_op(cpu_state: &mut CPUState, op: &Op) -> Result<(), Trap> {
+            match op.opcode {
+                $($(Opcode::$variant => {
+                    let ($cpu_state, $op, $operation) = (&mut *cpu_state, op, $op_type::$variant);
+                    $body
+                })*)*
+                Opcode::Jal => execute_jal(cpu_state, op.rd, op.imm, op.word),
+                Opcode::Jalr => execute_jalr(cpu_state, op.rd, op.rs1, op.imm, op.word),
+                Opcode::Lui => execute_lui(cpu_state, op.rd, op.imm as u32),
+                Opcode::Auipc => execute_auipc(cpu_state, op.rd, op.imm as u32),
+                Opcode::Interpret => execute_instruction(cpu_state),
+            }
+        }
+    };
+}
+
+opcodes! {
+    RegisterOp [Add, Sub, Sll, Slt, Sltu, Xor, Srl, Sra, Or, And, Mul, Mulh, Mulhsu, Mulhu, Div, Divu, Rem, Remu]
+        => |cpu_state, op, operation| execute_register(cpu_state, operation, op.rd, op.rs1, op.rs2);
+    ImmediateOp [Addi, Slti, Sltiu, Xori, Ori, Andi]
+        => |cpu_state, op, operation| execute_immediate(cpu_state, operation, op.rd, op.rs1, op.imm);
+    ShiftOp [Slli, Srli, Srai]
+        => |cpu_state, op, operation| execute_shift(cpu_state, operation, op.rd, op.rs1, op.imm as u8);
+    LoadOp [Lb, Lh, Lw, Lbu, Lhu]
+        => |cpu_state, op, operation| execute_load(cpu_state, operation, op.rd, op.rs1, op.imm, op.word);
+    StoreOp [Sb, Sh, Sw]
+        => |cpu_state, op, operation| execute_store(cpu_state, operation, op.rs1, op.rs2, op.imm, op.word);
+    BranchOp [Beq, Bne, Blt, Bge, Bltu, Bgeu]
+        => |cpu_state, op, operation| execute_branch(cpu_state, operation, op.rs1, op.rs2, op.imm, op.word);
+}
+
+/// The idioms fused into superinstructions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Fusion {
+    /// `lui rd, hi` then `addi rd, rd, lo`, a 32 bit constant.
+    LuiAddi,
+    /// `auipc rd, hi` then `jalr rd2, lo(rd)`, a pc-relative call or jump.
+    AuipcJalr,
+    /// `slt`, `sltu`, `slti` or `sltiu` then `beq` or `bne` of the result
+    /// against zero.
+    CompareBranch,
+}
+
+impl Fusion {
+    fn of(first: &Op, second: &Op) -> Option<Fusion> {
+        let tests_first = |second: &Op| {
+            (second.rs1 == first.rd && second.rs2 == 0)
+                || (second.rs2 == first.rd && second.rs1 == 0)
+        };
+        if first.rd == 0 {
+            return None;
+        }
+        match (first.opcode, second.opcode) {
+            (Opcode::Lui, Opcode::Addi) if second.rd == first.rd && second.rs1 == first.rd => {
+                Some(Fusion::LuiAddi)
+            }
+            (Opcode::Auipc, Opcode::Jalr) if second.rs1 == first.rd => Some(Fusion::AuipcJalr),
+            (
+                Opcode::Slt | Opcode::Sltu | Opcode::Slti | Opcode::Sltiu,
+                Opcode::Beq | Opcode::Bne,
+            ) if tests_first(second) => Some(Fusion::CompareBranch),
+            _ => None,
+        }
+    }
+}
+
+/// How many times each kind of superinstruction executed both of its
+/// instructions in one step.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FusionCounts {
+    pub lui_addi: u64,
+    pub auipc_jalr: u64,
+    pub compare_branch: u64,
+}
+
+impl FusionCounts {
+    pub fn total(&self) -> u64 {
+        self.lui_addi + self.auipc_jalr + self.compare_branch
+    }
+
+    fn record(&mut self, fusion: Fusion) {
+        match fusion {
+            Fusion::LuiAddi => self.lui_addi += 1,
+            Fusion::AuipcJalr => self.auipc_jalr += 1,
+            Fusion::CompareBranch => self.compare_branch += 1,
+        }
+    }
+}
+
+/// One translated instruction, with its operands from
+/// `Instruction::operands`.
+#[derive(Debug, Clone, Copy)]
+struct Op {
+    opcode: Opcode,
+    /// Set on the first instruction of a superinstruction.
+    fusion: Option<Fusion>,
+    rd: u8,
+    rs1: u8,
+    rs2: u8,
+    imm: i32,
+    /// The guest word, reported in traps.
+    word: u32,
+}
+
+impl Op {
+    fn translate(word: Option<u32>) -> Op {
+        let interpret = Op {
+            opcode: Opcode::Interpret,
+            fusion: None,
+            rd: 0,
+            rs1: 0,
+            rs2: 0,
+            imm: 0,
+            word: word.unwrap_or(0),
+        };
+        let Some(instruction) = word.and_then(|word| Instruction::decode(word).ok()) else {
+            return interpret;
+        };
+        let opcode = match instruction {
+            Instruction::Register { op, .. } => op.into(),
+            Instruction::Immediate { op, .. } => op.into(),
+            Instruction::Shift { op, .. } => op.into(),
+            Instruction::Load { op, .. } => op.into(),
+            Instruction::Store { op, .. } => op.into(),
+            Instruction::Branch { op, .. } => op.into(),
+            Instruction::Jal { .. } => Opcode::Jal,
+            Instruction::Jalr { .. } => Opcode::Jalr,
+            Instruction::Lui { .. } => Opcode::Lui,
+            Instruction::Auipc { .. } => Opcode::Auipc,
+            _ => return interpret,
+        };
+        let (rd, rs1, rs2, imm) = instruction.operands();
+        Op {
+            opcode,
+            fusion: None,
+            rd,
+            rs1,
+            rs2,
+            imm,
+            ..interpret
+        }
+    }
+}
+
+/// The bytecode for a region of guest memory, executed by
+/// `Engine::Bytecode`.
+///
+/// Writes through the bus to the region retranslate the words they touch,
+/// so self-modifying code and program loaders keep working. Memory changed
+/// any other way, such as through `Bus::device_mut`, needs a new translation.
+#[derive(Clone)]
+pub struct Translation {
+    base: u32,
+    ops: Vec<Op>,
+    /// Whether each op starts a basic block, pairs are not fused across them.
+    leaders: Vec<bool>,
+    fusion_counts: FusionCounts,
+}
+
+impl Translation {
+    /// Translates the `len` bytes of guest memory starting at `base`, which
+    /// must be word aligned. Words the bus cannot fetch are translated to
+    /// raise the fault when executed.
+    pub fn new(bus: &mut Bus, base: u32, len: u32) -> Self {
+        assert!(
+            base.is_multiple_of(4),
+            "translation base {:#010x} is misaligned",
+            base
+        );
+        let ops = (0..len / 4)
+            .map(|i| Op::translate(bus.fetch(base.wrapping_add(4 * i), 4)))
+            .collect();
+        let mut translation = Translation {
+            base,
+            ops,
+            leaders: vec![],
+            fusion_counts: FusionCounts::default(),
+        };
+        translation.find_leaders();
+        translation.fuse(0..translation.ops.len());
+        let first_block = base >> CODE_BLOCK_SHIFT << CODE_BLOCK_SHIFT;
+        for address in (first_block as u64..translation.end()).step_by(1 << CODE_BLOCK_SHIFT) {
+            bus.watch_code(address as u32);
+        }
+        translation
+    }
+
+    fn end(&self) -> u64 {
+        self.base as u64 + 4 * self.ops.len() as u64
+    }
+
+    fn index(&self, pc: u32) -> Option<usize> {
+        let offset = pc.wrapping_sub(self.base);
+        let index = offset as usize / 4;
+        (offset.is_multiple_of(4) && index < self.ops.len()).then_some(index)
+    }
+
+    /// Marks the first instruction of every basic block: the start of the
+    /// translation, the targets of branches and jal and the instructions
+    /// after anything that can change control flow.
+    fn find_leaders(&mut self) {
+        let mut leaders = vec![false; self.ops.len()];
+        if let Some(first) = leaders.first_mut() {
+            *first = true;
+        }
+        for (index, op) in self.ops.iter().enumerate() {
+            let pc = self.base.wrapping_add(4 * index as u32);
+            let is_branch = matches!(
+                op.opcode,
+                Opcode::Beq
+                    | Opcode::Bne
+                    | Opcode::Blt
+                    | Opcode::Bge
+                    | Opcode::Bltu
+                    | Opcode::Bgeu
+                    | Opcode::Jal
+            );
+            if is_branch {
+                if let Some(target) = self.index(pc.wrapping_add(op.imm as u32)) {
+                    leaders[target] = true;
+                }
+            }
+            if is_branch || matches!(op.opcode, Opcode::Jalr | Opcode::Interpret) {
+                if let Some(next) = leaders.get_mut(index + 1) {
+                    *next = true;
+                }
+            }
+        }
+        self.leaders = leaders;
+    }
+
+    /// Marks the superinstructions starting at `indices`.
+    fn fuse(&mut self, indices: std::ops::Range<usize>) {
+        for index in indices {
+            let fusion = match self.ops.get(index + 1) {
+                Some(second) if !self.leaders[index + 1] => Fusion::of(&self.ops[index], second),
+                _ => None,
+            };
+            self.ops[index].fusion = fusion;
+        }
+    }
+
+    /// How often each superinstruction has executed.
+    pub fn fusion_counts(&self) -> FusionCounts {
+        self.fusion_counts
+    }
+
+    /// Number of translated instructions.
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    fn retranslate(&mut self, bus: &mut Bus, block: u32) {
+        let start = ((block as u64) << CODE_BLOCK_SHIFT).max(self.base as u64);
+        let end = ((block as u64 + 1) << CODE_BLOCK_SHIFT).min(self.end());
+        if start >= end {
+            return;
+        }
+        let first = (start - self.base as u64) as usize / 4;
+        let last = (end - self.base as u64) as usize / 4;
+        for index in first..last {
+            let pc = self.base.wrapping_add(4 * index as u32);
+            self.ops[index] = Op::translate(bus.fetch(pc, 4));
+        }
+        // the leaders are left as they were, a new jump into the middle of a
+        // pair still runs the second instruction on its own
+        self.fuse(first.saturating_sub(1)..last);
+        bus.watch_code(start as u32);
+    }
+
+    /// Executes the instruction at the pc, interpreting it if the pc is
+    /// outside the translated region. With `fuse` set both instructions of a
+    /// superinstruction are executed.
+    pub(crate) fn execute(&mut self, cpu_state: &mut CPUState, fuse: bool) -> Result<(), Trap> {
+        if let Some(blocks) = cpu_state.bus.take_written_code_blocks() {
+            for block in blocks {
+                self.retranslate(&mut cpu_state.bus, block);
+            }
+        }
+        let Some(index) = self.index(cpu_state.pc) else {
+            return execute_instruction(cpu_state);
+        };
+        let op = self.ops[index];
+        execute_op(cpu_state, &op)?;
+        match op.fusion {
+            Some(fusion) if fuse && chain_cycle(cpu_state) => {
+                self.fusion_counts.record(fusion);
+                execute_op(cpu_state, &self.ops[index + 1])
+            }
+            _ => Ok(()),
+        }
+    }
+}
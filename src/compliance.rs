@@ -0,0 +1,96 @@
+// Running the riscv-tests and riscv-arch-test suites
+//
+// Both suites are statically linked ELF programs that exit through HTIF.
+// riscv-tests exit with 0 when every case passed or with the number of the
+// first case that failed, the arch tests halt the same way and leave their
+// results between `begin_signature` and `end_signature` to be compared with
+// a reference model.
+
+use crate::{CPUState, Elf, ElfError, Htif, MemoryConfig, RunOutcome};
+
+/// RAM mapped past the end of the last segment, for stacks.
+const HEADROOM: u32 = 0x10000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestOutcome {
+    Passed,
+    /// The number of the first test case that failed.
+    Failed(u32),
+    /// The program stopped without exiting through HTIF.
+    Stopped(RunOutcome),
+    /// The cycle budget ran out before the program exited.
+    Timeout,
+}
+
+/// A hart with RAM covering every segment of `elf` and an `Htif` at its
+/// `tohost`, loaded and ready to run. Traps enter the program's own handlers,
+/// as the suites expect.
+pub fn load(elf: &Elf) -> Result<CPUState, ElfError> {
+    let htif = Htif::for_elf(elf).ok_or(ElfError::MissingSymbol("tohost"))?;
+    let start = elf
+        .segments
+        .iter()
+        .map(|segment| segment.address)
+        .min()
+        .unwrap_or(elf.entry)
+        & !0xFFF;
+    let end = elf
+        .segments
+        .iter()
+        .map(|segment| segment.address as u64 + segment.memory_size as u64)
+        .max()
+        .unwrap_or(start as u64);
+    let size = end - start as u64 + HEADROOM as u64;
+    let mut cpu_state = CPUState::with_memory(MemoryConfig {
+        base: start,
+        size: size as usize,
+    })
+    .map_err(|_| ElfError::SegmentOutOfMemory {
+        address: start,
+        size: (end - start as u64) as u32,
+    })?;
+    cpu_state.vector_traps = true;
+    elf.load(&mut cpu_state)?;
+    cpu_state.attach_htif(htif);
+    Ok(cpu_state)
+}
+
+/// Runs a test program until it exits, for at most `max_cycles` cycles.
+pub fn run_test(cpu_state: &mut CPUState, max_cycles: u64) -> TestOutcome {
+    match cpu_state.run(max_cycles).outcome {
+        RunOutcome::Exited(0) => TestOutcome::Passed,
+        RunOutcome::Exited(case) => TestOutcome::Failed(case as u32),
+        RunOutcome::CycleLimit => TestOutcome::Timeout,
+        outcome => TestOutcome::Stopped(outcome),
+    }
+}
+
+/// The words from `begin_signature` up to `end_signature`.
+pub fn signature(cpu_state: &mut CPUState, elf: &Elf) -> Result<Vec<u32>, ElfError> {
+    let begin = elf
+        .symbol("begin_signature")
+        .ok_or(ElfError::MissingSymbol("begin_signature"))?;
+    let end = elf
+        .symbol("end_signature")
+        .ok_or(ElfError::MissingSymbol("end_signature"))?;
+    let bytes = cpu_state
+        .read_bytes(begin, end.saturating_sub(begin) as usize)
+        .ok_or(ElfError::SegmentOutOfMemory {
+            address: begin,
+            size: end.saturating_sub(begin),
+        })?;
+    Ok(bytes
+        .chunks(4)
+        .map(|word| {
+            let mut bytes = [0; 4];
+            bytes[..word.len()].copy_from_slice(word);
+            u32::from_le_bytes(bytes)
+        })
+        .collect())
+}
+
+/// A signature in the format of the reference signatures, one word per line
+/// as eight lowercase hex digits.
+pub fn format_signature(words: &[u32]) -> String {
+    words.iter().map(|word| format!("{:08x}\n", word)).collect()
+}
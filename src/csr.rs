@@ -0,0 +1,240 @@
+// Machine-mode control and status registers (Zicsr)
+
+pub const FFLAGS: u16 = 0x001;
+pub const FRM: u16 = 0x002;
+pub const FCSR: u16 = 0x003;
+
+pub const CYCLE: u16 = 0xC00;
+pub const TIME: u16 = 0xC01;
+pub const INSTRET: u16 = 0xC02;
+pub const CYCLEH: u16 = 0xC80;
+pub const TIMEH: u16 = 0xC81;
+pub const INSTRETH: u16 = 0xC82;
+
+pub const MSTATUS: u16 = 0x300;
+pub const MISA: u16 = 0x301;
+pub const MIE: u16 = 0x304;
+pub const MTVEC: u16 = 0x305;
+pub const MSCRATCH: u16 = 0x340;
+pub const MEPC: u16 = 0x341;
+pub const MCAUSE: u16 = 0x342;
+pub const MTVAL: u16 = 0x343;
+pub const MIP: u16 = 0x344;
+
+pub const MCYCLE: u16 = 0xB00;
+pub const MINSTRET: u16 = 0xB02;
+pub const MCYCLEH: u16 = 0xB80;
+pub const MINSTRETH: u16 = 0xB82;
+
+pub const MVENDORID: u16 = 0xF11;
+pub const MARCHID: u16 = 0xF12;
+pub const MIMPID: u16 = 0xF13;
+pub const MHARTID: u16 = 0xF14;
+
+pub const MSTATUS_MIE: u32 = 1 << 3;
+pub const MSTATUS_MPIE: u32 = 1 << 7;
+pub const MSTATUS_MPP: u32 = 0b11 << 11;
+pub const MSTATUS_FS: u32 = 0b11 << 13;
+
+pub const MIP_MSIP: u32 = 1 << 3;
+pub const MIP_MTIP: u32 = 1 << 7;
+pub const MIP_MEIP: u32 = 1 << 11;
+
+pub const MCAUSE_INTERRUPT: u32 = 1 << 31;
+
+// RV32 with the I, M and F extensions
+const MISA_VALUE: u32 = (1 << 30) | (1 << 8) | (1 << 12) | (1 << 5);
+
+/// The assembler name of an implemented CSR.
+pub fn csr_name(csr: u16) -> Option<&'static str> {
+    let name = match csr {
+        FFLAGS => "fflags",
+        FRM => "frm",
+        FCSR => "fcsr",
+        CYCLE => "cycle",
+        TIME => "time",
+        INSTRET => "instret",
+        CYCLEH => "cycleh",
+        TIMEH => "timeh",
+        INSTRETH => "instreth",
+        MSTATUS => "mstatus",
+        MISA => "misa",
+        MIE => "mie",
+        MTVEC => "mtvec",
+        MSCRATCH => "mscratch",
+        MEPC => "mepc",
+        MCAUSE => "mcause",
+        MTVAL => "mtval",
+        MIP => "mip",
+        MCYCLE => "mcycle",
+        MINSTRET => "minstret",
+        MCYCLEH => "mcycleh",
+        MINSTRETH => "minstreth",
+        MVENDORID => "mvendorid",
+        MARCHID => "marchid",
+        MIMPID => "mimpid",
+        MHARTID => "mhartid",
+        _ => return None,
+    };
+    Some(name)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CSRFile {
+    pub mstatus: u32,
+    pub misa: u32,
+    pub mie: u32,
+    pub mip: u32,
+    pub mtvec: u32,
+    pub mscratch: u32,
+    pub mepc: u32,
+    pub mcause: u32,
+    pub mtval: u32,
+    pub mhartid: u32,
+    pub fcsr: u32,
+    pub cycle: u64,
+    pub time: u64,
+    pub instret: u64,
+}
+
+impl Default for CSRFile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CSRFile {
+    pub fn new() -> Self {
+        CSRFile {
+            // Only machine mode is implemented so MPP is hardwired to M
+            mstatus: MSTATUS_MPP,
+            misa: MISA_VALUE,
+            mie: 0,
+            mip: 0,
+            mtvec: 0,
+            mscratch: 0,
+            mepc: 0,
+            mcause: 0,
+            mtval: 0,
+            mhartid: 0,
+            fcsr: 0,
+            cycle: 0,
+            time: 0,
+            instret: 0,
+        }
+    }
+
+    /// Returns `None` if the CSR does not exist.
+    pub fn read(&self, csr: u16) -> Option<u32> {
+        let value = match csr {
+            FFLAGS => self.fcsr & 0x1F,
+            FRM => (self.fcsr >> 5) & 0b111,
+            FCSR => self.fcsr,
+            CYCLE | MCYCLE => self.cycle as u32,
+            CYCLEH | MCYCLEH => (self.cycle >> 32) as u32,
+            TIME => self.time as u32,
+            TIMEH => (self.time >> 32) as u32,
+            INSTRET | MINSTRET => self.instret as u32,
+            INSTRETH | MINSTRETH => (self.instret >> 32) as u32,
+            MSTATUS => self.mstatus,
+            MISA => self.misa,
+            MIE => self.mie,
+            MIP => self.mip,
+            MTVEC => self.mtvec,
+            MSCRATCH => self.mscratch,
+            MEPC => self.mepc,
+            MCAUSE => self.mcause,
+            MTVAL => self.mtval,
+            MVENDORID | MARCHID | MIMPID => 0,
+            MHARTID => self.mhartid,
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    /// Returns `None` if the CSR does not exist or is read-only.
+    pub fn write(&mut self, csr: u16, value: u32) -> Option<()> {
+        // Bits 11:10 of the address mark a CSR as read-only
+        if (csr >> 10) & 0b11 == 0b11 {
+            return None;
+        }
+        match csr {
+            FFLAGS => self.fcsr = (self.fcsr & !0x1F) | (value & 0x1F),
+            FRM => self.fcsr = (self.fcsr & 0x1F) | ((value & 0b111) << 5),
+            FCSR => self.fcsr = value & 0xFF,
+            MCYCLE => self.cycle = (self.cycle & !0xFFFF_FFFF) | value as u64,
+            MCYCLEH => self.cycle = (self.cycle & 0xFFFF_FFFF) | ((value as u64) << 32),
+            MINSTRET => self.instret = (self.instret & !0xFFFF_FFFF) | value as u64,
+            MINSTRETH => self.instret = (self.instret & 0xFFFF_FFFF) | ((value as u64) << 32),
+            MSTATUS => {
+                let writable = MSTATUS_MIE | MSTATUS_MPIE | MSTATUS_FS;
+                self.mstatus = (self.mstatus & !writable) | (value & writable);
+            }
+            MISA => {} // WARL, the extensions can't be disabled
+            MIE => self.mie = value & (MIP_MSIP | MIP_MTIP | MIP_MEIP),
+            MIP => {} // pending bits are driven by the interrupt sources
+            MTVEC => {
+                // Only direct (0) and vectored (1) modes are supported
+                self.mtvec = (value & !0b11) | (value & 0b11 == 1) as u32;
+            }
+            MSCRATCH => self.mscratch = value,
+            MEPC => self.mepc = value & !0b11,
+            MCAUSE => self.mcause = value,
+            MTVAL => self.mtval = value,
+            _ => return None,
+        }
+        Some(())
+    }
+
+    /// The highest priority interrupt that is both pending and enabled, as an
+    /// mcause exception code.
+    pub fn pending_interrupt(&self) -> Option<u32> {
+        if self.mstatus & MSTATUS_MIE == 0 {
+            return None;
+        }
+        let pending = self.mip & self.mie;
+        // External, then software, then timer interrupts
+        [11, 3, 7]
+            .into_iter()
+            .find(|code| pending & (1 << code) != 0)
+    }
+
+    /// Records a trap in mepc, mcause and mtval, disables interrupts and
+    /// returns the address of the handler to jump to.
+    pub(crate) fn trap_entry(&mut self, pc: u32, cause: u32, value: u32) -> u32 {
+        self.mepc = pc;
+        self.mcause = cause;
+        self.mtval = value;
+        let interrupts_enabled = self.mstatus & MSTATUS_MIE != 0;
+        self.mstatus &= !(MSTATUS_MIE | MSTATUS_MPIE);
+        if interrupts_enabled {
+            self.mstatus |= MSTATUS_MPIE;
+        }
+        let base = self.mtvec & !0b11;
+        if (self.mtvec & 0b11 == 1) && (cause & MCAUSE_INTERRUPT != 0) {
+            base.wrapping_add(4 * (cause & !MCAUSE_INTERRUPT))
+        } else {
+            base
+        }
+    }
+
+    /// Restores the interrupt enable saved on trap entry and returns the
+    /// address to resume at.
+    pub(crate) fn trap_return(&mut self) -> u32 {
+        let interrupts_enabled = self.mstatus & MSTATUS_MPIE != 0;
+        self.mstatus &= !MSTATUS_MIE;
+        if interrupts_enabled {
+            self.mstatus |= MSTATUS_MIE;
+        }
+        self.mstatus |= MSTATUS_MPIE;
+        self.mepc
+    }
+
+    pub(crate) fn tick(&mut self, retired: bool) {
+        self.cycle = self.cycle.wrapping_add(1);
+        self.time = self.time.wrapping_add(1);
+        if retired {
+            self.instret = self.instret.wrapping_add(1);
+        }
+    }
+}
@@ -0,0 +1,194 @@
+// Pre-decoded instruction cache
+//
+// Each instruction is decoded once into an `Entry` holding its operands and a
+// handler specialised for its op, so executing a cached instruction is an
+// indirect call rather than a fetch, a decode and two matches. Entries are
+// dropped when the bus reports a write to the block they were decoded from.
+
+use crate::bus::CODE_BLOCK_SHIFT;
+use crate::instruction::{BranchOp, ImmediateOp, LoadOp, RegisterOp, ShiftOp, StoreOp};
+use crate::{
+    execute, execute_auipc, execute_branch, execute_immediate, execute_jal, execute_jalr,
+    execute_load, execute_lui, execute_register, execute_shift, execute_store, fetch_instruction,
+    CPUState, Instruction, Trap,
+};
+
+/// Number of entries, a power of two.
+const ENTRIES: usize = 1 << 14;
+
+type Handler = fn(&mut CPUState, &Entry) -> Result<(), Trap>;
+
+/// A decoded instruction, with its operands from `Instruction::operands`.
+#[derive(Clone, Copy)]
+struct Entry {
+    pc: u32,
+    handler: Handler,
+    instruction: Instruction,
+    word: u32,
+    rd: u8,
+    rs1: u8,
+    rs2: u8,
+    imm: i32,
+}
+
+// Expands to one handler per variant of `$op_type`, each calling `$body` with
+// `$op` bound to a constant so the op's match folds away.
+macro_rules! specialize {
+    ($value:expr, $op_type:ident, [$($variant:ident),*], |$cpu_state:ident, $entry:ident, $op:ident| $body:expr) => {
+        match $value {
+            $($op_type::$variant => |$cpu_state: &mut CPUState, $entry: &Entry| {
+                let $op = $op_type::$variant;
+                $body
+            },)*
+        }
+    };
+}
+
+fn handler(instruction: &Instruction) -> Handler {
+    match *instruction {
+        Instruction::Register { op, .. } => specialize!(
+            op,
+            RegisterOp,
+            [
+                Add, Sub, Sll, Slt, Sltu, Xor, Srl, Sra, Or, And, Mul, Mulh, Mulhsu, Mulhu, Div,
+                Divu, Rem, Remu
+            ],
+            |cpu_state, entry, op| execute_register(cpu_state, op, entry.rd, entry.rs1, entry.rs2)
+        ),
+        Instruction::Immediate { op, .. } => specialize!(
+            op,
+            ImmediateOp,
+            [Addi, Slti, Sltiu, Xori, Ori, Andi],
+            |cpu_state, entry, op| execute_immediate(cpu_state, op, entry.rd, entry.rs1, entry.imm)
+        ),
+        Instruction::Shift { op, .. } => {
+            specialize!(op, ShiftOp, [Slli, Srli, Srai], |cpu_state, entry, op| {
+                execute_shift(cpu_state, op, entry.rd, entry.rs1, entry.imm as u8)
+            })
+        }
+        Instruction::Load { op, .. } => specialize!(
+            op,
+            LoadOp,
+            [Lb, Lh, Lw, Lbu, Lhu],
+            |cpu_state, entry, op| execute_load(
+                cpu_state, op, entry.rd, entry.rs1, entry.imm, entry.word
+            )
+        ),
+        Instruction::Store { op, .. } => {
+            specialize!(op, StoreOp, [Sb, Sh, Sw], |cpu_state, entry, op| {
+                execute_store(cpu_state, op, entry.rs1, entry.rs2, entry.imm, entry.word)
+            })
+        }
+        Instruction::Branch { op, .. } => specialize!(
+            op,
+            BranchOp,
+            [Beq, Bne, Blt, Bge, Bltu, Bgeu],
+            |cpu_state, entry, op| execute_branch(
+                cpu_state, op, entry.rs1, entry.rs2, entry.imm, entry.word
+            )
+        ),
+        Instruction::Jal { .. } => {
+            |cpu_state, entry| execute_jal(cpu_state, entry.rd, entry.imm, entry.word)
+        }
+        Instruction::Jalr { .. } => {
+            |cpu_state, entry| execute_jalr(cpu_state, entry.rd, entry.rs1, entry.imm, entry.word)
+        }
+        Instruction::Lui { .. } => {
+            |cpu_state, entry| execute_lui(cpu_state, entry.rd, entry.imm as u32)
+        }
+        Instruction::Auipc { .. } => {
+            |cpu_state, entry| execute_auipc(cpu_state, entry.rd, entry.imm as u32)
+        }
+        // system and floating point instructions are rare enough to go
+        // through the interpreter's match
+        _ => |cpu_state, entry| execute(cpu_state, entry.instruction, entry.word),
+    }
+}
+
+impl Entry {
+    fn new(pc: u32, instruction: Instruction, word: u32) -> Self {
+        let (rd, rs1, rs2, imm) = instruction.operands();
+        Entry {
+            pc,
+            handler: handler(&instruction),
+            instruction,
+            word,
+            rd,
+            rs1,
+            rs2,
+            imm,
+        }
+    }
+}
+
+/// A direct mapped cache of decoded instructions indexed by pc, executed by
+/// `Engine::DecodeCache`.
+///
+/// Writes through the bus, including `Bus::load`, invalidate the cache.
+/// Call `clear` after changing memory any other way, such as through
+/// `Bus::device_mut`.
+#[derive(Clone)]
+pub struct DecodeCache {
+    entries: Vec<Option<Entry>>,
+}
+
+impl Default for DecodeCache {
+    fn default() -> Self {
+        DecodeCache::new()
+    }
+}
+
+impl DecodeCache {
+    pub fn new() -> Self {
+        DecodeCache {
+            entries: vec![None; ENTRIES],
+        }
+    }
+
+    /// Drops every decoded instruction.
+    pub fn clear(&mut self) {
+        self.entries.fill(None);
+    }
+
+    fn slot(pc: u32) -> usize {
+        (pc >> 2) as usize & (ENTRIES - 1)
+    }
+
+    fn invalidate(&mut self, block: u32) {
+        let start = block << CODE_BLOCK_SHIFT;
+        let in_block = |address: u32| address >> CODE_BLOCK_SHIFT == block;
+        // starting a word early catches a misaligned instruction that runs
+        // into the block
+        for offset in (0..=1 << CODE_BLOCK_SHIFT).step_by(4) {
+            let pc = start.wrapping_add(offset).wrapping_sub(4);
+            let slot = &mut self.entries[DecodeCache::slot(pc)];
+            if slot.is_some_and(|entry| in_block(entry.pc) || in_block(entry.pc.wrapping_add(3))) {
+                *slot = None;
+            }
+        }
+    }
+
+    /// Executes the instruction at the pc, decoding it on a miss.
+    pub(crate) fn execute(&mut self, cpu_state: &mut CPUState) -> Result<(), Trap> {
+        if let Some(blocks) = cpu_state.bus.take_written_code_blocks() {
+            for block in blocks {
+                self.invalidate(block);
+            }
+        }
+        let pc = cpu_state.pc;
+        let slot = &mut self.entries[DecodeCache::slot(pc)];
+        let entry = match slot {
+            Some(entry) if entry.pc == pc => *entry,
+            _ => {
+                let (instruction, word) = fetch_instruction(cpu_state)?;
+                // a misaligned pc can fetch across two blocks
+                cpu_state.bus.watch_code(pc);
+                cpu_state.bus.watch_code(pc.wrapping_add(3));
+                let entry = Entry::new(pc, instruction, word);
+                *slot = Some(entry);
+                entry
+            }
+        };
+        (entry.handler)(cpu_state, &entry)
+    }
+}
@@ -0,0 +1,163 @@
+// Peripherals that can be mapped onto the bus
+
+use std::collections::VecDeque;
+
+use crate::bus::Device;
+use crate::csr::{MIP_MSIP, MIP_MTIP};
+
+const UART_RBR_THR: u32 = 0;
+const UART_LSR: u32 = 5;
+const UART_LSR_DATA_READY: u32 = 1 << 0;
+const UART_LSR_THR_EMPTY: u32 = 1 << 5;
+const UART_LSR_TRANSMITTER_EMPTY: u32 = 1 << 6;
+
+/// A minimal 16550 compatible UART, transmitted bytes are collected and
+/// received bytes are queued by the embedder.
+#[derive(Debug, Clone, Default)]
+pub struct Uart {
+    input: VecDeque<u8>,
+    output: Vec<u8>,
+}
+
+impl Uart {
+    pub fn new() -> Self {
+        Uart {
+            input: VecDeque::new(),
+            output: vec![],
+        }
+    }
+
+    /// Queues bytes to be read by the guest.
+    pub fn push_input(&mut self, bytes: &[u8]) {
+        self.input.extend(bytes);
+    }
+
+    /// Bytes written by the guest so far.
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.output)
+    }
+}
+
+impl Device for Uart {
+    fn size(&self) -> u32 {
+        8
+    }
+
+    fn read(&mut self, offset: u32, _size: usize) -> Option<u32> {
+        let value = match offset {
+            UART_RBR_THR => self.input.pop_front().unwrap_or(0) as u32,
+            UART_LSR => {
+                let data_ready = if self.input.is_empty() {
+                    0
+                } else {
+                    UART_LSR_DATA_READY
+                };
+                UART_LSR_THR_EMPTY | UART_LSR_TRANSMITTER_EMPTY | data_ready
+            }
+            _ => 0,
+        };
+        Some(value)
+    }
+
+    fn write(&mut self, offset: u32, _size: usize, value: u32) -> Option<()> {
+        if offset == UART_RBR_THR {
+            self.output.push(value as u8);
+        }
+        Some(())
+    }
+}
+
+const CLINT_MSIP: u32 = 0x0000;
+const CLINT_MTIMECMP: u32 = 0x4000;
+const CLINT_MTIMECMPH: u32 = 0x4004;
+const CLINT_MTIME: u32 = 0xBFF8;
+const CLINT_MTIMEH: u32 = 0xBFFC;
+
+/// A SiFive style core local interruptor providing the machine timer and
+/// software interrupts. mtime advances by one every cycle.
+#[derive(Debug, Clone)]
+pub struct Clint {
+    pub msip: u32,
+    pub mtimecmp: u64,
+    pub mtime: u64,
+}
+
+impl Default for Clint {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clint {
+    pub fn new() -> Self {
+        Clint {
+            msip: 0,
+            mtimecmp: u64::MAX,
+            mtime: 0,
+        }
+    }
+}
+
+// The high word of each 64 bit register sits at offset + 4
+fn read_u64_half(value: u64, offset: u32) -> u32 {
+    if offset & 0b100 == 0 {
+        value as u32
+    } else {
+        (value >> 32) as u32
+    }
+}
+
+fn write_u64_half(target: &mut u64, offset: u32, value: u32) {
+    if offset & 0b100 == 0 {
+        *target = (*target & !0xFFFF_FFFF) | value as u64;
+    } else {
+        *target = (*target & 0xFFFF_FFFF) | ((value as u64) << 32);
+    }
+}
+
+impl Device for Clint {
+    fn size(&self) -> u32 {
+        0x10000
+    }
+
+    fn read(&mut self, offset: u32, size: usize) -> Option<u32> {
+        if size != 4 {
+            return None;
+        }
+        match offset {
+            CLINT_MSIP => Some(self.msip),
+            CLINT_MTIMECMP | CLINT_MTIMECMPH => Some(read_u64_half(self.mtimecmp, offset)),
+            CLINT_MTIME | CLINT_MTIMEH => Some(read_u64_half(self.mtime, offset)),
+            _ => Some(0),
+        }
+    }
+
+    fn write(&mut self, offset: u32, size: usize, value: u32) -> Option<()> {
+        if size != 4 {
+            return None;
+        }
+        match offset {
+            CLINT_MSIP => self.msip = value & 1,
+            CLINT_MTIMECMP | CLINT_MTIMECMPH => write_u64_half(&mut self.mtimecmp, offset, value),
+            CLINT_MTIME | CLINT_MTIMEH => write_u64_half(&mut self.mtime, offset, value),
+            _ => {}
+        }
+        Some(())
+    }
+
+    fn tick(&mut self) -> u32 {
+        self.mtime = self.mtime.wrapping_add(1);
+        let mut bits = 0;
+        if self.mtime >= self.mtimecmp {
+            bits |= MIP_MTIP;
+        }
+        if self.msip & 1 != 0 {
+            bits |= MIP_MSIP;
+        }
+        bits
+    }
+}
@@ -0,0 +1,267 @@
+// Disassembler for RV32IMF machine code
+//
+// Words are decoded with `Instruction::decode`, the same decoder the
+// interpreter uses, and printed in the GNU no-aliases syntax with ABI
+// register names. Branch and jump targets inside the disassembled range are
+// given labels.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+use crate::abi::{FLOAT_REGISTER_NAMES, REGISTER_NAMES};
+use crate::csr::csr_name;
+use crate::instruction::{Instruction, DYNAMIC_ROUNDING};
+
+/// One disassembled instruction, or a `.word` / `.byte` directive for bytes
+/// that do not decode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedLine {
+    pub address: u32,
+    pub word: u32,
+    /// Set when a branch or jump in the same listing targets this line.
+    pub label: Option<String>,
+    pub mnemonic: String,
+    pub operands: String,
+    /// The absolute target of a branch or jal.
+    pub target: Option<u32>,
+}
+
+impl fmt::Display for DecodedLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(label) = &self.label {
+            writeln!(f, "{}:", label)?;
+        }
+        if self.operands.is_empty() {
+            write!(f, "{}", self.mnemonic)
+        } else {
+            write!(f, "{} {}", self.mnemonic, self.operands)
+        }
+    }
+}
+
+fn register(register: u8) -> String {
+    REGISTER_NAMES[register as usize].to_string()
+}
+
+fn float_register(register: u8) -> String {
+    FLOAT_REGISTER_NAMES[register as usize].to_string()
+}
+
+// Picks the register file for the operands of floating point instructions
+fn either_register(register: u8, integer: bool) -> String {
+    if integer {
+        self::register(register)
+    } else {
+        float_register(register)
+    }
+}
+
+fn csr(csr: u16) -> String {
+    match csr_name(csr) {
+        Some(name) => name.to_string(),
+        None => format!("{:#x}", csr),
+    }
+}
+
+fn fence_set(set: u8) -> String {
+    if set == 0 {
+        return "0".to_string();
+    }
+    "iorw"
+        .chars()
+        .enumerate()
+        .filter(|(i, _)| set & (0b1000 >> i) != 0)
+        .map(|(_, c)| c)
+        .collect()
+}
+
+fn address_operand(offset: i32, base: u8) -> String {
+    format!("{}({})", offset, register(base))
+}
+
+/// The operands of `instruction` other than a branch target, followed by the
+/// absolute target of a branch or jal at `address`.
+fn operands(instruction: &Instruction, address: u32) -> (Vec<String>, Option<u32>) {
+    let target = |offset: i32| Some(address.wrapping_add(offset as u32));
+    let (mut operands, rm) = match *instruction {
+        Instruction::Register { rd, rs1, rs2, .. } => {
+            (vec![register(rd), register(rs1), register(rs2)], None)
+        }
+        Instruction::Immediate { rd, rs1, imm, .. } => {
+            (vec![register(rd), register(rs1), imm.to_string()], None)
+        }
+        Instruction::Shift { rd, rs1, shamt, .. } => {
+            (vec![register(rd), register(rs1), shamt.to_string()], None)
+        }
+        Instruction::Load {
+            rd, rs1, offset, ..
+        } => (vec![register(rd), address_operand(offset, rs1)], None),
+        Instruction::Store {
+            rs1, rs2, offset, ..
+        } => (vec![register(rs2), address_operand(offset, rs1)], None),
+        Instruction::Branch {
+            rs1, rs2, offset, ..
+        } => return (vec![register(rs1), register(rs2)], target(offset)),
+        Instruction::Lui { rd, imm } | Instruction::Auipc { rd, imm } => {
+            (vec![register(rd), format!("{:#x}", imm)], None)
+        }
+        Instruction::Jal { rd, offset } => return (vec![register(rd)], target(offset)),
+        Instruction::Jalr { rd, rs1, offset } => {
+            (vec![register(rd), address_operand(offset, rs1)], None)
+        }
+        Instruction::Fence { pred, succ } => (vec![fence_set(pred), fence_set(succ)], None),
+        Instruction::Ecall | Instruction::Ebreak | Instruction::Mret | Instruction::Wfi => {
+            (vec![], None)
+        }
+        Instruction::Csr {
+            op,
+            rd,
+            source,
+            csr,
+        } => {
+            let source = if op.is_immediate() {
+                source.to_string()
+            } else {
+                register(source)
+            };
+            (vec![register(rd), self::csr(csr), source], None)
+        }
+        Instruction::Flw { rd, rs1, offset } => {
+            (vec![float_register(rd), address_operand(offset, rs1)], None)
+        }
+        Instruction::Fsw { rs1, rs2, offset } => (
+            vec![float_register(rs2), address_operand(offset, rs1)],
+            None,
+        ),
+        Instruction::FloatArithmetic {
+            rd, rs1, rs2, rm, ..
+        } => (
+            vec![float_register(rd), float_register(rs1), float_register(rs2)],
+            Some(rm),
+        ),
+        Instruction::FloatBinary { op, rd, rs1, rs2 } => (
+            vec![
+                either_register(rd, op.integer_destination()),
+                float_register(rs1),
+                float_register(rs2),
+            ],
+            None,
+        ),
+        Instruction::FloatUnary { op, rd, rs1, rm } => (
+            vec![
+                either_register(rd, op.integer_destination()),
+                either_register(rs1, op.integer_source()),
+            ],
+            Some(rm),
+        ),
+        Instruction::FloatBits { op, rd, rs1 } => (
+            vec![
+                either_register(rd, op.integer_destination()),
+                either_register(rs1, op.integer_source()),
+            ],
+            None,
+        ),
+        Instruction::FloatFused {
+            rd,
+            rs1,
+            rs2,
+            rs3,
+            rm,
+            ..
+        } => (
+            vec![
+                float_register(rd),
+                float_register(rs1),
+                float_register(rs2),
+                float_register(rs3),
+            ],
+            Some(rm),
+        ),
+    };
+    // dynamic rounding is the default and is not printed
+    if let Some(rm) = rm.filter(|&rm| rm != DYNAMIC_ROUNDING) {
+        operands.push(["rne", "rtz", "rdn", "rup", "rmm"][rm as usize].to_string());
+    }
+    (operands, None)
+}
+
+fn decode(word: u32, address: u32) -> Option<(&'static str, Vec<String>, Option<u32>)> {
+    let instruction = Instruction::decode(word).ok()?;
+    let (operands, target) = operands(&instruction, address);
+    Some((instruction.mnemonic(), operands, target))
+}
+
+/// Disassembles `bytes` as if they were loaded at address 0.
+pub fn disassemble(bytes: &[u8]) -> Vec<DecodedLine> {
+    disassemble_at(bytes, 0)
+}
+
+/// Disassembles `bytes` loaded at `address`. Words that do not decode become
+/// `.word` directives and trailing bytes that do not fill a word `.byte`.
+pub fn disassemble_at(bytes: &[u8], address: u32) -> Vec<DecodedLine> {
+    let chunks = bytes.chunks_exact(4);
+    let remainder = chunks.remainder();
+    let decoded: Vec<_> = chunks
+        .enumerate()
+        .map(|(i, chunk)| {
+            let word = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+            let line_address = address.wrapping_add(4 * i as u32);
+            (line_address, word, decode(word, line_address))
+        })
+        .collect();
+
+    // Targets that land on a decoded line are labelled in address order
+    let end = address.wrapping_add(4 * decoded.len() as u32);
+    let targets: BTreeSet<u32> = decoded
+        .iter()
+        .filter_map(|(_, _, instruction)| instruction.as_ref()?.2)
+        .filter(|&target| target >= address && target < end && (target - address).is_multiple_of(4))
+        .collect();
+    let labels: HashMap<u32, String> = targets
+        .iter()
+        .enumerate()
+        .map(|(i, &target)| (target, format!("L{}", i)))
+        .collect();
+
+    let mut lines: Vec<DecodedLine> = decoded
+        .into_iter()
+        .map(|(line_address, word, instruction)| {
+            let (mnemonic, mut operands, target) = match instruction {
+                Some(instruction) => instruction,
+                None => (".word", vec![format!("{:#010x}", word)], None),
+            };
+            if let Some(target) = target {
+                operands.push(match labels.get(&target) {
+                    Some(label) => label.clone(),
+                    None => format!("{:#x}", target),
+                });
+            }
+            DecodedLine {
+                address: line_address,
+                word,
+                label: labels.get(&line_address).cloned(),
+                mnemonic: mnemonic.to_string(),
+                operands: operands.join(","),
+                target,
+            }
+        })
+        .collect();
+
+    if !remainder.is_empty() {
+        let mut word = [0; 4];
+        word[..remainder.len()].copy_from_slice(remainder);
+        lines.push(DecodedLine {
+            address: end,
+            word: u32::from_le_bytes(word),
+            label: None,
+            mnemonic: ".byte".to_string(),
+            operands: remainder
+                .iter()
+                .map(|byte| format!("{:#04x}", byte))
+                .collect::<Vec<_>>()
+                .join(","),
+            target: None,
+        });
+    }
+    lines
+}
@@ -0,0 +1,215 @@
+// ELF32 executable loader
+//
+// Only what is needed to run statically linked RISC-V programs is parsed:
+// the file header, the program headers for PT_LOAD segments and the symbol
+// table.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use crate::CPUState;
+
+const ELF_MAGIC: [u8; 4] = [0x7F, b'E', b'L', b'F'];
+const ELFCLASS32: u8 = 1;
+const ELFDATA2LSB: u8 = 1;
+const ET_EXEC: u16 = 2;
+const EM_RISCV: u16 = 243;
+
+const PT_LOAD: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+
+const EHDR_SIZE: usize = 52;
+const PHDR_SIZE: usize = 32;
+const SHDR_SIZE: usize = 40;
+const SYM_SIZE: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ElfError {
+    /// The file does not start with the ELF magic number.
+    NotElf,
+    /// The file is valid ELF but not a 32 bit little-endian RISC-V executable.
+    Unsupported(&'static str),
+    /// A header or table points past the end of the file.
+    Truncated,
+    /// A segment does not fit in the memory mapped on the bus.
+    SegmentOutOfMemory { address: u32, size: u32 },
+    /// The program lacks a symbol it must define.
+    MissingSymbol(&'static str),
+}
+
+impl fmt::Display for ElfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ElfError::NotElf => write!(f, "not an ELF file"),
+            ElfError::Unsupported(reason) => write!(f, "unsupported ELF file: {}", reason),
+            ElfError::Truncated => write!(f, "truncated ELF file"),
+            ElfError::SegmentOutOfMemory { address, size } => write!(
+                f,
+                "segment at {:#010x} ({:#x} bytes) does not fit in guest memory",
+                address, size
+            ),
+            ElfError::MissingSymbol(name) => write!(f, "no `{}` symbol", name),
+        }
+    }
+}
+
+impl Error for ElfError {}
+
+/// A PT_LOAD segment. `data` holds the bytes present in the file, the
+/// remaining `memory_size - data.len()` bytes are zero-filled (.bss).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub address: u32,
+    pub memory_size: u32,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Elf {
+    pub entry: u32,
+    pub segments: Vec<Segment>,
+    symbols: HashMap<String, u32>,
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Result<u16, ElfError> {
+    let field = bytes.get(offset..offset + 2).ok_or(ElfError::Truncated)?;
+    Ok(u16::from_le_bytes([field[0], field[1]]))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32, ElfError> {
+    let field = bytes.get(offset..offset + 4).ok_or(ElfError::Truncated)?;
+    Ok(u32::from_le_bytes([field[0], field[1], field[2], field[3]]))
+}
+
+fn slice(bytes: &[u8], offset: u32, size: u32) -> Result<&[u8], ElfError> {
+    let start = offset as usize;
+    let end = start
+        .checked_add(size as usize)
+        .ok_or(ElfError::Truncated)?;
+    bytes.get(start..end).ok_or(ElfError::Truncated)
+}
+
+impl Elf {
+    pub fn parse(bytes: &[u8]) -> Result<Self, ElfError> {
+        if bytes.get(0..4) != Some(&ELF_MAGIC[..]) {
+            return Err(ElfError::NotElf);
+        }
+        if bytes.len() < EHDR_SIZE {
+            return Err(ElfError::Truncated);
+        }
+        if bytes[4] != ELFCLASS32 {
+            return Err(ElfError::Unsupported("not a 32 bit file"));
+        }
+        if bytes[5] != ELFDATA2LSB {
+            return Err(ElfError::Unsupported("not little-endian"));
+        }
+        if read_u16(bytes, 16)? != ET_EXEC {
+            return Err(ElfError::Unsupported("not an executable"));
+        }
+        if read_u16(bytes, 18)? != EM_RISCV {
+            return Err(ElfError::Unsupported("not a RISC-V file"));
+        }
+
+        let entry = read_u32(bytes, 24)?;
+        let phoff = read_u32(bytes, 28)? as usize;
+        let shoff = read_u32(bytes, 32)? as usize;
+        let phentsize = read_u16(bytes, 42)? as usize;
+        let phnum = read_u16(bytes, 44)? as usize;
+        let shentsize = read_u16(bytes, 46)? as usize;
+        let shnum = read_u16(bytes, 48)? as usize;
+        if (phnum > 0 && phentsize < PHDR_SIZE) || (shnum > 0 && shentsize < SHDR_SIZE) {
+            return Err(ElfError::Unsupported("unexpected header entry size"));
+        }
+
+        let mut segments = vec![];
+        for i in 0..phnum {
+            let header = phoff + i * phentsize;
+            if read_u32(bytes, header)? != PT_LOAD {
+                continue;
+            }
+            let offset = read_u32(bytes, header + 4)?;
+            let address = read_u32(bytes, header + 12)?; // p_paddr
+            let file_size = read_u32(bytes, header + 16)?;
+            let memory_size = read_u32(bytes, header + 20)?;
+            if file_size > memory_size {
+                return Err(ElfError::Unsupported(
+                    "segment file size exceeds memory size",
+                ));
+            }
+            segments.push(Segment {
+                address,
+                memory_size,
+                data: slice(bytes, offset, file_size)?.to_vec(),
+            });
+        }
+
+        let mut symbols = HashMap::new();
+        for i in 0..shnum {
+            let header = shoff + i * shentsize;
+            if read_u32(bytes, header + 4)? != SHT_SYMTAB {
+                continue;
+            }
+            let table = slice(
+                bytes,
+                read_u32(bytes, header + 16)?,
+                read_u32(bytes, header + 20)?,
+            )?;
+            // sh_link is the index of the associated string table
+            let link = read_u32(bytes, header + 24)? as usize;
+            let strtab_header = shoff + link * shentsize;
+            let strtab = slice(
+                bytes,
+                read_u32(bytes, strtab_header + 16)?,
+                read_u32(bytes, strtab_header + 20)?,
+            )?;
+            for symbol in table.chunks_exact(SYM_SIZE) {
+                let name_offset = read_u32(symbol, 0)? as usize;
+                let value = read_u32(symbol, 4)?;
+                let name = strtab.get(name_offset..).ok_or(ElfError::Truncated)?;
+                let name = &name[..name.iter().position(|&b| b == 0).unwrap_or(name.len())];
+                if !name.is_empty() {
+                    symbols.insert(String::from_utf8_lossy(name).into_owned(), value);
+                }
+            }
+        }
+
+        Ok(Elf {
+            entry,
+            segments,
+            symbols,
+        })
+    }
+
+    /// The address of the symbol called `name`.
+    pub fn symbol(&self, name: &str) -> Option<u32> {
+        self.symbols.get(name).copied()
+    }
+
+    pub fn symbols(&self) -> impl Iterator<Item = (&str, u32)> {
+        self.symbols
+            .iter()
+            .map(|(name, value)| (name.as_str(), *value))
+    }
+
+    /// Copies every segment onto the bus, zero-fills the remainder of each
+    /// segment and points the pc at the entry point.
+    pub fn load(&self, cpu_state: &mut CPUState) -> Result<(), ElfError> {
+        for segment in &self.segments {
+            let mut contents = segment.data.clone();
+            contents.resize(segment.memory_size as usize, 0);
+            if contents.is_empty() {
+                continue;
+            }
+            cpu_state
+                .bus
+                .load(segment.address, &contents)
+                .ok_or(ElfError::SegmentOutOfMemory {
+                    address: segment.address,
+                    size: segment.memory_size,
+                })?;
+        }
+        cpu_state.pc = self.entry;
+        Ok(())
+    }
+}
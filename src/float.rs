@@ -0,0 +1,515 @@
+// Single precision arithmetic with the RISC-V rounding modes and exception
+// flags
+//
+// Host float operations always round to nearest, ties to even, and do not
+// report exceptions, so the F instructions that round are computed here on
+// the bits. Finite operands are unpacked into an exact significand and
+// exponent, the result is computed exactly, or with the bits below the
+// rounding position collapsed into a sticky bit, and then rounded once.
+// Tininess is detected after rounding, as RISC-V requires. NaN results are
+// the canonical NaN.
+
+use crate::CANONICAL_NAN;
+
+const SIGN_BIT: u32 = 1 << 31;
+const EXPONENT_MASK: u32 = 0xFF << 23;
+const FRACTION_MASK: u32 = (1 << 23) - 1;
+const QUIET_BIT: u32 = 1 << 22;
+const INFINITY: u32 = EXPONENT_MASK;
+const MAX_FINITE: u32 = EXPONENT_MASK - 1;
+
+/// Bits of fflags.
+pub const INEXACT: u32 = 1 << 0;
+pub const UNDERFLOW: u32 = 1 << 1;
+pub const OVERFLOW: u32 = 1 << 2;
+pub const DIVIDE_BY_ZERO: u32 = 1 << 3;
+pub const INVALID: u32 = 1 << 4;
+
+// The exponent of the least significant bit of the significands, normal
+// numbers have 24 significant bits and subnormals an lsb of 2^-149.
+const MIN_LSB_EXPONENT: i32 = -149;
+const PRECISION: u32 = 24;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoundingMode {
+    NearestEven,
+    TowardZero,
+    Down,
+    Up,
+    NearestMaxMagnitude,
+}
+
+impl RoundingMode {
+    /// The mode of an rm field or frm value, `None` for the reserved ones
+    /// and for the dynamic mode, which has to be resolved with frm.
+    pub fn from_bits(bits: u32) -> Option<Self> {
+        Some(match bits {
+            0b000 => RoundingMode::NearestEven,
+            0b001 => RoundingMode::TowardZero,
+            0b010 => RoundingMode::Down,
+            0b011 => RoundingMode::Up,
+            0b100 => RoundingMode::NearestMaxMagnitude,
+            _ => return None,
+        })
+    }
+}
+
+/// A finite value, `significand * 2^exponent` with the sign applied.
+#[derive(Debug, Clone, Copy)]
+struct Exact {
+    sign: bool,
+    exponent: i32,
+    significand: u128,
+}
+
+enum Class {
+    Nan,
+    Infinity(bool),
+    Finite(Exact),
+}
+
+fn is_nan(bits: u32) -> bool {
+    bits & EXPONENT_MASK == EXPONENT_MASK && bits & FRACTION_MASK != 0
+}
+
+fn is_signaling(bits: u32) -> bool {
+    is_nan(bits) && bits & QUIET_BIT == 0
+}
+
+fn classify(bits: u32) -> Class {
+    let sign = bits & SIGN_BIT != 0;
+    let exponent = ((bits & EXPONENT_MASK) >> 23) as i32;
+    let fraction = (bits & FRACTION_MASK) as u128;
+    match exponent {
+        0xFF if fraction != 0 => Class::Nan,
+        0xFF => Class::Infinity(sign),
+        // subnormals and zeros have no implicit bit
+        0 => Class::Finite(Exact {
+            sign,
+            exponent: MIN_LSB_EXPONENT,
+            significand: fraction,
+        }),
+        _ => Class::Finite(Exact {
+            sign,
+            exponent: exponent - 150,
+            significand: fraction | 1 << 23,
+        }),
+    }
+}
+
+fn with_sign(sign: bool, bits: u32) -> u32 {
+    if sign {
+        bits | SIGN_BIT
+    } else {
+        bits
+    }
+}
+
+// The canonical NaN, invalid if any operand is a signaling NaN
+fn nan(operands: &[u32], flags: &mut u32) -> u32 {
+    if operands.iter().any(|&bits| is_signaling(bits)) {
+        *flags |= INVALID;
+    }
+    CANONICAL_NAN
+}
+
+fn invalid(flags: &mut u32) -> u32 {
+    *flags |= INVALID;
+    CANONICAL_NAN
+}
+
+/// Shifts right by `shift`, keeping whether any bits were lost.
+fn shift_right(significand: u128, shift: u32) -> (u128, u128) {
+    if shift >= 128 {
+        return (0, significand);
+    }
+    if shift == 0 {
+        return (significand, 0);
+    }
+    (significand >> shift, significand & ((1 << shift) - 1))
+}
+
+/// Rounds `significand` shifted right by `shift` to an integer, returning it
+/// and whether it is inexact.
+fn round_shifted(sign: bool, significand: u128, shift: u32, rm: RoundingMode) -> (u128, bool) {
+    let (kept, rest) = shift_right(significand, shift);
+    if rest == 0 {
+        return (kept, false);
+    }
+    // compare the discarded bits with half of the last kept one
+    let half = if shift >= 129 {
+        None
+    } else {
+        Some(1u128 << (shift - 1))
+    };
+    let ordering = match half {
+        Some(half) => rest.cmp(&half),
+        None => std::cmp::Ordering::Less,
+    };
+    let up = match rm {
+        RoundingMode::NearestEven => ordering.is_gt() || (ordering.is_eq() && kept & 1 == 1),
+        RoundingMode::NearestMaxMagnitude => !ordering.is_lt(),
+        RoundingMode::TowardZero => false,
+        RoundingMode::Down => sign,
+        RoundingMode::Up => !sign,
+    };
+    (kept + up as u128, true)
+}
+
+/// Rounds a nonzero value to single precision.
+fn round(value: Exact, rm: RoundingMode, flags: &mut u32) -> u32 {
+    let Exact {
+        sign,
+        exponent,
+        significand,
+    } = value;
+    debug_assert!(significand != 0);
+    let top = 127 - significand.leading_zeros() as i32;
+    // the exponent of the lsb of the result, before a carry out of rounding
+    let lsb = (exponent + top - (PRECISION as i32 - 1)).max(MIN_LSB_EXPONENT);
+    let (mut kept, inexact, mut lsb) = if lsb > exponent {
+        let (kept, inexact) = round_shifted(sign, significand, (lsb - exponent) as u32, rm);
+        (kept, inexact, lsb)
+    } else {
+        (significand << (exponent - lsb), false, lsb)
+    };
+    if kept >> PRECISION != 0 {
+        kept >>= 1;
+        lsb += 1;
+    }
+
+    if inexact {
+        *flags |= INEXACT;
+        // tiny when rounding to 24 bits with an unbounded exponent would
+        // still leave the result below the smallest normal number
+        if exponent + top < -126 {
+            let shift = (top + 1 - PRECISION as i32).max(0) as u32;
+            let (unbounded, _) = round_shifted(sign, significand, shift, rm);
+            let carried = unbounded >> PRECISION != 0;
+            if !(carried && exponent + top == -127) {
+                *flags |= UNDERFLOW;
+            }
+        }
+    }
+
+    if kept >> (PRECISION - 1) == 0 {
+        // subnormal or zero, the lsb is 2^-149
+        return with_sign(sign, kept as u32);
+    }
+    let biased = lsb + 150;
+    if biased >= 0xFF {
+        *flags |= OVERFLOW | INEXACT;
+        let to_infinity = match rm {
+            RoundingMode::NearestEven | RoundingMode::NearestMaxMagnitude => true,
+            RoundingMode::TowardZero => false,
+            RoundingMode::Down => sign,
+            RoundingMode::Up => !sign,
+        };
+        return with_sign(sign, if to_infinity { INFINITY } else { MAX_FINITE });
+    }
+    with_sign(sign, (biased as u32) << 23 | (kept as u32 & FRACTION_MASK))
+}
+
+// The zero an exact sum of zero has, negative only when both terms are or
+// when rounding down adds opposite signs
+fn zero_sum(a: bool, b: bool, rm: RoundingMode) -> u32 {
+    with_sign(if a == b { a } else { rm == RoundingMode::Down }, 0)
+}
+
+/// Adds two finite values, rounding once.
+fn add_exact(a: Exact, b: Exact, rm: RoundingMode, flags: &mut u32) -> u32 {
+    if a.significand == 0 && b.significand == 0 {
+        return zero_sum(a.sign, b.sign, rm);
+    }
+    if a.significand == 0 {
+        return round(b, rm, flags);
+    }
+    if b.significand == 0 {
+        return round(a, rm, flags);
+    }
+    // move both significands to the top, leaving a bit for the carry, then
+    // align the smaller one, any bits it loses become a sticky bit
+    let normalize = |value: Exact| {
+        let shift = value.significand.leading_zeros() - 2;
+        Exact {
+            exponent: value.exponent - shift as i32,
+            significand: value.significand << shift,
+            ..value
+        }
+    };
+    let (mut large, mut small) = (normalize(a), normalize(b));
+    if large.exponent < small.exponent {
+        std::mem::swap(&mut large, &mut small);
+    }
+    let (aligned, lost) = shift_right(small.significand, (large.exponent - small.exponent) as u32);
+    let aligned = aligned | (lost != 0) as u128;
+    let (sign, significand) = if large.sign == small.sign {
+        (large.sign, large.significand + aligned)
+    } else if large.significand >= aligned {
+        (large.sign, large.significand - aligned)
+    } else {
+        (small.sign, aligned - large.significand)
+    };
+    if significand == 0 {
+        return zero_sum(a.sign, b.sign, rm);
+    }
+    round(
+        Exact {
+            sign,
+            exponent: large.exponent,
+            significand,
+        },
+        rm,
+        flags,
+    )
+}
+
+pub fn add(a: u32, b: u32, rm: RoundingMode, flags: &mut u32) -> u32 {
+    match (classify(a), classify(b)) {
+        (Class::Nan, _) | (_, Class::Nan) => nan(&[a, b], flags),
+        (Class::Infinity(a_sign), Class::Infinity(b_sign)) if a_sign != b_sign => invalid(flags),
+        (Class::Infinity(sign), _) | (_, Class::Infinity(sign)) => with_sign(sign, INFINITY),
+        (Class::Finite(a), Class::Finite(b)) => add_exact(a, b, rm, flags),
+    }
+}
+
+pub fn sub(a: u32, b: u32, rm: RoundingMode, flags: &mut u32) -> u32 {
+    add(a, b ^ SIGN_BIT, rm, flags)
+}
+
+// The exact product of two finite values
+fn multiply(a: Exact, b: Exact) -> Exact {
+    Exact {
+        sign: a.sign != b.sign,
+        exponent: a.exponent + b.exponent,
+        significand: a.significand * b.significand,
+    }
+}
+
+pub fn mul(a: u32, b: u32, rm: RoundingMode, flags: &mut u32) -> u32 {
+    let sign = (a ^ b) & SIGN_BIT != 0;
+    match (classify(a), classify(b)) {
+        (Class::Nan, _) | (_, Class::Nan) => nan(&[a, b], flags),
+        (Class::Infinity(_), Class::Finite(other)) | (Class::Finite(other), Class::Infinity(_))
+            if other.significand == 0 =>
+        {
+            invalid(flags)
+        }
+        (Class::Infinity(_), _) | (_, Class::Infinity(_)) => with_sign(sign, INFINITY),
+        (Class::Finite(a), Class::Finite(b)) => {
+            let product = multiply(a, b);
+            if product.significand == 0 {
+                return with_sign(sign, 0);
+            }
+            round(product, rm, flags)
+        }
+    }
+}
+
+pub fn div(a: u32, b: u32, rm: RoundingMode, flags: &mut u32) -> u32 {
+    let sign = (a ^ b) & SIGN_BIT != 0;
+    match (classify(a), classify(b)) {
+        (Class::Nan, _) | (_, Class::Nan) => nan(&[a, b], flags),
+        (Class::Infinity(_), Class::Infinity(_)) => invalid(flags),
+        (Class::Infinity(_), _) => with_sign(sign, INFINITY),
+        (_, Class::Infinity(_)) => with_sign(sign, 0),
+        (Class::Finite(a), Class::Finite(b)) => match (a.significand, b.significand) {
+            (0, 0) => invalid(flags),
+            (_, 0) => {
+                *flags |= DIVIDE_BY_ZERO;
+                with_sign(sign, INFINITY)
+            }
+            (0, _) => with_sign(sign, 0),
+            _ => {
+                // a quotient of at least 100 bits, with the remainder as a
+                // sticky bit
+                let shift = a.significand.leading_zeros() - 1;
+                let dividend = a.significand << shift;
+                let quotient = dividend / b.significand;
+                let sticky = (dividend % b.significand != 0) as u128;
+                round(
+                    Exact {
+                        sign,
+                        exponent: a.exponent - shift as i32 - b.exponent,
+                        significand: quotient | sticky,
+                    },
+                    rm,
+                    flags,
+                )
+            }
+        },
+    }
+}
+
+pub fn sqrt(a: u32, rm: RoundingMode, flags: &mut u32) -> u32 {
+    match classify(a) {
+        Class::Nan => nan(&[a], flags),
+        Class::Infinity(false) => INFINITY,
+        Class::Infinity(true) => invalid(flags),
+        // the square root of -0 is -0
+        Class::Finite(value) if value.significand == 0 => a,
+        Class::Finite(value) if value.sign => invalid(flags),
+        Class::Finite(value) => {
+            // widen to about 120 bits with an even exponent, for a root of
+            // about 60 bits
+            let mut shift = value.significand.leading_zeros() as i32 - 8;
+            if (value.exponent - shift) % 2 != 0 {
+                shift -= 1;
+            }
+            let radicand = value.significand << shift;
+            let root = radicand.isqrt();
+            let sticky = (root * root != radicand) as u128;
+            round(
+                Exact {
+                    sign: false,
+                    exponent: (value.exponent - shift) / 2,
+                    significand: root | sticky,
+                },
+                rm,
+                flags,
+            )
+        }
+    }
+}
+
+/// `(a * b) + c` rounded once, with the product or addend negated first.
+pub fn fused_multiply_add(
+    a: u32,
+    b: u32,
+    c: u32,
+    negate_product: bool,
+    negate_addend: bool,
+    rm: RoundingMode,
+    flags: &mut u32,
+) -> u32 {
+    let product_sign = ((a ^ b) & SIGN_BIT != 0) != negate_product;
+    let addend = c ^ (negate_addend as u32) << 31;
+    let (a_class, b_class, c_class) = (classify(a), classify(b), classify(addend));
+    let zero = |class: &Class| matches!(class, Class::Finite(value) if value.significand == 0);
+    // infinity times zero is invalid even with a quiet NaN addend
+    if matches!(a_class, Class::Infinity(_)) && zero(&b_class)
+        || zero(&a_class) && matches!(b_class, Class::Infinity(_))
+    {
+        return invalid(flags);
+    }
+    if matches!(a_class, Class::Nan)
+        || matches!(b_class, Class::Nan)
+        || matches!(c_class, Class::Nan)
+    {
+        return nan(&[a, b, c], flags);
+    }
+    let product_infinite =
+        matches!(a_class, Class::Infinity(_)) || matches!(b_class, Class::Infinity(_));
+    match (product_infinite, c_class) {
+        (true, Class::Infinity(sign)) if sign != product_sign => invalid(flags),
+        (true, _) => with_sign(product_sign, INFINITY),
+        (false, Class::Infinity(sign)) => with_sign(sign, INFINITY),
+        (false, Class::Finite(c)) => {
+            let (Class::Finite(a), Class::Finite(b)) = (a_class, b_class) else {
+                unreachable!()
+            };
+            let product = Exact {
+                sign: product_sign,
+                ..multiply(a, b)
+            };
+            add_exact(product, c, rm, flags)
+        }
+        (false, Class::Nan) => unreachable!(),
+    }
+}
+
+/// Converts to a signed or unsigned integer. Out of range values and NaNs
+/// are invalid and saturate, NaN to the maximum.
+pub fn to_integer(a: u32, signed: bool, rm: RoundingMode, flags: &mut u32) -> u32 {
+    let (min, max) = if signed {
+        (i32::MIN as u32, i32::MAX as u32)
+    } else {
+        (0, u32::MAX)
+    };
+    let value = match classify(a) {
+        Class::Nan => {
+            *flags |= INVALID;
+            return max;
+        }
+        Class::Infinity(sign) => {
+            *flags |= INVALID;
+            return if sign { min } else { max };
+        }
+        Class::Finite(value) => value,
+    };
+    let (magnitude, inexact) = if value.exponent < 0 {
+        round_shifted(value.sign, value.significand, -value.exponent as u32, rm)
+    } else if value.exponent < 64 {
+        (value.significand << value.exponent, false)
+    } else {
+        (u128::MAX, false)
+    };
+    let limit = match (signed, value.sign) {
+        (true, true) => 1 << 31,
+        (true, false) => i32::MAX as u128,
+        (false, true) => 0,
+        (false, false) => u32::MAX as u128,
+    };
+    if magnitude > limit {
+        *flags |= INVALID;
+        return if value.sign { min } else { max };
+    }
+    if inexact {
+        *flags |= INEXACT;
+    }
+    if value.sign {
+        (magnitude as u32).wrapping_neg()
+    } else {
+        magnitude as u32
+    }
+}
+
+/// Converts a signed or unsigned integer.
+pub fn from_integer(x: u32, signed: bool, rm: RoundingMode, flags: &mut u32) -> u32 {
+    let sign = signed && (x as i32) < 0;
+    let magnitude = if sign { x.wrapping_neg() } else { x };
+    if magnitude == 0 {
+        return 0;
+    }
+    round(
+        Exact {
+            sign,
+            exponent: 0,
+            significand: magnitude as u128,
+        },
+        rm,
+        flags,
+    )
+}
+
+/// FEQ.S, which is invalid only for signaling NaNs.
+pub fn eq(a: u32, b: u32, flags: &mut u32) -> bool {
+    if is_signaling(a) || is_signaling(b) {
+        *flags |= INVALID;
+    }
+    f32::from_bits(a) == f32::from_bits(b)
+}
+
+/// FLT.S or FLE.S, which are invalid for any NaN.
+pub fn less(a: u32, b: u32, or_equal: bool, flags: &mut u32) -> bool {
+    if is_nan(a) || is_nan(b) {
+        *flags |= INVALID;
+        return false;
+    }
+    let (a, b) = (f32::from_bits(a), f32::from_bits(b));
+    if or_equal {
+        a <= b
+    } else {
+        a < b
+    }
+}
+
+/// Whether FMIN.S or FMAX.S of `a` and `b` is invalid, which is only for
+/// signaling NaNs.
+pub fn min_max_flags(a: u32, b: u32) -> u32 {
+    if is_signaling(a) || is_signaling(b) {
+        INVALID
+    } else {
+        0
+    }
+}
@@ -0,0 +1,263 @@
+// The host-target interface of Spike, used by bare-metal test programs
+//
+// The program writes 64 bit commands to the `tohost` word and receives
+// replies in `fromhost`. The top byte of a command selects a device, the next
+// byte a command and the low 48 bits are its payload. Device 0 with an odd
+// payload exits with status `payload >> 1`, with an even payload it asks the
+// host to perform the system call described by the eight 64 bit words at
+// that address. Device 1 is a console, command 1 writes a character and
+// command 0 reads one.
+
+use std::collections::VecDeque;
+
+use crate::bus::Device;
+use crate::syscall::{EFAULT, ENOSYS, SYS_EXIT, SYS_EXIT_GROUP, SYS_WRITE};
+use crate::{CPUState, Elf, RunOutcome, SyscallAction};
+
+const DEVICE_SYSCALL: u8 = 0;
+const DEVICE_CONSOLE: u8 = 1;
+const CONSOLE_GETCHAR: u8 = 0;
+const CONSOLE_PUTCHAR: u8 = 1;
+const PAYLOAD_MASK: u64 = (1 << 48) - 1;
+
+/// Words of the system call block, the call number then its arguments.
+const SYSCALL_WORDS: usize = 8;
+
+const A0: usize = 10;
+const A7: usize = 17;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Request {
+    Exit(u32),
+    Syscall(u32),
+}
+
+/// The `tohost` register, mapped over the address a program links it at with
+/// `CPUState::attach_htif` next to a separate `fromhost` register, so the
+/// memory between the two stays in place.
+///
+/// A command is carried out once the high word of `tohost` is written, as a
+/// 32 bit program writes the low word first. Its reply reaches `fromhost` at
+/// the end of the step.
+#[derive(Debug, Clone)]
+pub struct Htif {
+    tohost_address: u32,
+    fromhost_address: Option<u32>,
+    tohost: u64,
+    pending_reply: Option<u64>,
+    request: Option<Request>,
+    input: VecDeque<u8>,
+    waiting_for_input: bool,
+    output: Vec<u8>,
+}
+
+impl Htif {
+    pub fn new(tohost: u32, fromhost: Option<u32>) -> Self {
+        Htif {
+            tohost_address: tohost,
+            fromhost_address: fromhost,
+            tohost: 0,
+            pending_reply: None,
+            request: None,
+            input: VecDeque::new(),
+            waiting_for_input: false,
+            output: vec![],
+        }
+    }
+
+    /// The registers at the `tohost` and `fromhost` symbols of `elf`, `None`
+    /// if it has no `tohost`.
+    pub fn for_elf(elf: &Elf) -> Option<Self> {
+        Some(Htif::new(elf.symbol("tohost")?, elf.symbol("fromhost")))
+    }
+
+    /// Queues bytes for the console to read.
+    pub fn push_input(&mut self, bytes: &[u8]) {
+        self.input.extend(bytes);
+        if self.waiting_for_input {
+            self.read_input();
+        }
+    }
+
+    /// Bytes written to the console, with putchar or a write system call.
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.output)
+    }
+
+    fn reply(&mut self, device: u8, command: u8, payload: u64) {
+        self.pending_reply =
+            Some((device as u64) << 56 | (command as u64) << 48 | payload & PAYLOAD_MASK);
+    }
+
+    fn read_input(&mut self) {
+        match self.input.pop_front() {
+            Some(byte) => {
+                self.waiting_for_input = false;
+                self.reply(DEVICE_CONSOLE, CONSOLE_GETCHAR, byte as u64);
+            }
+            None => self.waiting_for_input = true,
+        }
+    }
+
+    fn command(&mut self, value: u64) {
+        let device = (value >> 56) as u8;
+        let command = (value >> 48) as u8;
+        let payload = value & PAYLOAD_MASK;
+        match (device, command) {
+            (DEVICE_SYSCALL, _) if payload & 1 == 1 => {
+                self.request = Some(Request::Exit((payload >> 1) as u32));
+            }
+            (DEVICE_SYSCALL, _) => self.request = Some(Request::Syscall(payload as u32)),
+            (DEVICE_CONSOLE, CONSOLE_PUTCHAR) => {
+                self.output.push(payload as u8);
+                self.reply(DEVICE_CONSOLE, CONSOLE_PUTCHAR, 0);
+            }
+            (DEVICE_CONSOLE, CONSOLE_GETCHAR) => self.read_input(),
+            // like Spike, commands to devices that do not exist are dropped
+            _ => {}
+        }
+    }
+}
+
+impl Device for Htif {
+    fn size(&self) -> u32 {
+        8
+    }
+
+    fn read(&mut self, offset: u32, size: usize) -> Option<u32> {
+        Some(read_register(self.tohost, offset, size))
+    }
+
+    fn write(&mut self, offset: u32, size: usize, value: u32) -> Option<()> {
+        write_register(&mut self.tohost, offset, size, value);
+        if self.tohost != 0 && offset + size as u32 == 8 {
+            let command = std::mem::take(&mut self.tohost);
+            self.command(command);
+        }
+        Some(())
+    }
+}
+
+// The `fromhost` register, holding the last reply of the `Htif` until the
+// program clears it
+#[derive(Debug, Clone, Default)]
+struct Fromhost(u64);
+
+impl Device for Fromhost {
+    fn size(&self) -> u32 {
+        8
+    }
+
+    fn read(&mut self, offset: u32, size: usize) -> Option<u32> {
+        Some(read_register(self.0, offset, size))
+    }
+
+    fn write(&mut self, offset: u32, size: usize, value: u32) -> Option<()> {
+        write_register(&mut self.0, offset, size, value);
+        Some(())
+    }
+}
+
+fn read_register(register: u64, offset: u32, size: usize) -> u32 {
+    (0..size as u32).fold(0, |value, i| {
+        value | ((register >> (8 * (offset + i))) as u8 as u32) << (8 * i)
+    })
+}
+
+fn write_register(register: &mut u64, offset: u32, size: usize, value: u32) {
+    for i in 0..size as u32 {
+        let byte = offset + i;
+        *register &= !(0xFF << (8 * byte));
+        *register |= ((value >> (8 * i)) as u8 as u64) << (8 * byte);
+    }
+}
+
+/// Maps the registers of `htif` over the memory at their addresses.
+pub(crate) fn attach(cpu_state: &mut CPUState, htif: Htif) {
+    if let Some(fromhost) = htif.fromhost_address {
+        cpu_state.bus.map_over(fromhost, Fromhost::default());
+    }
+    cpu_state.bus.map_over(htif.tohost_address, htif);
+}
+
+/// Carries out the request the program made through the attached `Htif` in
+/// the last step and delivers its reply, returning the outcome if it ends
+/// the run.
+pub(crate) fn service(cpu_state: &mut CPUState) -> Option<RunOutcome> {
+    let htif = cpu_state.bus.device_mut::<Htif>()?;
+    if htif.request.is_none() && htif.pending_reply.is_none() {
+        return None;
+    }
+    let outcome = match htif.request.take() {
+        Some(Request::Exit(code)) => Some(RunOutcome::Exited(code as i32)),
+        Some(Request::Syscall(address)) => {
+            let (result, action) = match read_words(cpu_state, address) {
+                Some(words) => syscall(cpu_state, &words),
+                None => (-EFAULT as u32, SyscallAction::Continue),
+            };
+            // the result replaces the call number, sign extended
+            let result = (result as i32 as i64).to_le_bytes();
+            cpu_state.write_bytes(address, &result);
+            let htif = cpu_state.bus.device_mut::<Htif>()?;
+            htif.reply(DEVICE_SYSCALL, 0, 1);
+            match action {
+                SyscallAction::Exit(code) => Some(RunOutcome::Exited(code)),
+                _ => None,
+            }
+        }
+        None => None,
+    };
+    let reply = cpu_state.bus.device_mut::<Htif>()?.pending_reply.take();
+    if let (Some(reply), Some(fromhost)) = (reply, cpu_state.bus.device_mut::<Fromhost>()) {
+        fromhost.0 = reply;
+    }
+    outcome
+}
+
+// The low halves of the 64 bit words of a system call block
+fn read_words(cpu_state: &mut CPUState, address: u32) -> Option<[u32; SYSCALL_WORDS]> {
+    let bytes = cpu_state.read_bytes(address, SYSCALL_WORDS * 8)?;
+    let mut words = [0; SYSCALL_WORDS];
+    for (word, bytes) in words.iter_mut().zip(bytes.chunks(8)) {
+        *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    }
+    Some(words)
+}
+
+/// Performs a proxied system call through the hart's syscall handler, with
+/// the call number and arguments placed in the registers an ecall would use.
+/// Without a handler, writes to stdout and stderr go to the console and exit
+/// is understood.
+fn syscall(cpu_state: &mut CPUState, words: &[u32; SYSCALL_WORDS]) -> (u32, SyscallAction) {
+    let (number, arguments) = (words[0], &words[1..]);
+    if cpu_state.syscall_handler.is_some() {
+        let registers = cpu_state.registers;
+        cpu_state.registers[A0..A7].copy_from_slice(arguments);
+        cpu_state.registers[A7] = number;
+        let action = cpu_state.handle_syscall();
+        let result = match action {
+            SyscallAction::Unhandled => -ENOSYS as u32,
+            _ => cpu_state.registers[A0],
+        };
+        cpu_state.registers = registers;
+        return (result, action);
+    }
+    match number {
+        SYS_WRITE if arguments[0] == 1 || arguments[0] == 2 => {
+            match cpu_state.read_bytes(arguments[1], arguments[2] as usize) {
+                Some(bytes) => {
+                    let htif = cpu_state.bus.device_mut::<Htif>().unwrap();
+                    htif.output.extend_from_slice(&bytes);
+                    (bytes.len() as u32, SyscallAction::Continue)
+                }
+                None => (-EFAULT as u32, SyscallAction::Continue),
+            }
+        }
+        SYS_EXIT | SYS_EXIT_GROUP => (0, SyscallAction::Exit(arguments[0] as i32)),
+        _ => (-ENOSYS as u32, SyscallAction::Continue),
+    }
+}
@@ -0,0 +1,866 @@
+// Decoded RV32IMF instructions
+//
+// This is the one description of the instruction encodings: the interpreter
+// decodes with it, and the assembler and disassembler encode and name
+// instructions with it. Register fields are register numbers and immediates
+// are sign-extended.
+
+use std::error::Error;
+use std::fmt;
+
+/// The rounding mode that defers to the frm CSR.
+pub const DYNAMIC_ROUNDING: u8 = 0b111;
+
+// Declares an operation enum along with its mnemonics.
+macro_rules! operations {
+    ($(#[$attr:meta])* $name:ident { $($variant:ident => $mnemonic:literal;)* }) => {
+        $(#[$attr])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum $name {
+            $($variant,)*
+        }
+
+        impl $name {
+            pub const ALL: &'static [$name] = &[$($name::$variant,)*];
+
+            pub fn mnemonic(self) -> &'static str {
+                match self {
+                    $($name::$variant => $mnemonic,)*
+                }
+            }
+
+            pub fn from_mnemonic(mnemonic: &str) -> Option<Self> {
+                Self::ALL.iter().copied().find(|op| op.mnemonic() == mnemonic)
+            }
+        }
+    };
+}
+
+operations! {
+    /// Register-register integer operations.
+    RegisterOp {
+        Add => "add";
+        Sub => "sub";
+        Sll => "sll";
+        Slt => "slt";
+        Sltu => "sltu";
+        Xor => "xor";
+        Srl => "srl";
+        Sra => "sra";
+        Or => "or";
+        And => "and";
+        Mul => "mul";
+        Mulh => "mulh";
+        Mulhsu => "mulhsu";
+        Mulhu => "mulhu";
+        Div => "div";
+        Divu => "divu";
+        Rem => "rem";
+        Remu => "remu";
+    }
+}
+
+operations! {
+    /// Register-immediate integer operations other than shifts.
+    ImmediateOp {
+        Addi => "addi";
+        Slti => "slti";
+        Sltiu => "sltiu";
+        Xori => "xori";
+        Ori => "ori";
+        Andi => "andi";
+    }
+}
+
+operations! {
+    /// Shifts by an immediate.
+    ShiftOp {
+        Slli => "slli";
+        Srli => "srli";
+        Srai => "srai";
+    }
+}
+
+operations! {
+    /// Integer loads.
+    LoadOp {
+        Lb => "lb";
+        Lh => "lh";
+        Lw => "lw";
+        Lbu => "lbu";
+        Lhu => "lhu";
+    }
+}
+
+operations! {
+    /// Integer stores.
+    StoreOp {
+        Sb => "sb";
+        Sh => "sh";
+        Sw => "sw";
+    }
+}
+
+operations! {
+    /// Conditional branches.
+    BranchOp {
+        Beq => "beq";
+        Bne => "bne";
+        Blt => "blt";
+        Bge => "bge";
+        Bltu => "bltu";
+        Bgeu => "bgeu";
+    }
+}
+
+operations! {
+    /// CSR accesses.
+    CsrOp {
+        Csrrw => "csrrw";
+        Csrrs => "csrrs";
+        Csrrc => "csrrc";
+        Csrrwi => "csrrwi";
+        Csrrsi => "csrrsi";
+        Csrrci => "csrrci";
+    }
+}
+
+operations! {
+    /// Floating point arithmetic that rounds.
+    FloatArithmeticOp {
+        Fadd => "fadd.s";
+        Fsub => "fsub.s";
+        Fmul => "fmul.s";
+        Fdiv => "fdiv.s";
+    }
+}
+
+operations! {
+    /// Two operand floating point instructions that do not round: sign
+    /// injection, minimum / maximum and comparisons.
+    FloatBinaryOp {
+        Fsgnj => "fsgnj.s";
+        Fsgnjn => "fsgnjn.s";
+        Fsgnjx => "fsgnjx.s";
+        Fmin => "fmin.s";
+        Fmax => "fmax.s";
+        Feq => "feq.s";
+        Flt => "flt.s";
+        Fle => "fle.s";
+    }
+}
+
+operations! {
+    /// Single operand floating point instructions that round.
+    FloatUnaryOp {
+        Fsqrt => "fsqrt.s";
+        FcvtWS => "fcvt.w.s";
+        FcvtWuS => "fcvt.wu.s";
+        FcvtSW => "fcvt.s.w";
+        FcvtSWu => "fcvt.s.wu";
+    }
+}
+
+operations! {
+    /// Moves between the register files and classification, which work on
+    /// the raw bits.
+    FloatBitsOp {
+        FmvXW => "fmv.x.w";
+        Fclass => "fclass.s";
+        FmvWX => "fmv.w.x";
+    }
+}
+
+operations! {
+    /// Fused multiply-adds.
+    FusedOp {
+        Fmadd => "fmadd.s";
+        Fmsub => "fmsub.s";
+        Fnmsub => "fnmsub.s";
+        Fnmadd => "fnmadd.s";
+    }
+}
+
+impl CsrOp {
+    /// The immediate forms use the rs1 field as a 5 bit unsigned immediate.
+    pub fn is_immediate(self) -> bool {
+        matches!(self, CsrOp::Csrrwi | CsrOp::Csrrsi | CsrOp::Csrrci)
+    }
+}
+
+impl FloatBinaryOp {
+    /// Comparisons write an integer register.
+    pub fn integer_destination(self) -> bool {
+        matches!(
+            self,
+            FloatBinaryOp::Feq | FloatBinaryOp::Flt | FloatBinaryOp::Fle
+        )
+    }
+}
+
+impl FloatUnaryOp {
+    pub fn integer_destination(self) -> bool {
+        matches!(self, FloatUnaryOp::FcvtWS | FloatUnaryOp::FcvtWuS)
+    }
+
+    pub fn integer_source(self) -> bool {
+        matches!(self, FloatUnaryOp::FcvtSW | FloatUnaryOp::FcvtSWu)
+    }
+}
+
+impl FloatBitsOp {
+    pub fn integer_destination(self) -> bool {
+        matches!(self, FloatBitsOp::FmvXW | FloatBitsOp::Fclass)
+    }
+
+    pub fn integer_source(self) -> bool {
+        self == FloatBitsOp::FmvWX
+    }
+}
+
+/// A decoded instruction, grouped by format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Instruction {
+    Register {
+        op: RegisterOp,
+        rd: u8,
+        rs1: u8,
+        rs2: u8,
+    },
+    Immediate {
+        op: ImmediateOp,
+        rd: u8,
+        rs1: u8,
+        imm: i32,
+    },
+    Shift {
+        op: ShiftOp,
+        rd: u8,
+        rs1: u8,
+        shamt: u8,
+    },
+    Load {
+        op: LoadOp,
+        rd: u8,
+        rs1: u8,
+        offset: i32,
+    },
+    Store {
+        op: StoreOp,
+        rs1: u8,
+        rs2: u8,
+        offset: i32,
+    },
+    Branch {
+        op: BranchOp,
+        rs1: u8,
+        rs2: u8,
+        offset: i32,
+    },
+    /// `imm` is the upper 20 bits, as written in assembly.
+    Lui {
+        rd: u8,
+        imm: u32,
+    },
+    Auipc {
+        rd: u8,
+        imm: u32,
+    },
+    Jal {
+        rd: u8,
+        offset: i32,
+    },
+    Jalr {
+        rd: u8,
+        rs1: u8,
+        offset: i32,
+    },
+    /// `pred` and `succ` hold the I, O, R and W bits from most significant.
+    Fence {
+        pred: u8,
+        succ: u8,
+    },
+    Ecall,
+    Ebreak,
+    Mret,
+    Wfi,
+    /// `source` is rs1, or the immediate for the immediate forms.
+    Csr {
+        op: CsrOp,
+        rd: u8,
+        source: u8,
+        csr: u16,
+    },
+    Flw {
+        rd: u8,
+        rs1: u8,
+        offset: i32,
+    },
+    Fsw {
+        rs1: u8,
+        rs2: u8,
+        offset: i32,
+    },
+    FloatArithmetic {
+        op: FloatArithmeticOp,
+        rd: u8,
+        rs1: u8,
+        rs2: u8,
+        rm: u8,
+    },
+    FloatBinary {
+        op: FloatBinaryOp,
+        rd: u8,
+        rs1: u8,
+        rs2: u8,
+    },
+    FloatUnary {
+        op: FloatUnaryOp,
+        rd: u8,
+        rs1: u8,
+        rm: u8,
+    },
+    FloatBits {
+        op: FloatBitsOp,
+        rd: u8,
+        rs1: u8,
+    },
+    FloatFused {
+        op: FusedOp,
+        rd: u8,
+        rs1: u8,
+        rs2: u8,
+        rs3: u8,
+        rm: u8,
+    },
+}
+
+/// The word is not a supported instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeError {
+    pub instruction: u32,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "illegal instruction {:#010x}", self.instruction)
+    }
+}
+
+impl Error for DecodeError {}
+
+// The encoding of every instruction. Immediates are sign-extended and `imm_u`
+// is the upper 20 bits, as written in assembly.
+virtual_machine::vm! {
+    fields {
+        opcode: u32 = 0..7,
+        rd: u8 = 7..12,
+        funct3: u32 = 12..15,
+        rs1: u8 = 15..20,
+        // the source of the immediate CSR instructions
+        uimm: u8 = 15..20,
+        rs2: u8 = 20..25,
+        funct7: u32 = 25..32,
+        shamt: u8 = 20..25,
+        rm: u8 = 12..15,
+        rs3: u8 = 27..32,
+        // the format of fused multiply-adds, 0 for single precision
+        fmt: u32 = 25..27,
+        csr: u16 = 20..32,
+        pred: u8 = 24..28,
+        succ: u8 = 20..24,
+        imm_i: i32 = signed(20..32),
+        imm_s: i32 = signed(25..32, 7..12),
+        imm_b: i32 = signed(31..32, 7..8, 25..31, 8..12) << 1,
+        imm_j: i32 = signed(31..32, 12..20, 20..21, 21..31) << 1,
+        imm_u: u32 = 12..32,
+    }
+
+    // rough dynamic instruction frequencies of compiled integer code, in
+    // tenths of a percent
+    #[profile(
+        addi = 200, lw = 150, sw = 80, add = 60, beq = 50, bne = 50, lui = 30, jal = 30,
+        jalr = 30, slli = 30, srli = 20, srai = 10, andi = 20, auipc = 20, blt = 20, bge = 20,
+        bltu = 20, bgeu = 10, sub = 20, or = 10, and = 10, xor = 10, lbu = 20, sb = 20, ori = 10,
+        sltu = 10, sltiu = 10,
+    )]
+    #[encodings(pub ENCODINGS)]
+    fn decode_word(word: u32) -> Result<Instruction, DecodeError> {
+        lui: opcode == 0b0110111 => Ok(Instruction::Lui { rd, imm: imm_u }),
+        auipc: opcode == 0b0010111 => Ok(Instruction::Auipc { rd, imm: imm_u }),
+        jal: opcode == 0b1101111 => Ok(Instruction::Jal { rd, offset: imm_j }),
+        jalr(rd, imm_i, rs1): opcode == 0b1100111 && funct3 == 0
+            => Ok(Instruction::Jalr { rd, rs1, offset: imm_i }),
+
+        beq: opcode == 0b1100011 && funct3 == 0b000 => Ok(branch(BranchOp::Beq, rs1, rs2, imm_b)),
+        bne: opcode == 0b1100011 && funct3 == 0b001 => Ok(branch(BranchOp::Bne, rs1, rs2, imm_b)),
+        blt: opcode == 0b1100011 && funct3 == 0b100 => Ok(branch(BranchOp::Blt, rs1, rs2, imm_b)),
+        bge: opcode == 0b1100011 && funct3 == 0b101 => Ok(branch(BranchOp::Bge, rs1, rs2, imm_b)),
+        bltu: opcode == 0b1100011 && funct3 == 0b110 => Ok(branch(BranchOp::Bltu, rs1, rs2, imm_b)),
+        bgeu: opcode == 0b1100011 && funct3 == 0b111 => Ok(branch(BranchOp::Bgeu, rs1, rs2, imm_b)),
+
+        lb(rd, imm_i, rs1): opcode == 0b0000011 && funct3 == 0b000
+            => Ok(load(LoadOp::Lb, rd, rs1, imm_i)),
+        lh(rd, imm_i, rs1): opcode == 0b0000011 && funct3 == 0b001
+            => Ok(load(LoadOp::Lh, rd, rs1, imm_i)),
+        lw(rd, imm_i, rs1): opcode == 0b0000011 && funct3 == 0b010
+            => Ok(load(LoadOp::Lw, rd, rs1, imm_i)),
+        lbu(rd, imm_i, rs1): opcode == 0b0000011 && funct3 == 0b100
+            => Ok(load(LoadOp::Lbu, rd, rs1, imm_i)),
+        lhu(rd, imm_i, rs1): opcode == 0b0000011 && funct3 == 0b101
+            => Ok(load(LoadOp::Lhu, rd, rs1, imm_i)),
+
+        sb(rs2, imm_s, rs1): opcode == 0b0100011 && funct3 == 0b000
+            => Ok(store(StoreOp::Sb, rs1, rs2, imm_s)),
+        sh(rs2, imm_s, rs1): opcode == 0b0100011 && funct3 == 0b001
+            => Ok(store(StoreOp::Sh, rs1, rs2, imm_s)),
+        sw(rs2, imm_s, rs1): opcode == 0b0100011 && funct3 == 0b010
+            => Ok(store(StoreOp::Sw, rs1, rs2, imm_s)),
+
+        addi: opcode == 0b0010011 && funct3 == 0b000
+            => Ok(immediate(ImmediateOp::Addi, rd, rs1, imm_i)),
+        slti: opcode == 0b0010011 && funct3 == 0b010
+            => Ok(immediate(ImmediateOp::Slti, rd, rs1, imm_i)),
+        sltiu: opcode == 0b0010011 && funct3 == 0b011
+            => Ok(immediate(ImmediateOp::Sltiu, rd, rs1, imm_i)),
+        xori: opcode == 0b0010011 && funct3 == 0b100
+            => Ok(immediate(ImmediateOp::Xori, rd, rs1, imm_i)),
+        ori: opcode == 0b0010011 && funct3 == 0b110
+            => Ok(immediate(ImmediateOp::Ori, rd, rs1, imm_i)),
+        andi: opcode == 0b0010011 && funct3 == 0b111
+            => Ok(immediate(ImmediateOp::Andi, rd, rs1, imm_i)),
+        slli: opcode == 0b0010011 && funct3 == 0b001 && funct7 == 0b0000000
+            => Ok(shift(ShiftOp::Slli, rd, rs1, shamt)),
+        srli: opcode == 0b0010011 && funct3 == 0b101 && funct7 == 0b0000000
+            => Ok(shift(ShiftOp::Srli, rd, rs1, shamt)),
+        srai: opcode == 0b0010011 && funct3 == 0b101 && funct7 == 0b0100000
+            => Ok(shift(ShiftOp::Srai, rd, rs1, shamt)),
+
+        add: opcode == 0b0110011 && funct3 == 0b000 && funct7 == 0b0000000
+            => Ok(register(RegisterOp::Add, rd, rs1, rs2)),
+        sub: opcode == 0b0110011 && funct3 == 0b000 && funct7 == 0b0100000
+            => Ok(register(RegisterOp::Sub, rd, rs1, rs2)),
+        sll: opcode == 0b0110011 && funct3 == 0b001 && funct7 == 0b0000000
+            => Ok(register(RegisterOp::Sll, rd, rs1, rs2)),
+        slt: opcode == 0b0110011 && funct3 == 0b010 && funct7 == 0b0000000
+            => Ok(register(RegisterOp::Slt, rd, rs1, rs2)),
+        sltu: opcode == 0b0110011 && funct3 == 0b011 && funct7 == 0b0000000
+            => Ok(register(RegisterOp::Sltu, rd, rs1, rs2)),
+        xor: opcode == 0b0110011 && funct3 == 0b100 && funct7 == 0b0000000
+            => Ok(register(RegisterOp::Xor, rd, rs1, rs2)),
+        srl: opcode == 0b0110011 && funct3 == 0b101 && funct7 == 0b0000000
+            => Ok(register(RegisterOp::Srl, rd, rs1, rs2)),
+        sra: opcode == 0b0110011 && funct3 == 0b101 && funct7 == 0b0100000
+            => Ok(register(RegisterOp::Sra, rd, rs1, rs2)),
+        or: opcode == 0b0110011 && funct3 == 0b110 && funct7 == 0b0000000
+            => Ok(register(RegisterOp::Or, rd, rs1, rs2)),
+        and: opcode == 0b0110011 && funct3 == 0b111 && funct7 == 0b0000000
+            => Ok(register(RegisterOp::And, rd, rs1, rs2)),
+        mul: opcode == 0b0110011 && funct3 == 0b000 && funct7 == 0b0000001
+            => Ok(register(RegisterOp::Mul, rd, rs1, rs2)),
+        mulh: opcode == 0b0110011 && funct3 == 0b001 && funct7 == 0b0000001
+            => Ok(register(RegisterOp::Mulh, rd, rs1, rs2)),
+        mulhsu: opcode == 0b0110011 && funct3 == 0b010 && funct7 == 0b0000001
+            => Ok(register(RegisterOp::Mulhsu, rd, rs1, rs2)),
+        mulhu: opcode == 0b0110011 && funct3 == 0b011 && funct7 == 0b0000001
+            => Ok(register(RegisterOp::Mulhu, rd, rs1, rs2)),
+        div: opcode == 0b0110011 && funct3 == 0b100 && funct7 == 0b0000001
+            => Ok(register(RegisterOp::Div, rd, rs1, rs2)),
+        divu: opcode == 0b0110011 && funct3 == 0b101 && funct7 == 0b0000001
+            => Ok(register(RegisterOp::Divu, rd, rs1, rs2)),
+        rem: opcode == 0b0110011 && funct3 == 0b110 && funct7 == 0b0000001
+            => Ok(register(RegisterOp::Rem, rd, rs1, rs2)),
+        remu: opcode == 0b0110011 && funct3 == 0b111 && funct7 == 0b0000001
+            => Ok(register(RegisterOp::Remu, rd, rs1, rs2)),
+
+        // the fm, rs1 and rd fields are reserved and ignored
+        fence: opcode == 0b0001111 && funct3 == 0 => Ok(Instruction::Fence { pred, succ }),
+        ecall: word == 0x00000073 => Ok(Instruction::Ecall),
+        ebreak: word == 0x00100073 => Ok(Instruction::Ebreak),
+        mret: word == 0x30200073 => Ok(Instruction::Mret),
+        wfi: word == 0x10500073 => Ok(Instruction::Wfi),
+        csrrw(rd, csr, rs1): opcode == 0b1110011 && funct3 == 0b001
+            => Ok(csr_access(CsrOp::Csrrw, rd, rs1, csr)),
+        csrrs(rd, csr, rs1): opcode == 0b1110011 && funct3 == 0b010
+            => Ok(csr_access(CsrOp::Csrrs, rd, rs1, csr)),
+        csrrc(rd, csr, rs1): opcode == 0b1110011 && funct3 == 0b011
+            => Ok(csr_access(CsrOp::Csrrc, rd, rs1, csr)),
+        csrrwi(rd, csr, uimm): opcode == 0b1110011 && funct3 == 0b101
+            => Ok(csr_access(CsrOp::Csrrwi, rd, uimm, csr)),
+        csrrsi(rd, csr, uimm): opcode == 0b1110011 && funct3 == 0b110
+            => Ok(csr_access(CsrOp::Csrrsi, rd, uimm, csr)),
+        csrrci(rd, csr, uimm): opcode == 0b1110011 && funct3 == 0b111
+            => Ok(csr_access(CsrOp::Csrrci, rd, uimm, csr)),
+
+        flw(rd, imm_i, rs1): opcode == 0b0000111 && funct3 == 0b010
+            => Ok(Instruction::Flw { rd, rs1, offset: imm_i }),
+        fsw(rs2, imm_s, rs1): opcode == 0b0100111 && funct3 == 0b010
+            => Ok(Instruction::Fsw { rs1, rs2, offset: imm_s }),
+
+        // rounding modes 5 and 6 are reserved
+        fadd_s: opcode == 0b1010011 && funct7 == 0b0000000 if rounds(rm)
+            => Ok(arithmetic(FloatArithmeticOp::Fadd, rd, rs1, rs2, rm)),
+        fsub_s: opcode == 0b1010011 && funct7 == 0b0000100 if rounds(rm)
+            => Ok(arithmetic(FloatArithmeticOp::Fsub, rd, rs1, rs2, rm)),
+        fmul_s: opcode == 0b1010011 && funct7 == 0b0001000 if rounds(rm)
+            => Ok(arithmetic(FloatArithmeticOp::Fmul, rd, rs1, rs2, rm)),
+        fdiv_s: opcode == 0b1010011 && funct7 == 0b0001100 if rounds(rm)
+            => Ok(arithmetic(FloatArithmeticOp::Fdiv, rd, rs1, rs2, rm)),
+
+        fsgnj_s: opcode == 0b1010011 && funct7 == 0b0010000 && funct3 == 0b000
+            => Ok(binary(FloatBinaryOp::Fsgnj, rd, rs1, rs2)),
+        fsgnjn_s: opcode == 0b1010011 && funct7 == 0b0010000 && funct3 == 0b001
+            => Ok(binary(FloatBinaryOp::Fsgnjn, rd, rs1, rs2)),
+        fsgnjx_s: opcode == 0b1010011 && funct7 == 0b0010000 && funct3 == 0b010
+            => Ok(binary(FloatBinaryOp::Fsgnjx, rd, rs1, rs2)),
+        fmin_s: opcode == 0b1010011 && funct7 == 0b0010100 && funct3 == 0b000
+            => Ok(binary(FloatBinaryOp::Fmin, rd, rs1, rs2)),
+        fmax_s: opcode == 0b1010011 && funct7 == 0b0010100 && funct3 == 0b001
+            => Ok(binary(FloatBinaryOp::Fmax, rd, rs1, rs2)),
+        feq_s: opcode == 0b1010011 && funct7 == 0b1010000 && funct3 == 0b010
+            => Ok(binary(FloatBinaryOp::Feq, rd, rs1, rs2)),
+        flt_s: opcode == 0b1010011 && funct7 == 0b1010000 && funct3 == 0b001
+            => Ok(binary(FloatBinaryOp::Flt, rd, rs1, rs2)),
+        fle_s: opcode == 0b1010011 && funct7 == 0b1010000 && funct3 == 0b000
+            => Ok(binary(FloatBinaryOp::Fle, rd, rs1, rs2)),
+
+        fsqrt_s: opcode == 0b1010011 && funct7 == 0b0101100 && rs2 == 0b00000 if rounds(rm)
+            => Ok(unary(FloatUnaryOp::Fsqrt, rd, rs1, rm)),
+        fcvt_w_s: opcode == 0b1010011 && funct7 == 0b1100000 && rs2 == 0b00000 if rounds(rm)
+            => Ok(unary(FloatUnaryOp::FcvtWS, rd, rs1, rm)),
+        fcvt_wu_s: opcode == 0b1010011 && funct7 == 0b1100000 && rs2 == 0b00001 if rounds(rm)
+            => Ok(unary(FloatUnaryOp::FcvtWuS, rd, rs1, rm)),
+        fcvt_s_w: opcode == 0b1010011 && funct7 == 0b1101000 && rs2 == 0b00000 if rounds(rm)
+            => Ok(unary(FloatUnaryOp::FcvtSW, rd, rs1, rm)),
+        fcvt_s_wu: opcode == 0b1010011 && funct7 == 0b1101000 && rs2 == 0b00001 if rounds(rm)
+            => Ok(unary(FloatUnaryOp::FcvtSWu, rd, rs1, rm)),
+
+        fmv_x_w: opcode == 0b1010011 && funct7 == 0b1110000 && funct3 == 0b000 && rs2 == 0
+            => Ok(bits(FloatBitsOp::FmvXW, rd, rs1)),
+        fclass_s: opcode == 0b1010011 && funct7 == 0b1110000 && funct3 == 0b001 && rs2 == 0
+            => Ok(bits(FloatBitsOp::Fclass, rd, rs1)),
+        fmv_w_x: opcode == 0b1010011 && funct7 == 0b1111000 && funct3 == 0b000 && rs2 == 0
+            => Ok(bits(FloatBitsOp::FmvWX, rd, rs1)),
+
+        fmadd_s: opcode == 0b1000011 && fmt == 0 if rounds(rm)
+            => Ok(fused(FusedOp::Fmadd, rd, rs1, rs2, rs3, rm)),
+        fmsub_s: opcode == 0b1000111 && fmt == 0 if rounds(rm)
+            => Ok(fused(FusedOp::Fmsub, rd, rs1, rs2, rs3, rm)),
+        fnmsub_s: opcode == 0b1001011 && fmt == 0 if rounds(rm)
+            => Ok(fused(FusedOp::Fnmsub, rd, rs1, rs2, rs3, rm)),
+        fnmadd_s: opcode == 0b1001111 && fmt == 0 if rounds(rm)
+            => Ok(fused(FusedOp::Fnmadd, rd, rs1, rs2, rs3, rm)),
+
+        _ => Err(DecodeError { instruction: word }),
+    }
+}
+
+fn rounds(rm: u8) -> bool {
+    rm < 0b101 || rm == DYNAMIC_ROUNDING
+}
+
+fn register(op: RegisterOp, rd: u8, rs1: u8, rs2: u8) -> Instruction {
+    Instruction::Register { op, rd, rs1, rs2 }
+}
+
+fn immediate(op: ImmediateOp, rd: u8, rs1: u8, imm: i32) -> Instruction {
+    Instruction::Immediate { op, rd, rs1, imm }
+}
+
+fn shift(op: ShiftOp, rd: u8, rs1: u8, shamt: u8) -> Instruction {
+    Instruction::Shift { op, rd, rs1, shamt }
+}
+
+fn load(op: LoadOp, rd: u8, rs1: u8, offset: i32) -> Instruction {
+    Instruction::Load {
+        op,
+        rd,
+        rs1,
+        offset,
+    }
+}
+
+fn store(op: StoreOp, rs1: u8, rs2: u8, offset: i32) -> Instruction {
+    Instruction::Store {
+        op,
+        rs1,
+        rs2,
+        offset,
+    }
+}
+
+fn branch(op: BranchOp, rs1: u8, rs2: u8, offset: i32) -> Instruction {
+    Instruction::Branch {
+        op,
+        rs1,
+        rs2,
+        offset,
+    }
+}
+
+fn csr_access(op: CsrOp, rd: u8, source: u8, csr: u16) -> Instruction {
+    Instruction::Csr {
+        op,
+        rd,
+        source,
+        csr,
+    }
+}
+
+fn arithmetic(op: FloatArithmeticOp, rd: u8, rs1: u8, rs2: u8, rm: u8) -> Instruction {
+    Instruction::FloatArithmetic {
+        op,
+        rd,
+        rs1,
+        rs2,
+        rm,
+    }
+}
+
+fn binary(op: FloatBinaryOp, rd: u8, rs1: u8, rs2: u8) -> Instruction {
+    Instruction::FloatBinary { op, rd, rs1, rs2 }
+}
+
+fn unary(op: FloatUnaryOp, rd: u8, rs1: u8, rm: u8) -> Instruction {
+    Instruction::FloatUnary { op, rd, rs1, rm }
+}
+
+fn bits(op: FloatBitsOp, rd: u8, rs1: u8) -> Instruction {
+    Instruction::FloatBits { op, rd, rs1 }
+}
+
+fn fused(op: FusedOp, rd: u8, rs1: u8, rs2: u8, rs3: u8, rm: u8) -> Instruction {
+    Instruction::FloatFused {
+        op,
+        rd,
+        rs1,
+        rs2,
+        rs3,
+        rm,
+    }
+}
+
+impl Instruction {
+    pub fn decode(word: u32) -> Result<Instruction, DecodeError> {
+        decode_word(word)
+    }
+
+    /// Assembles the instruction with its entry in `ENCODINGS`, panics if an
+    /// operand does not fit its field.
+    pub fn encode(&self) -> u32 {
+        let mnemonic = self.mnemonic();
+        let encoding = ENCODINGS
+            .iter()
+            .find(|encoding| encoding.mnemonic == mnemonic)
+            .expect("every instruction has an encoding");
+        let fields = self.fields();
+        let word = encoding
+            .operands
+            .iter()
+            .fold(encoding.bits, |word, operand| {
+                let value = fields
+                    .iter()
+                    .find(|(name, _)| *name == operand.name)
+                    .map(|&(_, value)| value)
+                    .expect("the encoding's operands are fields of the instruction");
+                operand
+                    .insert(word, value)
+                    .unwrap_or_else(|| panic!("`{}` of {:?} does not fit", operand.name, self))
+            });
+        word as u32
+    }
+
+    /// The integer register the instruction writes, if any. Writes to x0 are
+    /// discarded and not reported.
+    pub fn integer_destination(&self) -> Option<usize> {
+        match self.destination() {
+            Some((rd, false)) if rd != 0 => Some(rd as usize),
+            _ => None,
+        }
+    }
+
+    /// The floating point register the instruction writes, if any.
+    pub fn float_destination(&self) -> Option<usize> {
+        match self.destination() {
+            Some((rd, true)) => Some(rd as usize),
+            _ => None,
+        }
+    }
+
+    // rd and whether it is in the floating point register file
+    fn destination(&self) -> Option<(u8, bool)> {
+        match *self {
+            Instruction::Register { rd, .. }
+            | Instruction::Immediate { rd, .. }
+            | Instruction::Shift { rd, .. }
+            | Instruction::Load { rd, .. }
+            | Instruction::Lui { rd, .. }
+            | Instruction::Auipc { rd, .. }
+            | Instruction::Jal { rd, .. }
+            | Instruction::Jalr { rd, .. }
+            | Instruction::Csr { rd, .. } => Some((rd, false)),
+            Instruction::Flw { rd, .. }
+            | Instruction::FloatArithmetic { rd, .. }
+            | Instruction::FloatFused { rd, .. } => Some((rd, true)),
+            Instruction::FloatBinary { op, rd, .. } => Some((rd, !op.integer_destination())),
+            Instruction::FloatUnary { op, rd, .. } => Some((rd, !op.integer_destination())),
+            Instruction::FloatBits { op, rd, .. } => Some((rd, !op.integer_destination())),
+            Instruction::Store { .. }
+            | Instruction::Branch { .. }
+            | Instruction::Fence { .. }
+            | Instruction::Ecall
+            | Instruction::Ebreak
+            | Instruction::Mret
+            | Instruction::Wfi
+            | Instruction::Fsw { .. } => None,
+        }
+    }
+
+    // The value of each field the instruction has, by its name in `vm!`
+    fn fields(&self) -> Vec<(&'static str, i64)> {
+        match *self {
+            Instruction::Register { rd, rs1, rs2, .. }
+            | Instruction::FloatBinary { rd, rs1, rs2, .. } => {
+                vec![("rd", rd as i64), ("rs1", rs1 as i64), ("rs2", rs2 as i64)]
+            }
+            Instruction::Immediate { rd, rs1, imm, .. } => {
+                vec![
+                    ("rd", rd as i64),
+                    ("rs1", rs1 as i64),
+                    ("imm_i", imm as i64),
+                ]
+            }
+            Instruction::Shift { rd, rs1, shamt, .. } => {
+                vec![
+                    ("rd", rd as i64),
+                    ("rs1", rs1 as i64),
+                    ("shamt", shamt as i64),
+                ]
+            }
+            Instruction::Load {
+                rd, rs1, offset, ..
+            }
+            | Instruction::Jalr { rd, rs1, offset }
+            | Instruction::Flw { rd, rs1, offset } => {
+                vec![
+                    ("rd", rd as i64),
+                    ("rs1", rs1 as i64),
+                    ("imm_i", offset as i64),
+                ]
+            }
+            Instruction::Store {
+                rs1, rs2, offset, ..
+            }
+            | Instruction::Fsw { rs1, rs2, offset } => {
+                vec![
+                    ("rs1", rs1 as i64),
+                    ("rs2", rs2 as i64),
+                    ("imm_s", offset as i64),
+                ]
+            }
+            Instruction::Branch {
+                rs1, rs2, offset, ..
+            } => vec![
+                ("rs1", rs1 as i64),
+                ("rs2", rs2 as i64),
+                ("imm_b", offset as i64),
+            ],
+            Instruction::Lui { rd, imm } | Instruction::Auipc { rd, imm } => {
+                vec![("rd", rd as i64), ("imm_u", imm as i64)]
+            }
+            Instruction::Jal { rd, offset } => vec![("rd", rd as i64), ("imm_j", offset as i64)],
+            Instruction::Fence { pred, succ } => vec![("pred", pred as i64), ("succ", succ as i64)],
+            Instruction::Ecall | Instruction::Ebreak | Instruction::Mret | Instruction::Wfi => {
+                vec![]
+            }
+            // the register forms name the source rs1, the immediate forms uimm
+            Instruction::Csr {
+                rd, source, csr, ..
+            } => vec![
+                ("rd", rd as i64),
+                ("rs1", source as i64),
+                ("uimm", source as i64),
+                ("csr", csr as i64),
+            ],
+            Instruction::FloatArithmetic {
+                rd, rs1, rs2, rm, ..
+            } => vec![
+                ("rd", rd as i64),
+                ("rs1", rs1 as i64),
+                ("rs2", rs2 as i64),
+                ("rm", rm as i64),
+            ],
+            Instruction::FloatUnary { rd, rs1, rm, .. } => {
+                vec![("rd", rd as i64), ("rs1", rs1 as i64), ("rm", rm as i64)]
+            }
+            Instruction::FloatBits { rd, rs1, .. } => vec![("rd", rd as i64), ("rs1", rs1 as i64)],
+            Instruction::FloatFused {
+                rd,
+                rs1,
+                rs2,
+                rs3,
+                rm,
+                ..
+            } => vec![
+                ("rd", rd as i64),
+                ("rs1", rs1 as i64),
+                ("rs2", rs2 as i64),
+                ("rs3", rs3 as i64),
+                ("rm", rm as i64),
+            ],
+        }
+    }
+
+    /// The integer operands as `(rd, rs1, rs2, imm)`, where `imm` is the
+    /// sign-extended immediate, offset, shift amount or upper immediate. The
+    /// fields an instruction lacks, and all of those of the floating point
+    /// and system instructions, are zero.
+    pub(crate) fn operands(&self) -> (u8, u8, u8, i32) {
+        match *self {
+            Instruction::Register { rd, rs1, rs2, .. } => (rd, rs1, rs2, 0),
+            Instruction::Immediate { rd, rs1, imm, .. } => (rd, rs1, 0, imm),
+            Instruction::Shift { rd, rs1, shamt, .. } => (rd, rs1, 0, shamt as i32),
+            Instruction::Load {
+                rd, rs1, offset, ..
+            }
+            | Instruction::Jalr { rd, rs1, offset } => (rd, rs1, 0, offset),
+            Instruction::Store {
+                rs1, rs2, offset, ..
+            }
+            | Instruction::Branch {
+                rs1, rs2, offset, ..
+            } => (0, rs1, rs2, offset),
+            Instruction::Jal { rd, offset } => (rd, 0, 0, offset),
+            Instruction::Lui { rd, imm } | Instruction::Auipc { rd, imm } => (rd, 0, 0, imm as i32),
+            _ => (0, 0, 0, 0),
+        }
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        match *self {
+            Instruction::Register { op, .. } => op.mnemonic(),
+            Instruction::Immediate { op, .. } => op.mnemonic(),
+            Instruction::Shift { op, .. } => op.mnemonic(),
+            Instruction::Load { op, .. } => op.mnemonic(),
+            Instruction::Store { op, .. } => op.mnemonic(),
+            Instruction::Branch { op, .. } => op.mnemonic(),
+            Instruction::Lui { .. } => "lui",
+            Instruction::Auipc { .. } => "auipc",
+            Instruction::Jal { .. } => "jal",
+            Instruction::Jalr { .. } => "jalr",
+            Instruction::Fence { .. } => "fence",
+            Instruction::Ecall => "ecall",
+            Instruction::Ebreak => "ebreak",
+            Instruction::Mret => "mret",
+            Instruction::Wfi => "wfi",
+            Instruction::Csr { op, .. } => op.mnemonic(),
+            Instruction::Flw { .. } => "flw",
+            Instruction::Fsw { .. } => "fsw",
+            Instruction::FloatArithmetic { op, .. } => op.mnemonic(),
+            Instruction::FloatBinary { op, .. } => op.mnemonic(),
+            Instruction::FloatUnary { op, .. } => op.mnemonic(),
+            Instruction::FloatBits { op, .. } => op.mnemonic(),
+            Instruction::FloatFused { op, .. } => op.mnemonic(),
+        }
+    }
+}
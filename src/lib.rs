@@ -1,13 +1,53 @@
 use std::fs::File;
 use std::io::{self, Read};
-use std::ops::{Index, IndexMut};
+use std::num::FpCategory;
 
 use hardware::GPIOState;
 
 mod abi;
 mod hardware;
 pub mod assembler;
-mod riscv_spec;
+mod bus;
+mod bytecode;
+pub mod compliance;
+pub mod csr;
+mod decode_cache;
+mod devices;
+mod disassembler;
+mod elf;
+mod float;
+mod htif;
+pub mod instruction;
+mod memory;
+mod outcome;
+pub mod rars;
+pub mod syscall;
+pub mod trace;
+mod trap;
+
+use csr::{MCAUSE_INTERRUPT, MSTATUS_MIE};
+use float::RoundingMode;
+use instruction::{
+    BranchOp, CsrOp, FloatArithmeticOp, FloatBinaryOp, FloatBitsOp, FloatUnaryOp, FusedOp,
+    ImmediateOp, LoadOp, RegisterOp, ShiftOp, StoreOp, DYNAMIC_ROUNDING,
+};
+use trace::{InstructionFields, RegisterWrite};
+
+pub use bus::{Bus, Device, DeviceClone};
+pub use bytecode::{FusionCounts, Translation};
+pub use csr::CSRFile;
+pub use decode_cache::DecodeCache;
+pub use devices::{Clint, Uart};
+pub use disassembler::{disassemble, disassemble_at, DecodedLine};
+pub use elf::{Elf, ElfError, Segment};
+pub use htif::Htif;
+pub use instruction::{DecodeError, Instruction};
+pub use memory::{Memory, MemoryConfig, MemoryTooLarge, Rom};
+pub use outcome::{RunOutcome, RunResult};
+pub use rars::RarsSyscalls;
+pub use syscall::{LinuxSyscalls, SyscallAction, SyscallHandler};
+pub use trace::{JsonTracer, NullTracer, SpikeTracer, TextTracer, TraceRecord, Tracer};
+pub use trap::Trap;
 type SizeInt = u32;
 type RegisterValue = SizeInt;
 
@@ -15,757 +55,991 @@ const NUM_REGISTERS: usize = 32;
 const MEM_SIZE_WORDS: usize = 1024;
 const NUM_GPIOS: usize = 31;
 
+const SIGN_BIT: u32 = 1 << 31;
+const CANONICAL_NAN: u32 = 0x7FC0_0000;
+
+// The one-hot class of FCLASS.S, from bit 0 for negative infinity to bit 9
+// for a quiet NaN
 fn mask_generate(value: f32) -> u32 {
-    match value {
-        // negative infinity
-        x if x.is_infinite() && x.is_sign_negative() => return 0x00_00_00_00,
-        // negative normal number
-        x if x.is_normal() && x.is_sign_negative() => return 0x80_00_00_00,
-        // negative sub normal number
-        x if x.is_subnormal() && x.is_sign_negative() => return 0x80_00_00_00,
-        // negative zero
-        x if (x == 0.0) && x.is_sign_negative() => return 0x80_00_00_00,
-        // positive 0
-        x if (x == 0.0) && x.is_sign_positive() => return 0x00_00_00_00,
-        // positive sub normal number
-        x if x.is_subnormal() && x.is_sign_positive() => return 0x00_00_00_00,
-        // positive infinity
-        x if x.is_infinite() && x.is_sign_positive() => return 0x00_00_00_00,
-        // signaling NaN
-        x if x.is_nan() && x.is_sign_positive() => return 0x00_00_00_00,
-        // quiet NaN
-        x if x.is_nan() && x.is_sign_positive() => return 0x00_00_00_00,
-        _ => panic!("Unknown value: {}", value),
-    }
-}
-
-#[derive(Debug, Clone, Copy)]
-pub struct CPUMem {
-    e: [u32; MEM_SIZE_WORDS],
-}
-
-impl Index<usize> for CPUMem {
-    type Output = u32;
-    fn index<'a>(&'a self, i: usize) -> &'a u32 {
-        if i % 4 == 0 {
-            &self.e[i / 4 as usize]
-        } else {
-            // TODO fix
-            &self.e[i / 4 as usize]
-        }
-    }
+    let negative = value.is_sign_negative();
+    let class = match value.classify() {
+        FpCategory::Nan if value.to_bits() & (1 << 22) == 0 => 8,
+        FpCategory::Nan => 9,
+        FpCategory::Infinite if negative => 0,
+        FpCategory::Normal if negative => 1,
+        FpCategory::Subnormal if negative => 2,
+        FpCategory::Zero if negative => 3,
+        FpCategory::Zero => 4,
+        FpCategory::Subnormal => 5,
+        FpCategory::Normal => 6,
+        FpCategory::Infinite => 7,
+    };
+    1 << class
 }
 
-impl IndexMut<usize> for CPUMem {
-    fn index_mut<'a>(&'a mut self, i: usize) -> &'a mut u32 {
-        &mut self.e[i / 4]
+// The rounding mode an rm field selects, the dynamic mode reading frm from
+// `fcsr`. `None` for the reserved modes, which make the instruction illegal.
+fn rounding_mode(rm: u8, fcsr: u32) -> Option<RoundingMode> {
+    if rm == DYNAMIC_ROUNDING {
+        RoundingMode::from_bits((fcsr >> 5) & 0b111)
+    } else {
+        RoundingMode::from_bits(rm as u32)
     }
 }
 
-impl CPUMem {
-    fn new() -> Self {
-        CPUMem {
-            e: [0; MEM_SIZE_WORDS],
-        }
+// FMIN.S and FMAX.S return the other operand for one NaN and order -0 below
+// +0
+fn float_min_max(a: f32, b: f32, max: bool) -> f32 {
+    match (a.is_nan(), b.is_nan()) {
+        (true, true) => f32::from_bits(CANONICAL_NAN),
+        (true, false) => b,
+        (false, true) => a,
+        _ if a == b && max => f32::from_bits(a.to_bits() & b.to_bits()),
+        _ if a == b => f32::from_bits(a.to_bits() | b.to_bits()),
+        _ if max => a.max(b),
+        _ => a.min(b),
     }
 }
 
-#[derive(Clone)]
 pub struct CPUState {
     pub registers: [RegisterValue; NUM_REGISTERS],
     pub floating_point_registers: [f32; NUM_REGISTERS],
-    // pub memory: CPUMem,
-    pub memory_bytes: [u8; MEM_SIZE_WORDS * 4],
+    pub bus: Bus,
     pub pc: u32,
     pub gpio_states: [GPIOState; NUM_GPIOS],
+    pub csrs: CSRFile,
+    /// Deliver synchronous traps to the guest handler in mtvec instead of
+    /// returning them to the embedder.
+    pub vector_traps: bool,
+    /// Set by WFI, execution stalls until an enabled interrupt is pending.
+    pub waiting_for_interrupt: bool,
+    /// Services ecall in host mode, without one a7 == 10 ends the program.
+    pub syscall_handler: Option<Box<dyn SyscallHandler>>,
+    /// Receives a record of every executed instruction when set.
+    pub tracer: Option<Box<dyn Tracer>>,
+    /// How instructions are executed.
+    pub engine: Engine,
+    /// Set once an `Htif` is mapped, its requests are then checked after
+    /// every step.
+    htif_attached: bool,
+}
+
+/// The ways `CPUState::step` can execute instructions, they all leave the
+/// hart in the same architectural state.
+#[derive(Clone)]
+pub enum Engine {
+    /// Fetches and decodes every instruction.
+    Interpreter,
+    /// Executes instructions decoded on first use.
+    DecodeCache(DecodeCache),
+    /// Executes a bytecode translation of a region of memory, instructions
+    /// outside it are interpreted.
+    Bytecode(Translation),
+}
+
+/// Copies the hart along with its bus and engine. The syscall handler and
+/// tracer belong to the host rather than the hart, the copy starts without
+/// them.
+impl Clone for CPUState {
+    fn clone(&self) -> Self {
+        CPUState {
+            registers: self.registers,
+            floating_point_registers: self.floating_point_registers,
+            bus: self.bus.clone(),
+            pc: self.pc,
+            gpio_states: self.gpio_states,
+            csrs: self.csrs,
+            vector_traps: self.vector_traps,
+            waiting_for_interrupt: self.waiting_for_interrupt,
+            syscall_handler: None,
+            tracer: None,
+            engine: self.engine.clone(),
+            htif_attached: self.htif_attached,
+        }
+    }
+}
+
+impl Default for CPUState {
+    fn default() -> Self {
+        CPUState::new()
+    }
 }
 
 impl CPUState {
     pub fn new() -> Self {
+        CPUState::with_memory(MemoryConfig::default()).unwrap()
+    }
+
+    /// Creates a hart with `config.size` bytes of RAM at `config.base`, the pc
+    /// starts at the base of RAM.
+    pub fn with_memory(config: MemoryConfig) -> Result<Self, MemoryTooLarge> {
+        let mut bus = Bus::new();
+        bus.map(config.base, Memory::new(config.size)?);
+        Ok(CPUState::with_bus(bus, config.base))
+    }
+
+    /// Creates a hart attached to a bus the embedder has already populated.
+    pub fn with_bus(bus: Bus, pc: u32) -> Self {
         CPUState {
             registers: [0; NUM_REGISTERS],
             floating_point_registers: [0.0; NUM_REGISTERS],
-            // memory: CPUMem::new(),
-            memory_bytes: [0; MEM_SIZE_WORDS * 4],
-            pc: 0,
+            bus,
+            pc,
             gpio_states: [GPIOState::new(); NUM_GPIOS],
+            csrs: CSRFile::new(),
+            vector_traps: false,
+            waiting_for_interrupt: false,
+            syscall_handler: None,
+            tracer: None,
+            engine: Engine::Interpreter,
+            htif_attached: false,
         }
     }
 
-    pub fn set_mem(&mut self, address: usize, value: u32) {
-        self.memory_bytes[address as usize] = (value & 0xFF) as u8;
-        self.memory_bytes[(address + 1) as usize] = ((value >> 8) & 0xFF) as u8;
-        self.memory_bytes[(address + 2) as usize] = ((value >> 16) & 0xFF) as u8;
-        self.memory_bytes[(address + 3) as usize] = ((value >> 24) & 0xFF) as u8;
-    }
-
-    pub fn read_mem(&mut self, address: usize) -> u32 {
-        let mut value = 0;
-        value += (self.memory_bytes[address as usize] as u32) << 0;
-        value += (self.memory_bytes[(address + 1) as usize] as u32) << 8;
-        value += (self.memory_bytes[(address + 2) as usize] as u32) << 16;
-        value += (self.memory_bytes[(address + 3) as usize] as u32) << 24;
-        return value;
-    }
-}
-
-pub fn decode_instruction(cpu_state: &mut CPUState) -> bool {
-    let instruction = cpu_state.read_mem(cpu_state.pc as usize);
-    println!("instruction: {:#34b}", instruction);
-    // let mut cpu_state.registers = cpu_state.registers;
-    // let mut cpu_state.floating_point_registers = cpu_state.floating_point_registers;
-    let pc = &mut cpu_state.pc;
-    // let mut memory = cpu_state.memory;
-
-    let opcode = instruction & 0b00000000000000000000000001111111;
-    let rd = (instruction >> 7) & 0b00000000000000000000000000011111;
-    let rs1 = (instruction >> 15) & 0b00000000000000000000000000011111;
-    let rs2 = (instruction >> 20) & 0b00000000000000000000000000011111;
-
-    let funct7 = (instruction >> 25) & 0b1111111;
-    let funct3 = (instruction >> 12) & 0b111;
-
-    let imm_i = (instruction as u32) >> 20;
-    let imm_l = (instruction as u32) >> 20;
-    let imm_u = (instruction as i32) >> 12;
-    let imm_s = ((instruction >> 25) << 11) | ((instruction >> 7) & 0b11111);
-    let imm_b = ((((instruction >> 31) & 1) << 12)
-        | (((instruction >> 25) & 0b111111) << 5)
-        | (((instruction >> 8) & 0b1111) << 1)
-        | ((instruction >> 7) & 0b1) << 11)
-        & 0b11111111111111111111111111111110;
-
-    let imm_j = (((instruction >> 31) << 20)
-        | (((instruction >> 21) & 0b0111111111) << 1)
-        | (((instruction >> 19) & 0b1) << 11)
-        | (((instruction >> 12) & 0b11111111) << 12))
-        & 0b11111111111111111111111111111110;
-
-    println!("instruction: {:b}", instruction);
-    println!("opcode: {:b}", opcode);
-    println!("rd: {:b}", rd);
-    println!("funct3: {:b}", funct3);
-    println!("funct7: {:b}", funct7);
-    match opcode {
-        ///////////////////////////////////////////// RV32I Base Instruction Set /////////////////////////////////////////////
-        ///////////////////////////////////////////// RV32M Standard Extension //////////////////////////////////////////////
-        // Arithmetic Int Instructions
-        0b0110011 => {
-            println!("in 0110011");
-            match funct7 {
-                0b0000000 => match funct3 {
-                    0b111 => {
-                        cpu_state.registers[rd as usize] =
-                            cpu_state.registers[rs1 as usize] & cpu_state.registers[rs2 as usize]
-                    } // AND
-                    0b110 => {
-                        cpu_state.registers[rd as usize] =
-                            cpu_state.registers[rs1 as usize] | cpu_state.registers[rs2 as usize]
-                    } // OR
-                    0b100 => {
-                        cpu_state.registers[rd as usize] =
-                            cpu_state.registers[rs1 as usize] ^ cpu_state.registers[rs2 as usize]
-                    } // XOR
-                    0b011 => {
-                        println!("rs2 val: {}", cpu_state.registers[rs2 as usize]);
-                        cpu_state.registers[rd as usize] = if cpu_state.registers[rs1 as usize]
-                            < cpu_state.registers[rs2 as usize]
-                        {
-                            1
-                        } else {
-                            0
-                        };
-                    } // SLTU
-                    0b010 => {
-                        cpu_state.registers[rd as usize] = {
-                            if (cpu_state.registers[rs1 as usize] as i32)
-                                < (cpu_state.registers[rs2 as usize] as i32)
-                            {
-                                1
-                            } else {
-                                0
-                            } // SLT
-                        }
-                    }
-                    0b001 => {
-                        cpu_state.registers[rd as usize] =
-                            cpu_state.registers[rs1 as usize] << cpu_state.registers[rs2 as usize]
-                    } // SLL
-                    0b101 => {
-                        cpu_state.registers[rd as usize] =
-                            cpu_state.registers[rs1 as usize] >> cpu_state.registers[rs2 as usize]
-                    } // SRL
-                    0b000 => {
-                        cpu_state.registers[rd as usize] =
-                            cpu_state.registers[rs1 as usize] + cpu_state.registers[rs2 as usize]
-                    } // ADD
-                    _ => panic!("Unknown funct3: {}", funct3),
-                },
-                0b0100000 => match funct3 {
-                    0b000 => {
-                        println!("rd: {}", rd);
-                        println!("rs1: {}", rd);
-                        println!("rs2: {}", rd);
-                        cpu_state.registers[rd as usize] =
-                            cpu_state.registers[rs1 as usize] - cpu_state.registers[rs2 as usize];
-                        // SUB
-                    }
-                    0b101 => {
-                        cpu_state.registers[rd as usize] =
-                            cpu_state.registers[rs1 as usize] >> cpu_state.registers[rs2 as usize]
-                    } // SRA
-                    _ => panic!("Unknown funct3: {}", funct3),
-                },
-                0b0000001 => match funct3 {
-                    0b000 => {
-                        cpu_state.registers[rd as usize] =
-                            cpu_state.registers[rs1 as usize] * cpu_state.registers[rs2 as usize]
-                    } // MUL
-                    0b001 => {
-                        let result = (cpu_state.registers[rs1 as usize] as i32 as i64)
-                            * (cpu_state.registers[rs2 as usize] as i32 as i64);
-                        let upper_result = (result >> 32) as u32;
-                        cpu_state.registers[rd as usize] = upper_result;
-                    } // MULH
-                    0b010 => {
-                        let result = (cpu_state.registers[rs1 as usize] as i32 as i64)
-                            * (cpu_state.registers[rs2 as usize] as u32 as i64);
-                        let upper_result = (result >> 32) as u32;
-                        cpu_state.registers[rd as usize] = upper_result;
-                    } // MULHSU
-                    0b011 => {
-                        let result = (cpu_state.registers[rs1 as usize] as u32 as u64)
-                            * (cpu_state.registers[rs2 as usize] as u32 as u64);
-                        let upper_result = (result >> 32) as u32;
-                        cpu_state.registers[rd as usize] = upper_result;
-                    } // MULHU
-                    0b100 => {
-                        cpu_state.registers[rd as usize] = ((cpu_state.registers[rs1 as usize]
-                            as i32)
-                            / (cpu_state.registers[rs2 as usize] as i32))
-                            as u32
-                    } // DIV
-                    0b101 => {
-                        cpu_state.registers[rd as usize] =
-                            cpu_state.registers[rs1 as usize] / cpu_state.registers[rs2 as usize]
-                    } // DIVU
-                    0b110 => {
-                        cpu_state.registers[rd as usize] = ((cpu_state.registers[rs1 as usize]
-                            as i32)
-                            % (cpu_state.registers[rs2 as usize] as i32))
-                            as u32
-                    } // REM
-                    0b111 => {
-                        cpu_state.registers[rd as usize] =
-                            cpu_state.registers[rs1 as usize] % cpu_state.registers[rs2 as usize]
-                    } // REMU
-                    _ => panic!("Unknown funct3: {}", funct3),
-                },
-                _ => panic!("Unknown funct7: {}", funct7),
+    pub fn set_mem(&mut self, address: u32, value: u32) -> Option<()> {
+        self.bus.write(address, 4, value)
+    }
+
+    pub fn read_mem(&mut self, address: u32) -> Option<u32> {
+        self.bus.read(address, 4)
+    }
+
+    /// Reads `len` bytes of guest memory starting at `address`.
+    pub fn read_bytes(&mut self, address: u32, len: usize) -> Option<Vec<u8>> {
+        (0..len as u32)
+            .map(|i| {
+                self.bus
+                    .read(address.wrapping_add(i), 1)
+                    .map(|byte| byte as u8)
+            })
+            .collect()
+    }
+
+    pub fn write_bytes(&mut self, address: u32, bytes: &[u8]) -> Option<()> {
+        for (i, byte) in bytes.iter().enumerate() {
+            self.bus
+                .write(address.wrapping_add(i as u32), 1, *byte as u32)?;
+        }
+        Some(())
+    }
+
+    /// Copies a little-endian program into memory at the current pc.
+    pub fn load(&mut self, program: &[u8]) -> Option<()> {
+        if program.is_empty() {
+            return Some(());
+        }
+        self.bus.load(self.pc, program)
+    }
+
+    /// Executes a single instruction, or a single stalled cycle while waiting
+    /// for an interrupt. Returns the outcome if execution cannot continue.
+    pub fn step(&mut self) -> Option<RunOutcome> {
+        self.step_with(false).0
+    }
+
+    /// Like `step`, but with `fuse` set a superinstruction may execute two
+    /// instructions. Returns the cycles taken as well.
+    fn step_with(&mut self, fuse: bool) -> (Option<RunOutcome>, u64) {
+        let mut engine = std::mem::replace(&mut self.engine, Engine::Interpreter);
+        let mut cycles = 1;
+        let result = match &mut engine {
+            Engine::Interpreter => decode_instruction(self),
+            Engine::DecodeCache(cache) => run_cycle(self, |cpu_state| cache.execute(cpu_state)),
+            Engine::Bytecode(translation) => {
+                let fused = translation.fusion_counts().total();
+                let result = run_cycle(self, |cpu_state| translation.execute(cpu_state, fuse));
+                cycles += translation.fusion_counts().total() - fused;
+                result
+            }
+        };
+        self.engine = engine;
+        (self.finish_step(result), cycles)
+    }
+
+    fn finish_step(&mut self, result: Result<(), Trap>) -> Option<RunOutcome> {
+        if self.htif_attached {
+            if let Some(outcome) = htif::service(self) {
+                return Some(outcome);
             }
         }
-        0b0010011 => {
-            let funct3: u32 = (instruction >> 12) & 0b00000000000000000000000000001111;
-            match funct3 {
-                001 => cpu_state.registers[rd as usize] = cpu_state.registers[rs1 as usize] << rs2, // SLLI
-                101 => match funct7 {
-                    0b0000000 => {
-                        cpu_state.registers[rd as usize] = cpu_state.registers[rs1 as usize] >> rs2
-                    } // SRLI
-                    0b0100000 => {
-                        cpu_state.registers[rd as usize] =
-                            ((cpu_state.registers[rs1 as usize] as i32) >> rs2) as u32
-                    } // SRAI
-                    _ => panic!("Unknown funct3: {}", funct3),
-                },
-                0b111 => {
-                    cpu_state.registers[rd as usize] = cpu_state.registers[rs1 as usize] & imm_i
-                } // ANDI
-                0b110 => {
-                    cpu_state.registers[rd as usize] = cpu_state.registers[rs1 as usize] | imm_i
-                } // ORI
-                0b100 => {
-                    cpu_state.registers[rd as usize] = cpu_state.registers[rs1 as usize] ^ imm_i
-                } // XORI
-                0b011 => {
-                    cpu_state.registers[rd as usize] = if cpu_state.registers[rs1 as usize] < imm_i
-                    {
-                        1
-                    } else {
-                        0
-                    }
-                } // SLTIU
-                0b010 => {
-                    cpu_state.registers[rd as usize] = {
-                        if (cpu_state.registers[rs1 as usize] as i32) < (imm_i as i32) {
-                            1
-                        } else {
-                            0
-                        } // SLTI
-                    }
+        match result {
+            Ok(()) => None,
+            Err(trap @ Trap::EnvironmentCall { .. }) => {
+                self.pc = self.pc.wrapping_add(4);
+                let action = self.handle_syscall();
+                if action != SyscallAction::Unhandled {
+                    // serviced by the host, so the ecall retires
+                    self.csrs.instret = self.csrs.instret.wrapping_add(1);
                 }
-                0b000 => {
-                    println!("rd: {}", rd);
-                    println!("rs1: {}", rs1);
-                    println!("imm_i: {}", imm_i);
-                    println!("result: {}", cpu_state.registers[rd as usize]);
-                    cpu_state.registers[rd as usize] = cpu_state.registers[rs1 as usize] + imm_i; // ADDI
-                    println!("result: {}", cpu_state.registers[rd as usize]);
+                match action {
+                    SyscallAction::Continue => None,
+                    SyscallAction::Exit(code) => Some(RunOutcome::Exited(code)),
+                    SyscallAction::Unhandled => {
+                        self.pc = trap.pc();
+                        Some(RunOutcome::Trapped(trap))
+                    }
                 }
-                _ => panic!("Unknown funct3: {}", funct3),
             }
+            Err(Trap::Breakpoint { pc, .. }) => Some(RunOutcome::Breakpoint { pc }),
+            Err(trap) => Some(RunOutcome::Trapped(trap)),
         }
-        // Store instructions
-        0b0100011 => {
-            match funct3 {
-                // TODO test sign extension of register value
-                0b010 => cpu_state.set_mem(
-                    ((cpu_state.registers[rs1 as usize] as i32) + (imm_s as i32)) as usize,
-                    cpu_state.registers[rd as usize],
-                ), // SW
-                0b001 => {
-                    let value = (cpu_state.registers[rd as usize]) as u32
-                        & 0x0000FFFF
-                            + cpu_state.read_mem(
-                                ((cpu_state.registers[rs1 as usize] as i32) + (imm_s as i32))
-                                    as usize,
-                            )
-                        & 0xFFFF0000;
-                    cpu_state.set_mem(
-                        ((cpu_state.registers[rs1 as usize] as i32) + (imm_s as i32)) as usize,
-                        value,
-                    );
-                } // SH
-                0b000 => {
-                    let value = (cpu_state.registers[rd as usize]) as u32
-                        & 0x000000FF
-                            + cpu_state.read_mem(
-                                ((cpu_state.registers[rs1 as usize] as i32) + (imm_s as i32))
-                                    as usize,
-                            )
-                        & 0xFFFFFF00;
-                    cpu_state.set_mem(
-                        ((cpu_state.registers[rs1 as usize] as i32) + (imm_s as i32)) as usize,
-                        value,
-                    );
-                } // SB
-                _ => panic!("Unknown funct3: {}", funct3),
+    }
+
+    /// Steps for at most `max_cycles` cycles.
+    pub fn run(&mut self, max_cycles: u64) -> RunResult {
+        self.run_with(Some(max_cycles), true, |_| false)
+    }
+
+    /// Steps until `predicate` returns true, it is checked before every
+    /// cycle so the hart is left just before the instruction that matched.
+    pub fn run_until(&mut self, predicate: impl FnMut(&CPUState) -> bool) -> RunResult {
+        self.run_with(None, false, predicate)
+    }
+
+    /// Superinstructions are only executed with `fuse` set, as `predicate`
+    /// would not see the pc between the two instructions.
+    fn run_with(
+        &mut self,
+        max_cycles: Option<u64>,
+        fuse: bool,
+        mut predicate: impl FnMut(&CPUState) -> bool,
+    ) -> RunResult {
+        let start_instret = self.csrs.instret;
+        let mut cycles = 0;
+        let outcome = loop {
+            if max_cycles.is_some_and(|max_cycles| cycles >= max_cycles) {
+                break RunOutcome::CycleLimit;
+            }
+            if predicate(self) {
+                break RunOutcome::Stopped;
+            }
+            let budget = max_cycles.map_or(u64::MAX, |max_cycles| max_cycles - cycles);
+            let (outcome, step_cycles) = self.step_with(fuse && budget >= 2);
+            cycles += step_cycles;
+            if let Some(outcome) = outcome {
+                break outcome;
             }
+        };
+        RunResult {
+            outcome,
+            instructions: self.csrs.instret.wrapping_sub(start_instret),
+            cycles,
         }
-        // Load Instructions
-        0b0000011 => {
-            match funct3 {
-                0b010 => {
-                    println!(
-                        "lw: {}",
-                        ((cpu_state.registers[rs1 as usize] as i32) + (imm_l as i32)) as usize
-                    );
-                    cpu_state.registers[rd as usize] = cpu_state.read_mem(
-                        ((cpu_state.registers[rs1 as usize] as i32) + (imm_l as i32)) as usize,
-                    );
-                } // LW
-                0b001 => {
-                    let val = cpu_state.read_mem(
-                        ((cpu_state.registers[rs1 as usize] as i32) + (imm_s as i32)) as usize,
-                    ) & 0x0000FFFF;
-                    cpu_state.registers[rd as usize] = val
-                        + if (val & 0x00008000) == 0x00008000 {
-                            0xFFFF0000
-                        } else {
-                            0x00000000
-                        };
-                } // LH
-                0b000 => {
-                    let val = cpu_state.read_mem(
-                        ((cpu_state.registers[rs1 as usize] as i32) + (imm_s as i32)) as usize,
-                    ) & 0x000000FF;
-                    cpu_state.registers[rd as usize] = val
-                        + if (val & 0x00000080) == 0x00000080 {
-                            0xFFFFFF00
-                        } else {
-                            0x00000000
-                        };
-                } // LB
-                0b101 => {
-                    cpu_state.registers[rd as usize] = cpu_state.read_mem(
-                        ((cpu_state.registers[rs1 as usize] as i32) + (imm_l as i32)) as usize,
-                    ) & 0x0000FFFF
-                } // LHU
-                0b100 => {
-                    cpu_state.registers[rd as usize] = cpu_state.read_mem(
-                        ((cpu_state.registers[rs1 as usize] as i32) + (imm_l as i32)) as usize,
-                    ) & 0x000000FF
-                } // LBU
-                _ => panic!("Unknown funct3: {}", funct3),
+    }
+
+    /// Passes the ecall that was just executed to the syscall handler.
+    pub fn handle_syscall(&mut self) -> SyscallAction {
+        match self.syscall_handler.take() {
+            Some(mut handler) => {
+                let action = handler.handle(self);
+                self.syscall_handler = Some(handler);
+                action
+            }
+            None => {
+                if self.registers[17] == 10 {
+                    SyscallAction::Exit(0)
+                } else {
+                    SyscallAction::Continue
+                }
             }
         }
-        // Branch Instructions
-        0b1100011 => {
-            match funct3 {
-                0b000 => {
-                    println!("imm_b: {}", imm_b);
-                    println!("pc: {}", *pc);
-                    if cpu_state.registers[rs1 as usize] == cpu_state.registers[rs2 as usize] {
-                        *pc = (*pc as i32 + ((imm_b as i32) << 0)) as u32;
-                        println!("pc: {}", *pc);
-                        return false;
-                    }
-                } // BEQ
-                0b001 => {
-                    println!("imm_b: {}", imm_b);
-                    println!("pc: {}", *pc);
-                    if cpu_state.registers[rs1 as usize] != cpu_state.registers[rs2 as usize] {
-                        *pc = (*pc as i32 + ((imm_b as i32) << 0)) as u32;
-                        println!("pc: {}", *pc);
-                        return false;
-                    }
-                } // BNE
-                0b100 => {
-                    println!("imm_b: {}", imm_b);
-                    println!("pc: {}", *pc);
-                    if (cpu_state.registers[rs1 as usize] as i32)
-                        < (cpu_state.registers[rs2 as usize] as i32)
-                    {
-                        *pc = (*pc as i32 + ((imm_b as i32) << 0)) as u32;
-                        println!("pc: {}", *pc);
-                        return false;
-                    }
-                } // BLT
-                0b101 => {
-                    println!("imm_b: {}", imm_b);
-                    println!("pc: {}", *pc);
-                    if (cpu_state.registers[rs1 as usize] as i32)
-                        >= (cpu_state.registers[rs2 as usize] as i32)
-                    {
-                        *pc = (*pc as i32 + ((imm_b as i32) << 0)) as u32;
-                        println!("pc: {}", *pc);
-                        return false;
-                    }
-                } // BGE
-                0b110 => {
-                    if cpu_state.registers[rs1 as usize] < cpu_state.registers[rs2 as usize] {
-                        *pc = (*pc as i32 + ((imm_b as i32) << 0)) as u32;
-                        return false;
-                    }
-                } // BLTU
-                0b111 => {
-                    if cpu_state.registers[rs1 as usize] >= cpu_state.registers[rs2 as usize] {
-                        *pc = (*pc as i32 + ((imm_b as i32) << 0)) as u32;
-                        return false;
-                    }
-                } // BGEU
-                _ => panic!("Unknown funct3: {}", funct3),
+    }
+
+    /// Maps `htif` over the memory at its registers, a program that exits
+    /// through it ends the run with `RunOutcome::Exited`.
+    pub fn attach_htif(&mut self, htif: Htif) {
+        htif::attach(self, htif);
+        self.htif_attached = true;
+    }
+
+    /// Enters the trap handler in mtvec as if the trap had been raised by the
+    /// instruction at `trap.pc()`.
+    pub fn take_trap(&mut self, trap: Trap) {
+        self.pc = self.csrs.trap_entry(trap.pc(), trap.cause(), trap.value());
+    }
+
+    /// Reports the instruction at `pc` that just executed to the tracer.
+    fn trace(&mut self, pc: u32, instruction: u32, trap: Option<Trap>) {
+        let fields = InstructionFields::decode(instruction);
+        let mut register_writes = vec![];
+        if let (None, Ok(decoded)) = (trap, Instruction::decode(instruction)) {
+            if let Some(register) = decoded.integer_destination() {
+                register_writes.push(RegisterWrite::Integer {
+                    register,
+                    value: self.registers[register],
+                });
+            }
+            if let Some(register) = decoded.float_destination() {
+                register_writes.push(RegisterWrite::Float {
+                    register,
+                    value: self.floating_point_registers[register],
+                });
             }
         }
+        let record = TraceRecord {
+            pc,
+            instruction,
+            fields,
+            register_writes,
+            memory_accesses: self.bus.stop_recording(),
+            trap,
+        };
+        if let Some(tracer) = &mut self.tracer {
+            tracer.trace(&record);
+        }
+    }
+
+    fn take_interrupt(&mut self, code: u32) {
+        self.pc = self.csrs.trap_entry(self.pc, MCAUSE_INTERRUPT | code, 0);
+    }
+}
+
+fn check_jump_target(pc: u32, target: u32, instruction: u32) -> Result<(), Trap> {
+    if !target.is_multiple_of(4) {
+        return Err(Trap::InstructionAddressMisaligned {
+            pc,
+            instruction,
+            address: target,
+        });
+    }
+    Ok(())
+}
+
+pub fn decode_instruction(cpu_state: &mut CPUState) -> Result<(), Trap> {
+    run_cycle(cpu_state, execute_instruction)
+}
+
+/// Everything around the execution of one instruction: device ticks,
+/// interrupts, tracing and trap vectoring. `execute` runs the instruction at
+/// the pc, either by decoding it or from the decode cache.
+fn run_cycle(
+    cpu_state: &mut CPUState,
+    execute: impl FnOnce(&mut CPUState) -> Result<(), Trap>,
+) -> Result<(), Trap> {
+    tick_devices(cpu_state);
+    if cpu_state.waiting_for_interrupt {
+        if cpu_state.csrs.mip & cpu_state.csrs.mie == 0 {
+            cpu_state.csrs.tick(false);
+            return Ok(());
+        }
+        cpu_state.waiting_for_interrupt = false;
+    }
+    if let Some(code) = cpu_state.csrs.pending_interrupt() {
+        cpu_state.take_interrupt(code);
+    }
+    let pc = cpu_state.pc;
+    let traced_instruction = cpu_state.tracer.is_some().then(|| {
+        cpu_state.bus.start_recording();
+        cpu_state.bus.fetch(pc, 4).unwrap_or(0)
+    });
+    let result = execute(cpu_state);
+    cpu_state.registers[0] = 0;
+    cpu_state.csrs.tick(result.is_ok());
+    if let Some(instruction) = traced_instruction {
+        cpu_state.trace(pc, instruction, result.err());
+    }
+    match result {
+        Err(trap) if cpu_state.vector_traps => {
+            cpu_state.take_trap(trap);
+            Ok(())
+        }
+        _ => result,
+    }
+}
+
+fn tick_devices(cpu_state: &mut CPUState) {
+    // Interrupt lines driven by devices replace the bits they drove last cycle
+    let (previous, asserted) = cpu_state.bus.tick();
+    cpu_state.csrs.mip = (cpu_state.csrs.mip & !previous) | asserted;
+}
+
+/// Ends the cycle of an instruction that executed without trapping and starts
+/// the next one the way `run_cycle` would, so that a superinstruction can
+/// execute its second instruction in the same step. Returns false, leaving
+/// the cycle alone, if an interrupt could be taken or a tracer is set.
+fn chain_cycle(cpu_state: &mut CPUState) -> bool {
+    let interrupts_enabled = cpu_state.csrs.mstatus & MSTATUS_MIE != 0 && cpu_state.csrs.mie != 0;
+    if interrupts_enabled || cpu_state.tracer.is_some() {
+        return false;
+    }
+    cpu_state.registers[0] = 0;
+    cpu_state.csrs.tick(true);
+    tick_devices(cpu_state);
+    true
+}
+
+/// Fetches and decodes the instruction at the pc.
+fn fetch_instruction(cpu_state: &mut CPUState) -> Result<(Instruction, u32), Trap> {
+    let word = cpu_state
+        .bus
+        .fetch(cpu_state.pc, 4)
+        .ok_or(Trap::InstructionAccessFault { pc: cpu_state.pc })?;
+    let instruction = Instruction::decode(word).map_err(|_| Trap::IllegalInstruction {
+        pc: cpu_state.pc,
+        instruction: word,
+    })?;
+    Ok((instruction, word))
+}
+
+fn execute_instruction(cpu_state: &mut CPUState) -> Result<(), Trap> {
+    let (instruction, word) = fetch_instruction(cpu_state)?;
+    execute(cpu_state, instruction, word)
+}
+
+// The integer formats are executed by the functions after `execute`, they are
+// always inlined so that the decode cache and the bytecode can specialise them
+// for each op.
+fn execute(cpu_state: &mut CPUState, instruction: Instruction, word: u32) -> Result<(), Trap> {
+    let illegal = Trap::IllegalInstruction {
+        pc: cpu_state.pc,
+        instruction: word,
+    };
+    let current_pc = cpu_state.pc;
+    let load_fault = move |address| Trap::LoadAccessFault {
+        pc: current_pc,
+        instruction: word,
+        address,
+    };
+    let store_fault = move |address| Trap::StoreAccessFault {
+        pc: current_pc,
+        instruction: word,
+        address,
+    };
+    let registers = &mut cpu_state.registers;
+    let floating_point_registers = &mut cpu_state.floating_point_registers;
+    // the exception flags accrue in the low bits of fcsr
+    let fcsr = &mut cpu_state.csrs.fcsr;
+
+    match instruction {
+        ///////////////////////////////////////////// RV32I Base Instruction Set /////////////////////////////////////////////
+        ///////////////////////////////////////////// RV32M Standard Extension //////////////////////////////////////////////
+        // Arithmetic Int Instructions
+        Instruction::Register { op, rd, rs1, rs2 } => {
+            return execute_register(cpu_state, op, rd, rs1, rs2)
+        }
+        Instruction::Immediate { op, rd, rs1, imm } => {
+            return execute_immediate(cpu_state, op, rd, rs1, imm)
+        }
+        Instruction::Shift { op, rd, rs1, shamt } => {
+            return execute_shift(cpu_state, op, rd, rs1, shamt)
+        }
+        // Store instructions
+        Instruction::Store {
+            op,
+            rs1,
+            rs2,
+            offset,
+        } => return execute_store(cpu_state, op, rs1, rs2, offset, word),
+        // Load Instructions
+        Instruction::Load {
+            op,
+            rd,
+            rs1,
+            offset,
+        } => return execute_load(cpu_state, op, rd, rs1, offset, word),
+        // Branch Instructions
+        Instruction::Branch {
+            op,
+            rs1,
+            rs2,
+            offset,
+        } => return execute_branch(cpu_state, op, rs1, rs2, offset, word),
         // Jump and Link Instructions
-        0b1100111 => {
-            println!("jalr");
-            println!("imm_i: {}", imm_i);
-            println!("rs1: {}", rs1);
-            println!("reg: {}", cpu_state.registers[rs1 as usize]);
-            let jump =
-                ((cpu_state.registers[rs1 as usize] as i32 + imm_i as i32) as u32) & 0xFFFFFFFE;
-            cpu_state.registers[rd as usize] = (*pc + 4) as u32;
-            *pc = jump as u32;
-            return false;
-        } // JALR
-        0b1101111 => {
-            println!("imm_j: {}", imm_j);
-            println!("pc: {}", *pc);
-            cpu_state.registers[rd as usize] = (*pc + 4) as u32;
-            *pc = (*pc as i32 + ((imm_j as i32) << 0)) as u32;
-            println!("pc: {}", *pc);
-            return false;
-        } // JAL
+        Instruction::Jalr { rd, rs1, offset } => {
+            return execute_jalr(cpu_state, rd, rs1, offset, word)
+        }
+        Instruction::Jal { rd, offset } => return execute_jal(cpu_state, rd, offset, word),
 
         // Load or Add Immediate Instructions
-        0b0110111 => cpu_state.registers[rd as usize] = imm_u as u32, // LUI
-        0b0010111 => cpu_state.registers[rd as usize] = (imm_u << 12) as u32 + (*pc as u32), // AUIPC
+        Instruction::Lui { rd, imm } => return execute_lui(cpu_state, rd, imm),
+        Instruction::Auipc { rd, imm } => return execute_auipc(cpu_state, rd, imm),
 
         // Fence Instructions
-        0b0001111 => {} // FENCE
+        Instruction::Fence { .. } => {}
 
         // ECall Instructions
-        0b1110011 => {
-            match funct3 {
-                0b000 => {
-                    println!("ECALL");
-                    match imm_i {
-                        0 => {
-                            println!("ECALL");
-                            return true;
-                        } // ECALL
-                        1 => {} // EBREAK
-                        _ => panic!("Unknown imm_i: {}", imm_i),
-                    }
-                }
-                0b001 => {} // CSRRW
-                0b010 => {} // CSRRS
-                0b011 => {} // CSRRC
-                0b101 => {} // CSRRWI
-                0b110 => {} // CSRRSI
-                0b111 => {} // CSRRCI
-                _ => panic!("Unknown funct3: {}", funct3),
+        Instruction::Ecall => {
+            return Err(Trap::EnvironmentCall {
+                pc: cpu_state.pc,
+                instruction: word,
+            })
+        }
+        Instruction::Ebreak => {
+            return Err(Trap::Breakpoint {
+                pc: cpu_state.pc,
+                instruction: word,
+            })
+        }
+        Instruction::Mret => {
+            cpu_state.pc = cpu_state.csrs.trap_return();
+            return Ok(());
+        }
+        Instruction::Wfi => cpu_state.waiting_for_interrupt = true,
+        Instruction::Csr {
+            op,
+            rd,
+            source,
+            csr,
+        } => {
+            let operand = if op.is_immediate() {
+                source as u32
+            } else {
+                registers[source as usize]
+            };
+            let old_value = cpu_state.csrs.read(csr).ok_or(illegal)?;
+            let new_value = match op {
+                CsrOp::Csrrw | CsrOp::Csrrwi => Some(operand),
+                CsrOp::Csrrs | CsrOp::Csrrsi if source != 0 => Some(old_value | operand),
+                CsrOp::Csrrc | CsrOp::Csrrci if source != 0 => Some(old_value & !operand),
+                _ => None,
+            };
+            if let Some(new_value) = new_value {
+                cpu_state.csrs.write(csr, new_value).ok_or(illegal)?;
             }
+            registers[rd as usize] = old_value;
         }
 
         ///////////////////////////////////////////// RV32F Standard Extension /////////////////////////////////////////////
 
         // FLoating Point Instructions
-        0b0000111 => {
-            match funct3 {
-                0b010 => {
-                    cpu_state.floating_point_registers[rd as usize] = (cpu_state.read_mem(
-                        ((cpu_state.registers[rs1 as usize] as i32) + (imm_l as i32)) as usize,
-                    )) as f32
-                } // FLW
-                0b011 => {
-                    cpu_state.floating_point_registers[rd as usize] = (cpu_state.read_mem(
-                        ((cpu_state.registers[rs1 as usize] as i32) + (imm_l as i32)) as usize,
-                    )) as f32
-                } // FLW
-                _ => panic!("Unknown funct3: {}", funct3),
+        Instruction::Flw { rd, rs1, offset } => {
+            let address = registers[rs1 as usize].wrapping_add(offset as u32);
+            floating_point_registers[rd as usize] = f32::from_bits(
+                cpu_state
+                    .bus
+                    .read(address, 4)
+                    .ok_or_else(|| load_fault(address))?,
+            )
+        }
+        Instruction::Fsw { rs1, rs2, offset } => {
+            let address = registers[rs1 as usize].wrapping_add(offset as u32);
+            cpu_state
+                .bus
+                .write(address, 4, floating_point_registers[rs2 as usize].to_bits())
+                .ok_or_else(|| store_fault(address))?;
+        }
+        Instruction::FloatArithmetic {
+            op,
+            rd,
+            rs1,
+            rs2,
+            rm,
+        } => {
+            let rm = rounding_mode(rm, *fcsr).ok_or(illegal)?;
+            let a = floating_point_registers[rs1 as usize].to_bits();
+            let b = floating_point_registers[rs2 as usize].to_bits();
+            let result = match op {
+                FloatArithmeticOp::Fadd => float::add(a, b, rm, fcsr),
+                FloatArithmeticOp::Fsub => float::sub(a, b, rm, fcsr),
+                FloatArithmeticOp::Fmul => float::mul(a, b, rm, fcsr),
+                FloatArithmeticOp::Fdiv => float::div(a, b, rm, fcsr),
+            };
+            floating_point_registers[rd as usize] = f32::from_bits(result);
+        }
+        Instruction::FloatBinary { op, rd, rs1, rs2 } => {
+            let a = floating_point_registers[rs1 as usize];
+            let b = floating_point_registers[rs2 as usize];
+            let (a_bits, b_bits) = (a.to_bits(), b.to_bits());
+            // sign injection copies bits, so it works on NaNs and zeros too
+            let (magnitude, sign) = (a_bits & !SIGN_BIT, b_bits & SIGN_BIT);
+            match op {
+                FloatBinaryOp::Fsgnj => {
+                    floating_point_registers[rd as usize] = f32::from_bits(magnitude | sign)
+                }
+                FloatBinaryOp::Fsgnjn => {
+                    floating_point_registers[rd as usize] =
+                        f32::from_bits(magnitude | (sign ^ SIGN_BIT))
+                }
+                FloatBinaryOp::Fsgnjx => {
+                    floating_point_registers[rd as usize] = f32::from_bits(a_bits ^ sign)
+                }
+                FloatBinaryOp::Fmin => {
+                    *fcsr |= float::min_max_flags(a_bits, b_bits);
+                    floating_point_registers[rd as usize] = float_min_max(a, b, false)
+                }
+                FloatBinaryOp::Fmax => {
+                    *fcsr |= float::min_max_flags(a_bits, b_bits);
+                    floating_point_registers[rd as usize] = float_min_max(a, b, true)
+                }
+                FloatBinaryOp::Feq => {
+                    registers[rd as usize] = float::eq(a_bits, b_bits, fcsr) as u32
+                }
+                FloatBinaryOp::Flt => {
+                    registers[rd as usize] = float::less(a_bits, b_bits, false, fcsr) as u32
+                }
+                FloatBinaryOp::Fle => {
+                    registers[rd as usize] = float::less(a_bits, b_bits, true, fcsr) as u32
+                }
+            }
+        }
+        Instruction::FloatUnary { op, rd, rs1, rm } => {
+            let rm = rounding_mode(rm, *fcsr).ok_or(illegal)?;
+            let a = floating_point_registers[rs1 as usize].to_bits();
+            let x = registers[rs1 as usize];
+            // out of range conversions saturate, NaN converts to the maximum
+            match op {
+                FloatUnaryOp::Fsqrt => {
+                    floating_point_registers[rd as usize] = f32::from_bits(float::sqrt(a, rm, fcsr))
+                }
+                FloatUnaryOp::FcvtWS => {
+                    registers[rd as usize] = float::to_integer(a, true, rm, fcsr)
+                }
+                FloatUnaryOp::FcvtWuS => {
+                    registers[rd as usize] = float::to_integer(a, false, rm, fcsr)
+                }
+                FloatUnaryOp::FcvtSW => {
+                    floating_point_registers[rd as usize] =
+                        f32::from_bits(float::from_integer(x, true, rm, fcsr))
+                }
+                FloatUnaryOp::FcvtSWu => {
+                    floating_point_registers[rd as usize] =
+                        f32::from_bits(float::from_integer(x, false, rm, fcsr))
+                }
+            }
+        }
+        Instruction::FloatBits { op, rd, rs1 } => match op {
+            FloatBitsOp::FmvXW => {
+                registers[rd as usize] = floating_point_registers[rs1 as usize].to_bits()
             }
-            cpu_state.floating_point_registers[rd as usize] = (cpu_state
-                .read_mem(((cpu_state.registers[rs1 as usize] as i32) + (imm_l as i32)) as usize))
-                as f32
-        } // FLW
-        0b0100111 => cpu_state.set_mem(
-            ((cpu_state.registers[rs1 as usize] as i32) + (imm_s as i32)) as usize,
-            cpu_state.floating_point_registers[rs2 as usize] as u32,
-        ), // FSW
-        0b1010011 => {
-            match funct7 {
-                0b0000000 => {
-                    cpu_state.floating_point_registers[rd as usize] = cpu_state
-                        .floating_point_registers[rs1 as usize]
-                        + cpu_state.floating_point_registers[rs2 as usize]
-                } // FADD.S
-                0b0000100 => {
-                    cpu_state.floating_point_registers[rd as usize] = cpu_state
-                        .floating_point_registers[rs1 as usize]
-                        - cpu_state.floating_point_registers[rs2 as usize]
-                } // FSUB.S
-                0b0001000 => {
-                    cpu_state.floating_point_registers[rd as usize] = cpu_state
-                        .floating_point_registers[rs1 as usize]
-                        * cpu_state.floating_point_registers[rs2 as usize]
-                } // FMUL.S
-                0b0001100 => {
-                    cpu_state.floating_point_registers[rd as usize] = cpu_state
-                        .floating_point_registers[rs1 as usize]
-                        / cpu_state.floating_point_registers[rs2 as usize]
-                } // FDIV.S
-                0b0101100 => {
-                    cpu_state.floating_point_registers[rd as usize] =
-                        (cpu_state.floating_point_registers[rs1 as usize]).sqrt()
-                } // FSQRT.S
-                0b0010000 => match funct3 {
-                    0b000 => {
-                        cpu_state.floating_point_registers[rd as usize] =
-                            cpu_state.floating_point_registers[rs1 as usize].abs()
-                                * cpu_state.floating_point_registers[rs2 as usize].signum()
-                    } // FSGNJ.S
-                    0b001 => {
-                        cpu_state.floating_point_registers[rd as usize] =
-                            cpu_state.floating_point_registers[rs1 as usize].abs()
-                                * cpu_state.floating_point_registers[rs2 as usize].signum()
-                                * -1.0
-                    } // FSGNJN.S
-                    0b010 => {
-                        cpu_state.floating_point_registers[rd as usize] = cpu_state
-                            .floating_point_registers[rs1 as usize]
-                            * cpu_state.floating_point_registers[rs2 as usize].signum()
-                    } // FSGNJX.S
-                    _ => panic!("Unknown funct3: {}", funct3),
-                },
-                0b0010100 => match funct3 {
-                    0b000 => {
-                        cpu_state.floating_point_registers[rd as usize] = if cpu_state
-                            .floating_point_registers[rs1 as usize]
-                            < cpu_state.floating_point_registers[rs2 as usize]
-                        {
-                            cpu_state.floating_point_registers[rs1 as usize]
-                        } else {
-                            cpu_state.floating_point_registers[rs2 as usize]
-                        }
-                    } // FMIN.S
-                    0b001 => {
-                        cpu_state.floating_point_registers[rd as usize] = if cpu_state
-                            .floating_point_registers[rs1 as usize]
-                            > cpu_state.floating_point_registers[rs2 as usize]
-                        {
-                            cpu_state.floating_point_registers[rs1 as usize]
-                        } else {
-                            cpu_state.floating_point_registers[rs2 as usize]
-                        }
-                    } // FMAX.S
-                    _ => panic!("Unknown funct3: {}", funct3),
-                },
-                0b1100000 => match rs2 as u32 {
-                    0b00000 => {
-                        cpu_state.registers[rd as usize] =
-                            cpu_state.floating_point_registers[rs1 as usize] as i32 as u32
-                    } // FCVT.W.S
-                    0b00001 => {
-                        cpu_state.registers[rd as usize] =
-                            cpu_state.floating_point_registers[rs1 as usize] as u32
-                    } // FCVT.WU.S
-                    _ => panic!("Unknown rs2: {}", rs2),
-                },
-                0b1110000 => match funct3 {
-                    0b000 => {
-                        cpu_state.registers[rd as usize] =
-                            cpu_state.floating_point_registers[rs1 as usize] as u32
-                    } // FMV.X.W
-                    0b001 => {
-                        cpu_state.registers[rd as usize] =
-                            mask_generate(cpu_state.floating_point_registers[rs1 as usize])
-                    } // FCLASS.S
-                    _ => panic!("Unknown funct3: {}", funct3),
-                },
-                0b1010000 => match funct3 {
-                    0b000 => {
-                        cpu_state.registers[rd as usize] = if cpu_state.floating_point_registers
-                            [rs1 as usize]
-                            <= cpu_state.floating_point_registers[rs2 as usize]
-                        {
-                            1
-                        } else {
-                            0
-                        }
-                    } // FLE.S
-                    0b001 => {
-                        cpu_state.registers[rd as usize] = if cpu_state.floating_point_registers
-                            [rs1 as usize]
-                            < cpu_state.floating_point_registers[rs2 as usize]
-                        {
-                            1
-                        } else {
-                            0
-                        }
-                    } // FLT.S
-                    0b010 => {
-                        cpu_state.registers[rd as usize] = if cpu_state.floating_point_registers
-                            [rs1 as usize]
-                            == cpu_state.floating_point_registers[rs2 as usize]
-                        {
-                            1
-                        } else {
-                            0
-                        }
-                    } // FEQ.S
-                    _ => panic!("Unknown funct3: {}", funct3),
-                },
-                0b1101000 => match rs2 as u32 {
-                    0b00000 => {
-                        cpu_state.registers[rd as usize] =
-                            cpu_state.floating_point_registers[rs1 as usize] as i32 as u32
-                    } // FCVT.S.W
-                    0b00001 => {
-                        cpu_state.registers[rd as usize] =
-                            cpu_state.floating_point_registers[rs1 as usize] as u32
-                    } // FCVT.S.WU
-                    _ => panic!("Unknown rs2: {}", rs2),
-                },
-                0b1111000 => match funct3 {
-                    0b000 => {
-                        cpu_state.floating_point_registers[rd as usize] =
-                            cpu_state.registers[rs1 as usize] as f32
-                    } // FMV.W.X
-                    _ => panic!("Unknown funct3: {}", funct3),
-                },
-
-                _ => panic!("Unknown funct7: {}", funct7),
+            FloatBitsOp::Fclass => {
+                registers[rd as usize] = mask_generate(floating_point_registers[rs1 as usize])
             }
+            FloatBitsOp::FmvWX => {
+                floating_point_registers[rd as usize] = f32::from_bits(registers[rs1 as usize])
+            }
+        },
+        Instruction::FloatFused {
+            op,
+            rd,
+            rs1,
+            rs2,
+            rs3,
+            rm,
+        } => {
+            let rm = rounding_mode(rm, *fcsr).ok_or(illegal)?;
+            let a = floating_point_registers[rs1 as usize].to_bits();
+            let b = floating_point_registers[rs2 as usize].to_bits();
+            let c = floating_point_registers[rs3 as usize].to_bits();
+            let (negate_product, negate_addend) = match op {
+                FusedOp::Fmadd => (false, false),
+                FusedOp::Fmsub => (false, true),
+                FusedOp::Fnmsub => (true, false),
+                FusedOp::Fnmadd => (true, true),
+            };
+            floating_point_registers[rd as usize] = f32::from_bits(float::fused_multiply_add(
+                a,
+                b,
+                c,
+                negate_product,
+                negate_addend,
+                rm,
+                fcsr,
+            ));
         }
+    }
+    cpu_state.pc = cpu_state.pc.wrapping_add(4);
+    Ok(())
+}
 
-        // ///////////////////////////////////////////// Custom GPIO Extensions /////////////////////////////////////////////
-        // 0b1111000 => match funct3 {
-        //     000 => {
-        //         // Set Config
-        //         cpu_state.pc += 4;
-        //         let config_address = cpu_state.read_mem(cpu_state.pc as usize) as usize;
-        //         let bytes_slice = &cpu_state.memory_bytes
-        //             [config_address..(config_address + std::mem::size_of::<GPIOState>())];
-        //         let struct_ref = unsafe { &*(bytes_slice.as_ptr() as *const GPIOState) };
-        //         cpu_state.gpio_states[imm_i as usize] = *struct_ref;
-        //         // TODO check for any connections to this gpio and update them
-        //     }
-        //     001 => {
-        //         // Reset Config
-        //         cpu_state.gpio_states[imm_i as usize] = GPIOState::new();
-        //     }
-        //     _ => panic!("Unknown funct3: {}", funct3),
-        // },
-
-        _ => {
-            panic!("Unknown opcode: {:b}", opcode);
-        }
+#[inline(always)]
+fn execute_register(
+    cpu_state: &mut CPUState,
+    op: RegisterOp,
+    rd: u8,
+    rs1: u8,
+    rs2: u8,
+) -> Result<(), Trap> {
+    let a = cpu_state.registers[rs1 as usize];
+    let b = cpu_state.registers[rs2 as usize];
+    cpu_state.registers[rd as usize] = match op {
+        RegisterOp::Add => a.wrapping_add(b),
+        RegisterOp::Sub => a.wrapping_sub(b),
+        // only the low five bits of rs2 are the shift amount
+        RegisterOp::Sll => a << (b & 0x1F),
+        RegisterOp::Slt => ((a as i32) < (b as i32)) as u32,
+        RegisterOp::Sltu => (a < b) as u32,
+        RegisterOp::Xor => a ^ b,
+        RegisterOp::Srl => a >> (b & 0x1F),
+        RegisterOp::Sra => ((a as i32) >> (b & 0x1F)) as u32,
+        RegisterOp::Or => a | b,
+        RegisterOp::And => a & b,
+        RegisterOp::Mul => a.wrapping_mul(b),
+        RegisterOp::Mulh => ((a as i32 as i64 * b as i32 as i64) >> 32) as u32,
+        RegisterOp::Mulhsu => ((a as i32 as i64 * b as i64) >> 32) as u32,
+        RegisterOp::Mulhu => ((a as u64 * b as u64) >> 32) as u32,
+        // division by zero gives all ones or the dividend and never traps,
+        // and the one signed overflow wraps
+        RegisterOp::Div if b == 0 => u32::MAX,
+        RegisterOp::Div => (a as i32).wrapping_div(b as i32) as u32,
+        RegisterOp::Divu => a.checked_div(b).unwrap_or(u32::MAX),
+        RegisterOp::Rem if b == 0 => a,
+        RegisterOp::Rem => (a as i32).wrapping_rem(b as i32) as u32,
+        RegisterOp::Remu => a.checked_rem(b).unwrap_or(a),
+    };
+    cpu_state.pc = cpu_state.pc.wrapping_add(4);
+    Ok(())
+}
+
+#[inline(always)]
+fn execute_immediate(
+    cpu_state: &mut CPUState,
+    op: ImmediateOp,
+    rd: u8,
+    rs1: u8,
+    imm: i32,
+) -> Result<(), Trap> {
+    let a = cpu_state.registers[rs1 as usize];
+    cpu_state.registers[rd as usize] = match op {
+        ImmediateOp::Addi => a.wrapping_add(imm as u32),
+        ImmediateOp::Slti => ((a as i32) < imm) as u32,
+        ImmediateOp::Sltiu => (a < imm as u32) as u32,
+        ImmediateOp::Xori => a ^ imm as u32,
+        ImmediateOp::Ori => a | imm as u32,
+        ImmediateOp::Andi => a & imm as u32,
+    };
+    cpu_state.pc = cpu_state.pc.wrapping_add(4);
+    Ok(())
+}
+
+#[inline(always)]
+fn execute_shift(
+    cpu_state: &mut CPUState,
+    op: ShiftOp,
+    rd: u8,
+    rs1: u8,
+    shamt: u8,
+) -> Result<(), Trap> {
+    let a = cpu_state.registers[rs1 as usize];
+    cpu_state.registers[rd as usize] = match op {
+        ShiftOp::Slli => a << shamt,
+        ShiftOp::Srli => a >> shamt,
+        ShiftOp::Srai => ((a as i32) >> shamt) as u32,
+    };
+    cpu_state.pc = cpu_state.pc.wrapping_add(4);
+    Ok(())
+}
+
+#[inline(always)]
+fn execute_load(
+    cpu_state: &mut CPUState,
+    op: LoadOp,
+    rd: u8,
+    rs1: u8,
+    offset: i32,
+    word: u32,
+) -> Result<(), Trap> {
+    let address = cpu_state.registers[rs1 as usize].wrapping_add(offset as u32);
+    let size = match op {
+        LoadOp::Lw => 4,
+        LoadOp::Lh | LoadOp::Lhu => 2,
+        LoadOp::Lb | LoadOp::Lbu => 1,
+    };
+    let value = cpu_state
+        .bus
+        .read(address, size)
+        .ok_or(Trap::LoadAccessFault {
+            pc: cpu_state.pc,
+            instruction: word,
+            address,
+        })?;
+    cpu_state.registers[rd as usize] = match op {
+        LoadOp::Lh => value as u16 as i16 as u32,
+        LoadOp::Lb => value as u8 as i8 as u32,
+        _ => value,
+    };
+    cpu_state.pc = cpu_state.pc.wrapping_add(4);
+    Ok(())
+}
+
+#[inline(always)]
+fn execute_store(
+    cpu_state: &mut CPUState,
+    op: StoreOp,
+    rs1: u8,
+    rs2: u8,
+    offset: i32,
+    word: u32,
+) -> Result<(), Trap> {
+    let address = cpu_state.registers[rs1 as usize].wrapping_add(offset as u32);
+    let size = match op {
+        StoreOp::Sw => 4,
+        StoreOp::Sh => 2,
+        StoreOp::Sb => 1,
+    };
+    cpu_state
+        .bus
+        .write(address, size, cpu_state.registers[rs2 as usize])
+        .ok_or(Trap::StoreAccessFault {
+            pc: cpu_state.pc,
+            instruction: word,
+            address,
+        })?;
+    cpu_state.pc = cpu_state.pc.wrapping_add(4);
+    Ok(())
+}
+
+#[inline(always)]
+fn execute_branch(
+    cpu_state: &mut CPUState,
+    op: BranchOp,
+    rs1: u8,
+    rs2: u8,
+    offset: i32,
+    word: u32,
+) -> Result<(), Trap> {
+    let a = cpu_state.registers[rs1 as usize];
+    let b = cpu_state.registers[rs2 as usize];
+    let taken = match op {
+        BranchOp::Beq => a == b,
+        BranchOp::Bne => a != b,
+        BranchOp::Blt => (a as i32) < (b as i32),
+        BranchOp::Bge => (a as i32) >= (b as i32),
+        BranchOp::Bltu => a < b,
+        BranchOp::Bgeu => a >= b,
+    };
+    if taken {
+        let target = cpu_state.pc.wrapping_add(offset as u32);
+        check_jump_target(cpu_state.pc, target, word)?;
+        cpu_state.pc = target;
+    } else {
+        cpu_state.pc = cpu_state.pc.wrapping_add(4);
     }
-    cpu_state.pc += 4;
-    return false;
+    Ok(())
+}
+
+#[inline(always)]
+fn execute_jal(cpu_state: &mut CPUState, rd: u8, offset: i32, word: u32) -> Result<(), Trap> {
+    let target = cpu_state.pc.wrapping_add(offset as u32);
+    check_jump_target(cpu_state.pc, target, word)?;
+    cpu_state.registers[rd as usize] = cpu_state.pc.wrapping_add(4);
+    cpu_state.pc = target;
+    Ok(())
+}
+
+#[inline(always)]
+fn execute_jalr(
+    cpu_state: &mut CPUState,
+    rd: u8,
+    rs1: u8,
+    offset: i32,
+    word: u32,
+) -> Result<(), Trap> {
+    let target = cpu_state.registers[rs1 as usize].wrapping_add(offset as u32) & !1;
+    check_jump_target(cpu_state.pc, target, word)?;
+    cpu_state.registers[rd as usize] = cpu_state.pc.wrapping_add(4);
+    cpu_state.pc = target;
+    Ok(())
+}
+
+#[inline(always)]
+fn execute_lui(cpu_state: &mut CPUState, rd: u8, imm: u32) -> Result<(), Trap> {
+    cpu_state.registers[rd as usize] = imm << 12;
+    cpu_state.pc = cpu_state.pc.wrapping_add(4);
+    Ok(())
+}
+
+#[inline(always)]
+fn execute_auipc(cpu_state: &mut CPUState, rd: u8, imm: u32) -> Result<(), Trap> {
+    cpu_state.registers[rd as usize] = cpu_state.pc.wrapping_add(imm << 12);
+    cpu_state.pc = cpu_state.pc.wrapping_add(4);
+    Ok(())
 }
 
 fn get_file_as_byte_vec(filename: &str) -> Vec<u8> {
     use std::fs;
-    let mut f = File::open(&filename).expect("no file found");
-    let metadata = fs::metadata(&filename).expect("unable to read metadata");
+    let mut f = File::open(filename).expect("no file found");
+    let metadata = fs::metadata(filename).expect("unable to read metadata");
     let mut buffer = vec![0; metadata.len() as usize];
-    f.read(&mut buffer).expect("buffer overflow");
-    return buffer;
-}
-
-pub fn interpret_file(file_name: &str, cpu_state: &mut CPUState) -> io::Result<()> {
-    let buffer: Vec<u8> = get_file_as_byte_vec(file_name);
-    for i in 0..buffer.len() {
-        let index: usize = (i / 4) * 4 + 3 - (i % 4);
-        cpu_state.memory_bytes[index] = buffer[i];
-        // cpu_state.memory[(i / 4) * 4 as usize] <<= 8;
-        // cpu_state.memory[(i / 4) * 4 as usize] += (buffer[i] & 0xFF) as u32;
-    }
-    let mut count = 0;
-    loop {
-        count += 1;
-        let ecall: bool = decode_instruction(cpu_state);
-        cpu_state.registers[0] = 0;
-        if ecall {
-            println!("{}", cpu_state.registers[17]);
-            if cpu_state.registers[17] == 10 {
-                break;
-            }
-        }
-        if count >= 10 {
-            assert!(false);
+    f.read_exact(&mut buffer).expect("buffer overflow");
+    buffer
+}
+
+/// Byte order of a flat binary image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ByteOrder {
+    /// Standard RISC-V machine code, the bytes are loaded verbatim.
+    #[default]
+    LittleEndian,
+    /// Images produced by older versions of the assembler, which wrote every
+    /// 32 bit word most significant byte first.
+    LegacyBigEndian,
+}
+
+impl ByteOrder {
+    /// Rearranges `bytes` into the little-endian layout of guest memory.
+    pub fn to_little_endian(self, bytes: &[u8]) -> Vec<u8> {
+        match self {
+            ByteOrder::LittleEndian => bytes.to_vec(),
+            ByteOrder::LegacyBigEndian => bytes
+                .chunks(4)
+                .flat_map(|word| word.iter().rev().copied())
+                .collect(),
         }
     }
+}
 
-    return Ok(());
+/// Loads the flat little-endian image `file_name` at the pc and runs it to
+/// completion.
+pub fn interpret_file(file_name: &str, cpu_state: &mut CPUState) -> io::Result<RunResult> {
+    interpret_file_with_byte_order(file_name, cpu_state, ByteOrder::LittleEndian, 0)
 }
 
-pub fn interpret(bytes: &Vec<u8>,  cpu_state: &mut CPUState) {
-    interpret_max_cycles(bytes, cpu_state, 0);
+/// Like `interpret_file`, but `byte_order` selects how the image was written,
+/// use `ByteOrder::LegacyBigEndian` for `.bin` files from the old assembler.
+/// A `max_cycles` of 0 means no limit.
+pub fn interpret_file_with_byte_order(
+    file_name: &str,
+    cpu_state: &mut CPUState,
+    byte_order: ByteOrder,
+    max_cycles: usize,
+) -> io::Result<RunResult> {
+    let buffer: Vec<u8> = byte_order.to_little_endian(&get_file_as_byte_vec(file_name));
+    Ok(interpret_max_cycles(&buffer, cpu_state, max_cycles))
 }
 
-pub fn interpret_max_cycles(bytes: &Vec<u8>,  cpu_state: &mut CPUState, max_cycles: usize) {
-    for i in 0..bytes.len() {
-        let index: usize = (i / 4) * 4 + 3 - (i % 4);
-        cpu_state.memory_bytes[index] = bytes[i];
+/// Loads the ELF executable `file_name` into guest memory and sets the pc to
+/// its entry point, the parsed file is returned for symbol lookups. An
+/// `Htif` is attached if the program defines `tohost`.
+pub fn load_elf_file(file_name: &str, cpu_state: &mut CPUState) -> io::Result<Elf> {
+    let bytes = std::fs::read(file_name)?;
+    let elf = Elf::parse(&bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    elf.load(cpu_state)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    if let Some(htif) = Htif::for_elf(&elf) {
+        cpu_state.attach_htif(htif);
     }
+    Ok(elf)
+}
 
-    let mut count = 0;
-    loop {
-        count += 1;
-        let ecall: bool = decode_instruction(cpu_state);
-        cpu_state.registers[0] = 0;
-        if ecall {
-            println!("{}", cpu_state.registers[17]);
-            if cpu_state.registers[17] == 10 {
-                break;
-            }
-        }
-        if (count >= max_cycles) && (max_cycles != 0) {
-            assert!(false);
-        }
+/// Runs the ELF executable `file_name` until it stops, use `load_elf_file`
+/// first instead when symbols are needed.
+pub fn interpret_elf_file(file_name: &str, cpu_state: &mut CPUState) -> io::Result<RunResult> {
+    load_elf_file(file_name, cpu_state)?;
+    Ok(cpu_state.run_until(|_| false))
+}
+
+pub fn interpret(bytes: &[u8], cpu_state: &mut CPUState) -> RunResult {
+    interpret_max_cycles(bytes, cpu_state, 0)
+}
+
+/// Loads `bytes` at the current pc and runs them, a `max_cycles` of 0 means
+/// no limit. A program that does not fit in memory is not run and gives
+/// `RunOutcome::LoadFailed`.
+pub fn interpret_max_cycles(
+    bytes: &[u8],
+    cpu_state: &mut CPUState,
+    max_cycles: usize,
+) -> RunResult {
+    if cpu_state.load(bytes).is_none() {
+        return RunResult {
+            outcome: RunOutcome::LoadFailed,
+            instructions: 0,
+            cycles: 0,
+        };
+    }
+    if max_cycles == 0 {
+        cpu_state.run_until(|_| false)
+    } else {
+        cpu_state.run(max_cycles as u64)
     }
 }
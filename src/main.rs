@@ -1,20 +1,16 @@
 #[macro_use]
 extern crate lalrpop_util;
+mod abi;
 mod assembler;
-mod riscv_spec;
+mod csr;
+mod instruction;
 #[macro_use]
 extern crate test_gen;
-#[macro_use]
-extern crate virtual_machine;
-use virtual_machine::vm;
 
 
 // macro_rules! immi {() => {};}
 fn main() {
     println!("hello");
-    vm! {
-        let x = 2;
-    };
 
     // let program_text = "
     // add x1, x2, x3
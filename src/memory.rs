@@ -0,0 +1,152 @@
+// Guest RAM and ROM
+
+use std::error::Error;
+use std::fmt;
+
+use crate::bus::Device;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryConfig {
+    /// Guest physical address of the first byte of RAM.
+    pub base: u32,
+    /// Size of RAM in bytes.
+    pub size: usize,
+}
+
+impl Default for MemoryConfig {
+    fn default() -> Self {
+        MemoryConfig {
+            base: 0,
+            size: crate::MEM_SIZE_WORDS * 4,
+        }
+    }
+}
+
+/// RAM larger than the 32 bit address space can reach.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryTooLarge {
+    pub size: usize,
+}
+
+impl fmt::Display for MemoryTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:#x} bytes of memory do not fit in the 32 bit address space",
+            self.size
+        )
+    }
+}
+
+impl Error for MemoryTooLarge {}
+
+fn check_size(size: usize) -> Result<(), MemoryTooLarge> {
+    if size as u64 > u32::MAX as u64 {
+        return Err(MemoryTooLarge { size });
+    }
+    Ok(())
+}
+
+fn read_le(bytes: &[u8], offset: u32, size: usize) -> Option<u32> {
+    let offset = offset as usize;
+    let bytes = bytes.get(offset..offset.checked_add(size)?)?;
+    let mut value = 0;
+    for (i, byte) in bytes.iter().enumerate() {
+        value |= (*byte as u32) << (8 * i);
+    }
+    Some(value)
+}
+
+fn write_le(bytes: &mut [u8], offset: u32, size: usize, value: u32) -> Option<()> {
+    let offset = offset as usize;
+    let bytes = bytes.get_mut(offset..offset.checked_add(size)?)?;
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = (value >> (8 * i)) as u8;
+    }
+    Some(())
+}
+
+/// A contiguous, little-endian block of guest RAM.
+#[derive(Debug, Clone)]
+pub struct Memory {
+    bytes: Vec<u8>,
+}
+
+impl Memory {
+    /// Zeroed RAM of `size` bytes, at most `u32::MAX` so that the bus can
+    /// address all of it.
+    pub fn new(size: usize) -> Result<Self, MemoryTooLarge> {
+        check_size(size)?;
+        Ok(Memory {
+            bytes: vec![0; size],
+        })
+    }
+
+    /// Grows (or shrinks) RAM to `size` bytes, new bytes are zeroed.
+    pub fn resize(&mut self, size: usize) -> Result<(), MemoryTooLarge> {
+        check_size(size)?;
+        self.bytes.resize(size, 0);
+        Ok(())
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn bytes_mut(&mut self) -> &mut [u8] {
+        &mut self.bytes
+    }
+}
+
+impl Device for Memory {
+    fn size(&self) -> u32 {
+        self.bytes.len() as u32
+    }
+
+    fn read(&mut self, offset: u32, size: usize) -> Option<u32> {
+        read_le(&self.bytes, offset, size)
+    }
+
+    fn write(&mut self, offset: u32, size: usize, value: u32) -> Option<()> {
+        write_le(&mut self.bytes, offset, size, value)
+    }
+}
+
+/// Read-only memory, guest stores raise access faults but loaders can still
+/// initialise it.
+#[derive(Debug, Clone)]
+pub struct Rom {
+    bytes: Vec<u8>,
+}
+
+impl Rom {
+    pub fn new(bytes: Vec<u8>) -> Self {
+        Rom { bytes }
+    }
+}
+
+impl Device for Rom {
+    fn size(&self) -> u32 {
+        self.bytes.len() as u32
+    }
+
+    fn read(&mut self, offset: u32, size: usize) -> Option<u32> {
+        read_le(&self.bytes, offset, size)
+    }
+
+    fn write(&mut self, _offset: u32, _size: usize, _value: u32) -> Option<()> {
+        None
+    }
+
+    fn writable(&self) -> bool {
+        false
+    }
+
+    fn load(&mut self, offset: u32, data: &[u8]) -> Option<()> {
+        let offset = offset as usize;
+        self.bytes
+            .get_mut(offset..offset.checked_add(data.len())?)?
+            .copy_from_slice(data);
+        Some(())
+    }
+}
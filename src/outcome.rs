@@ -0,0 +1,54 @@
+use std::fmt;
+
+use crate::Trap;
+
+/// Why a run of the interpreter stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunOutcome {
+    /// The guest made an exit system call with this status.
+    Exited(i32),
+    /// The cycle budget ran out before the guest exited.
+    CycleLimit,
+    /// An exception that neither the guest nor the syscall handler dealt with.
+    Trapped(Trap),
+    /// An ebreak was executed at `pc`.
+    Breakpoint { pc: u32 },
+    /// The predicate passed to `run_until` returned true.
+    Stopped,
+    /// The program passed to `interpret` does not fit in guest memory at the
+    /// pc, nothing was run.
+    LoadFailed,
+}
+
+/// The outcome of a run along with how much work it did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RunResult {
+    pub outcome: RunOutcome,
+    /// Instructions retired during the run.
+    pub instructions: u64,
+    /// Cycles elapsed during the run, including cycles stalled in WFI.
+    pub cycles: u64,
+}
+
+impl RunResult {
+    /// The exit status, or `None` if the guest did not exit.
+    pub fn exit_code(&self) -> Option<i32> {
+        match self.outcome {
+            RunOutcome::Exited(code) => Some(code),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for RunOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RunOutcome::Exited(code) => write!(f, "exited with status {}", code),
+            RunOutcome::CycleLimit => write!(f, "cycle limit reached"),
+            RunOutcome::Trapped(trap) => write!(f, "{}", trap),
+            RunOutcome::Breakpoint { pc } => write!(f, "breakpoint at pc {:#010x}", pc),
+            RunOutcome::Stopped => write!(f, "stopped"),
+            RunOutcome::LoadFailed => write!(f, "program does not fit in guest memory"),
+        }
+    }
+}
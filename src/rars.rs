@@ -0,0 +1,152 @@
+// The environment calls provided by the RARS and Venus simulators
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+use crate::syscall::{SyscallAction, SyscallHandler};
+use crate::CPUState;
+
+const A0: usize = 10;
+const A1: usize = 11;
+const A7: usize = 17;
+const FA0: usize = 10;
+
+pub const PRINT_INT: u32 = 1;
+pub const PRINT_FLOAT: u32 = 2;
+pub const PRINT_STRING: u32 = 4;
+pub const READ_INT: u32 = 5;
+pub const READ_FLOAT: u32 = 6;
+pub const READ_STRING: u32 = 8;
+pub const SBRK: u32 = 9;
+pub const EXIT: u32 = 10;
+pub const PRINT_CHAR: u32 = 11;
+pub const READ_CHAR: u32 = 12;
+/// Venus numbers exit with a status 17, RARS uses 93.
+pub const VENUS_EXIT2: u32 = 17;
+pub const PRINT_INT_HEX: u32 = 34;
+pub const PRINT_INT_BINARY: u32 = 35;
+pub const PRINT_INT_UNSIGNED: u32 = 36;
+pub const EXIT2: u32 = 93;
+
+/// Start of the RARS heap in its default memory configuration.
+pub const DEFAULT_HEAP_START: u32 = 0x1004_0000;
+
+/// The RARS / Venus ecall table, a7 selects the call and a0 or fa0 hold the
+/// argument. Input is read a line at a time like the originals.
+pub struct RarsSyscalls {
+    stdin: Box<dyn BufRead>,
+    stdout: Box<dyn Write>,
+    heap_end: u32,
+}
+
+impl Default for RarsSyscalls {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RarsSyscalls {
+    pub fn new() -> Self {
+        RarsSyscalls {
+            stdin: Box::new(BufReader::new(io::stdin())),
+            stdout: Box::new(io::stdout()),
+            heap_end: DEFAULT_HEAP_START,
+        }
+    }
+
+    pub fn with_stdin(mut self, stdin: impl BufRead + 'static) -> Self {
+        self.stdin = Box::new(stdin);
+        self
+    }
+
+    pub fn with_stdout(mut self, stdout: impl Write + 'static) -> Self {
+        self.stdout = Box::new(stdout);
+        self
+    }
+
+    /// Sets the address the first sbrk call returns.
+    pub fn with_heap_start(mut self, address: u32) -> Self {
+        self.heap_end = address;
+        self
+    }
+
+    fn read_line(&mut self) -> String {
+        let mut line = String::new();
+        // end of input reads as an empty line
+        let _ = self.stdin.read_line(&mut line);
+        line
+    }
+
+    fn print(&mut self, bytes: &[u8]) {
+        let _ = self.stdout.write_all(bytes);
+        let _ = self.stdout.flush();
+    }
+
+    fn print_string(&mut self, cpu_state: &mut CPUState) {
+        let mut address = cpu_state.registers[A0];
+        let mut bytes = vec![];
+        while let Some(byte) = cpu_state.bus.read(address, 1) {
+            if byte == 0 {
+                break;
+            }
+            bytes.push(byte as u8);
+            address = address.wrapping_add(1);
+        }
+        self.print(&bytes);
+    }
+
+    /// Reads at most a1 - 1 bytes into the buffer at a0 and null terminates
+    /// it, the newline is kept if it fits.
+    fn read_string(&mut self, cpu_state: &mut CPUState) {
+        let capacity = cpu_state.registers[A1] as usize;
+        if capacity == 0 {
+            return;
+        }
+        let line = self.read_line();
+        let mut bytes: Vec<u8> = line.bytes().take(capacity - 1).collect();
+        bytes.push(0);
+        let _ = cpu_state.write_bytes(cpu_state.registers[A0], &bytes);
+    }
+}
+
+impl SyscallHandler for RarsSyscalls {
+    fn handle(&mut self, cpu_state: &mut CPUState) -> SyscallAction {
+        let a0 = cpu_state.registers[A0];
+        match cpu_state.registers[A7] {
+            PRINT_INT => self.print((a0 as i32).to_string().as_bytes()),
+            PRINT_FLOAT => {
+                let value = cpu_state.floating_point_registers[FA0];
+                self.print(format!("{:?}", value).as_bytes())
+            }
+            PRINT_STRING => self.print_string(cpu_state),
+            READ_INT => {
+                cpu_state.registers[A0] =
+                    self.read_line().trim().parse::<i32>().unwrap_or(0) as u32;
+            }
+            READ_FLOAT => {
+                cpu_state.floating_point_registers[FA0] =
+                    self.read_line().trim().parse::<f32>().unwrap_or(0.0);
+            }
+            READ_STRING => self.read_string(cpu_state),
+            SBRK => {
+                // the heap grows in whole words
+                cpu_state.registers[A0] = self.heap_end;
+                self.heap_end = self.heap_end.wrapping_add(a0.wrapping_add(3) & !3);
+            }
+            EXIT => return SyscallAction::Exit(0),
+            PRINT_CHAR => self.print(&[a0 as u8]),
+            READ_CHAR => {
+                let mut byte = [0];
+                cpu_state.registers[A0] = match self.stdin.read_exact(&mut byte) {
+                    Ok(()) => byte[0] as u32,
+                    Err(_) => 0,
+                };
+            }
+            VENUS_EXIT2 | EXIT2 => return SyscallAction::Exit(a0 as i32),
+            PRINT_INT_HEX => self.print(format!("{:#010x}", a0).as_bytes()),
+            PRINT_INT_BINARY => self.print(format!("{:032b}", a0).as_bytes()),
+            PRINT_INT_UNSIGNED => self.print(a0.to_string().as_bytes()),
+            _ => return SyscallAction::Unhandled,
+        }
+        SyscallAction::Continue
+    }
+}
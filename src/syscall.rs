@@ -0,0 +1,396 @@
+// Host emulation of the system calls guest programs make with ecall
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Component, Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::CPUState;
+
+const A0: usize = 10;
+const A1: usize = 11;
+const A2: usize = 12;
+const A7: usize = 17;
+
+pub const SYS_OPENAT: u32 = 56;
+pub const SYS_CLOSE: u32 = 57;
+pub const SYS_LSEEK: u32 = 62;
+pub const SYS_READ: u32 = 63;
+pub const SYS_WRITE: u32 = 64;
+pub const SYS_FSTAT: u32 = 80;
+pub const SYS_EXIT: u32 = 93;
+pub const SYS_EXIT_GROUP: u32 = 94;
+pub const SYS_CLOCK_GETTIME: u32 = 113;
+pub const SYS_GETTIMEOFDAY: u32 = 169;
+pub const SYS_BRK: u32 = 214;
+
+const ENOENT: i32 = 2;
+const EIO: i32 = 5;
+const EBADF: i32 = 9;
+const EACCES: i32 = 13;
+pub(crate) const EFAULT: i32 = 14;
+const EEXIST: i32 = 17;
+const EISDIR: i32 = 21;
+const EINVAL: i32 = 22;
+pub(crate) const ENOSYS: i32 = 38;
+const ELOOP: i32 = 40;
+
+// Linux open flags
+const O_ACCMODE: u32 = 0o3;
+const O_WRONLY: u32 = 0o1;
+const O_RDWR: u32 = 0o2;
+const O_CREAT: u32 = 0o100;
+const O_EXCL: u32 = 0o200;
+const O_TRUNC: u32 = 0o1000;
+const O_APPEND: u32 = 0o2000;
+const AT_FDCWD: i32 = -100;
+
+const S_IFCHR: u32 = 0o020000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+const STAT_SIZE: usize = 128;
+
+const CLOCK_REALTIME: u32 = 0;
+
+/// What the interpreter should do once a system call has been handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyscallAction {
+    Continue,
+    Exit(i32),
+    /// The handler does not implement the requested call, the ecall is
+    /// reported to the embedder as an `EnvironmentCall` trap.
+    Unhandled,
+}
+
+/// Services the ecall instructions executed by a guest.
+///
+/// The handler is called after the pc has been advanced past the ecall, it
+/// reads the call number and arguments from the registers and writes any
+/// results back to them.
+pub trait SyscallHandler {
+    fn handle(&mut self, cpu_state: &mut CPUState) -> SyscallAction;
+}
+
+fn errno(error: &io::Error) -> i32 {
+    match error.kind() {
+        io::ErrorKind::NotFound => ENOENT,
+        io::ErrorKind::PermissionDenied => EACCES,
+        io::ErrorKind::AlreadyExists => EEXIST,
+        io::ErrorKind::InvalidInput => EINVAL,
+        _ if error.raw_os_error() == Some(libc::ELOOP) => ELOOP,
+        _ => EIO,
+    }
+}
+
+fn read_c_string(cpu_state: &mut CPUState, mut address: u32) -> Option<String> {
+    let mut bytes = vec![];
+    loop {
+        let byte = cpu_state.bus.read(address, 1)? as u8;
+        if byte == 0 {
+            return Some(String::from_utf8_lossy(&bytes).into_owned());
+        }
+        bytes.push(byte);
+        address = address.wrapping_add(1);
+    }
+}
+
+/// A newlib / Linux compatible system call layer.
+///
+/// File system calls are confined to a host directory: guest paths are
+/// resolved relative to it, whether absolute or not, and may not escape it.
+/// Descriptors 0, 1 and 2 are connected to the host's standard streams unless
+/// replaced with `with_stdin`, `with_stdout` and `with_stderr`.
+pub struct LinuxSyscalls {
+    root: PathBuf,
+    stdin: Box<dyn Read>,
+    stdout: Box<dyn Write>,
+    stderr: Box<dyn Write>,
+    files: HashMap<i32, File>,
+    program_break: u32,
+    minimum_break: u32,
+    start_time: Instant,
+}
+
+impl LinuxSyscalls {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        LinuxSyscalls {
+            root: root.into(),
+            stdin: Box::new(io::stdin()),
+            stdout: Box::new(io::stdout()),
+            stderr: Box::new(io::stderr()),
+            files: HashMap::new(),
+            program_break: 0,
+            minimum_break: 0,
+            start_time: Instant::now(),
+        }
+    }
+
+    pub fn with_stdin(mut self, stdin: impl Read + 'static) -> Self {
+        self.stdin = Box::new(stdin);
+        self
+    }
+
+    pub fn with_stdout(mut self, stdout: impl Write + 'static) -> Self {
+        self.stdout = Box::new(stdout);
+        self
+    }
+
+    pub fn with_stderr(mut self, stderr: impl Write + 'static) -> Self {
+        self.stderr = Box::new(stderr);
+        self
+    }
+
+    /// Sets the initial program break, normally the `_end` symbol of the
+    /// executable. brk never shrinks the heap below this address.
+    pub fn with_program_break(mut self, address: u32) -> Self {
+        self.program_break = address;
+        self.minimum_break = address;
+        self
+    }
+
+    pub fn program_break(&self) -> u32 {
+        self.program_break
+    }
+
+    /// Maps a guest path onto the sandbox, rejecting `..` components and
+    /// symlinks that lead outside of it. The returned path has its symlinks
+    /// resolved, and a final component that is still a symlink is refused so
+    /// a dangling one cannot create a file outside.
+    fn resolve(&self, path: &str) -> Result<PathBuf, i32> {
+        let mut resolved = self.root.clone();
+        for component in Path::new(path).components() {
+            match component {
+                Component::Normal(part) => resolved.push(part),
+                Component::RootDir | Component::CurDir => {}
+                Component::ParentDir | Component::Prefix(_) => return Err(EACCES),
+            }
+        }
+        let root = self.root.canonicalize().map_err(|e| errno(&e))?;
+        let resolved = match resolved.canonicalize() {
+            Ok(path) => path,
+            // the file may be about to be created, check its directory instead
+            Err(_) => match (resolved.parent(), resolved.file_name()) {
+                (Some(parent), Some(name)) => {
+                    parent.canonicalize().map_err(|e| errno(&e))?.join(name)
+                }
+                _ => return Err(ENOENT),
+            },
+        };
+        if !resolved.starts_with(&root) {
+            return Err(EACCES);
+        }
+        match fs::symlink_metadata(&resolved) {
+            Ok(metadata) if metadata.file_type().is_symlink() => Err(ELOOP),
+            _ => Ok(resolved),
+        }
+    }
+
+    fn allocate_fd(&self) -> i32 {
+        (3..).find(|fd| !self.files.contains_key(fd)).unwrap()
+    }
+
+    fn openat(&mut self, cpu_state: &mut CPUState) -> Result<u32, i32> {
+        let dirfd = cpu_state.registers[A0] as i32;
+        let path = read_c_string(cpu_state, cpu_state.registers[A1]).ok_or(EFAULT)?;
+        let flags = cpu_state.registers[A2];
+        if dirfd != AT_FDCWD && !path.starts_with('/') {
+            return Err(EBADF);
+        }
+        let path = self.resolve(&path)?;
+        if path.is_dir() {
+            return Err(EISDIR);
+        }
+
+        // a symlink swapped in since resolve is not followed either
+        let mut options = OpenOptions::new();
+        options.custom_flags(libc::O_NOFOLLOW);
+        match flags & O_ACCMODE {
+            O_WRONLY => options.write(true),
+            O_RDWR => options.read(true).write(true),
+            _ => options.read(true),
+        };
+        if flags & O_APPEND != 0 {
+            options.append(true);
+        }
+        if flags & O_TRUNC != 0 {
+            options.truncate(true);
+        }
+        if flags & O_CREAT != 0 {
+            if flags & O_EXCL != 0 {
+                options.create_new(true);
+            } else {
+                options.create(true);
+            }
+        }
+        let file = options.open(path).map_err(|e| errno(&e))?;
+        let fd = self.allocate_fd();
+        self.files.insert(fd, file);
+        Ok(fd as u32)
+    }
+
+    fn read(&mut self, cpu_state: &mut CPUState) -> Result<u32, i32> {
+        let fd = cpu_state.registers[A0] as i32;
+        let address = cpu_state.registers[A1];
+        let requested = cpu_state.registers[A2] as usize;
+        // a short read into the part of the buffer that exists, so nothing is
+        // consumed that cannot be stored and the guest cannot choose the size
+        // of the host allocation
+        let len = cpu_state.bus.writable_len(address, requested);
+        if len == 0 && requested != 0 {
+            return Err(EFAULT);
+        }
+        let mut buffer = vec![0; len];
+        let count = match fd {
+            0 => self.stdin.read(&mut buffer),
+            _ => self.files.get_mut(&fd).ok_or(EBADF)?.read(&mut buffer),
+        }
+        .map_err(|e| errno(&e))?;
+        cpu_state
+            .write_bytes(address, &buffer[..count])
+            .ok_or(EFAULT)?;
+        Ok(count as u32)
+    }
+
+    fn write(&mut self, cpu_state: &mut CPUState) -> Result<u32, i32> {
+        let fd = cpu_state.registers[A0] as i32;
+        let buffer = cpu_state
+            .read_bytes(cpu_state.registers[A1], cpu_state.registers[A2] as usize)
+            .ok_or(EFAULT)?;
+        let stream: &mut dyn Write = match fd {
+            1 => &mut self.stdout,
+            2 => &mut self.stderr,
+            _ => self.files.get_mut(&fd).ok_or(EBADF)?,
+        };
+        stream
+            .write_all(&buffer)
+            .and_then(|_| stream.flush())
+            .map_err(|e| errno(&e))?;
+        Ok(buffer.len() as u32)
+    }
+
+    fn close(&mut self, cpu_state: &mut CPUState) -> Result<u32, i32> {
+        let fd = cpu_state.registers[A0] as i32;
+        match fd {
+            0..=2 => Ok(0),
+            _ => self.files.remove(&fd).map(|_| 0).ok_or(EBADF),
+        }
+    }
+
+    fn lseek(&mut self, cpu_state: &mut CPUState) -> Result<u32, i32> {
+        let fd = cpu_state.registers[A0] as i32;
+        let offset = cpu_state.registers[A1] as i32 as i64;
+        let position = match cpu_state.registers[A2] {
+            0 if offset >= 0 => SeekFrom::Start(offset as u64),
+            1 => SeekFrom::Current(offset),
+            2 => SeekFrom::End(offset),
+            _ => return Err(EINVAL),
+        };
+        let file = self.files.get_mut(&fd).ok_or(EBADF)?;
+        let position = file.seek(position).map_err(|e| errno(&e))?;
+        u32::try_from(position).map_err(|_| EINVAL)
+    }
+
+    fn fstat(&mut self, cpu_state: &mut CPUState) -> Result<u32, i32> {
+        let fd = cpu_state.registers[A0] as i32;
+        let (mode, size, modified) = match fd {
+            0..=2 => (S_IFCHR | 0o620, 0, 0),
+            _ => {
+                let metadata = self
+                    .files
+                    .get(&fd)
+                    .ok_or(EBADF)?
+                    .metadata()
+                    .map_err(|e| errno(&e))?;
+                let kind = if metadata.is_dir() { S_IFDIR } else { S_IFREG };
+                let modified = metadata
+                    .modified()
+                    .ok()
+                    .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                    .map_or(0, |time| time.as_secs());
+                (kind | 0o644, metadata.len(), modified)
+            }
+        };
+
+        // struct stat as laid out by newlib and the rv32 Linux ABI
+        let mut stat = [0u8; STAT_SIZE];
+        stat[16..20].copy_from_slice(&mode.to_le_bytes());
+        stat[20..24].copy_from_slice(&1u32.to_le_bytes()); // st_nlink
+        stat[48..56].copy_from_slice(&size.to_le_bytes());
+        stat[56..60].copy_from_slice(&4096u32.to_le_bytes()); // st_blksize
+        stat[64..72].copy_from_slice(&size.div_ceil(512).to_le_bytes());
+        for time in [72, 88, 104] {
+            stat[time..time + 8].copy_from_slice(&modified.to_le_bytes());
+        }
+        cpu_state
+            .write_bytes(cpu_state.registers[A1], &stat)
+            .ok_or(EFAULT)?;
+        Ok(0)
+    }
+
+    fn brk(&mut self, cpu_state: &mut CPUState) -> Result<u32, i32> {
+        let requested = cpu_state.registers[A0];
+        // Like Linux, a failed request returns the unchanged break
+        let mapped = requested == 0 || cpu_state.bus.read(requested - 1, 1).is_some();
+        if requested >= self.minimum_break && mapped {
+            self.program_break = requested;
+        }
+        Ok(self.program_break)
+    }
+
+    fn gettimeofday(&mut self, cpu_state: &mut CPUState) -> Result<u32, i32> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|_| EINVAL)?;
+        // struct timeval with a 64 bit time_t
+        let mut timeval = [0u8; 16];
+        timeval[0..8].copy_from_slice(&now.as_secs().to_le_bytes());
+        timeval[8..12].copy_from_slice(&now.subsec_micros().to_le_bytes());
+        cpu_state
+            .write_bytes(cpu_state.registers[A0], &timeval)
+            .ok_or(EFAULT)?;
+        Ok(0)
+    }
+
+    fn clock_gettime(&mut self, cpu_state: &mut CPUState) -> Result<u32, i32> {
+        let time = match cpu_state.registers[A0] {
+            CLOCK_REALTIME => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_err(|_| EINVAL)?,
+            // every other clock counts from when the handler was created
+            _ => self.start_time.elapsed(),
+        };
+        // struct timespec with a 64 bit time_t
+        let mut timespec = [0u8; 16];
+        timespec[0..8].copy_from_slice(&time.as_secs().to_le_bytes());
+        timespec[8..12].copy_from_slice(&time.subsec_nanos().to_le_bytes());
+        cpu_state
+            .write_bytes(cpu_state.registers[A1], &timespec)
+            .ok_or(EFAULT)?;
+        Ok(0)
+    }
+}
+
+impl SyscallHandler for LinuxSyscalls {
+    fn handle(&mut self, cpu_state: &mut CPUState) -> SyscallAction {
+        let result = match cpu_state.registers[A7] {
+            SYS_EXIT | SYS_EXIT_GROUP => {
+                return SyscallAction::Exit(cpu_state.registers[A0] as i32);
+            }
+            SYS_OPENAT => self.openat(cpu_state),
+            SYS_CLOSE => self.close(cpu_state),
+            SYS_LSEEK => self.lseek(cpu_state),
+            SYS_READ => self.read(cpu_state),
+            SYS_WRITE => self.write(cpu_state),
+            SYS_FSTAT => self.fstat(cpu_state),
+            SYS_BRK => self.brk(cpu_state),
+            SYS_GETTIMEOFDAY => self.gettimeofday(cpu_state),
+            SYS_CLOCK_GETTIME => self.clock_gettime(cpu_state),
+            _ => Err(ENOSYS),
+        };
+        // errors are returned as negated errno values
+        cpu_state.registers[A0] = result.unwrap_or_else(|errno| -errno as u32);
+        SyscallAction::Continue
+    }
+}
@@ -0,0 +1,255 @@
+// Opt-in execution tracing
+
+use std::io::Write;
+
+use serde_json::json;
+
+use crate::Trap;
+
+/// The fixed fields of a 32 bit instruction word.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InstructionFields {
+    pub opcode: u32,
+    pub rd: u32,
+    pub funct3: u32,
+    pub rs1: u32,
+    pub rs2: u32,
+    pub funct7: u32,
+}
+
+impl InstructionFields {
+    pub fn decode(instruction: u32) -> Self {
+        InstructionFields {
+            opcode: instruction & 0b1111111,
+            rd: (instruction >> 7) & 0b11111,
+            funct3: (instruction >> 12) & 0b111,
+            rs1: (instruction >> 15) & 0b11111,
+            rs2: (instruction >> 20) & 0b11111,
+            funct7: (instruction >> 25) & 0b1111111,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RegisterWrite {
+    Integer { register: usize, value: u32 },
+    Float { register: usize, value: f32 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+/// A successful data access made by an instruction, instruction fetches are
+/// not included.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub kind: AccessKind,
+    pub address: u32,
+    pub size: usize,
+    pub value: u32,
+}
+
+/// Everything an instruction did, passed to the tracer once it completes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceRecord {
+    pub pc: u32,
+    pub instruction: u32,
+    pub fields: InstructionFields,
+    pub register_writes: Vec<RegisterWrite>,
+    pub memory_accesses: Vec<MemoryAccess>,
+    /// Set when the instruction raised an exception instead of retiring.
+    pub trap: Option<Trap>,
+}
+
+/// Receives a record of every executed instruction. Tracing is off unless a
+/// tracer is installed in `CPUState::tracer`.
+pub trait Tracer {
+    fn trace(&mut self, _record: &TraceRecord) {}
+}
+
+/// A tracer that discards every record.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NullTracer;
+
+impl Tracer for NullTracer {}
+
+/// Writes one human readable line per instruction.
+pub struct TextTracer<W: Write> {
+    output: W,
+}
+
+impl<W: Write> TextTracer<W> {
+    pub fn new(output: W) -> Self {
+        TextTracer { output }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.output
+    }
+}
+
+impl<W: Write> Tracer for TextTracer<W> {
+    fn trace(&mut self, record: &TraceRecord) {
+        let mut line = format!("{:#010x}: {:#010x}", record.pc, record.instruction);
+        for write in &record.register_writes {
+            match write {
+                RegisterWrite::Integer { register, value } => {
+                    line += &format!("  x{} <- {:#010x}", register, value)
+                }
+                RegisterWrite::Float { register, value } => {
+                    line += &format!("  f{} <- {:?}", register, value)
+                }
+            }
+        }
+        for access in &record.memory_accesses {
+            let arrow = match access.kind {
+                AccessKind::Read => "->",
+                AccessKind::Write => "<-",
+            };
+            line += &format!(
+                "  mem{}[{:#010x}] {} {:#x}",
+                access.size * 8,
+                access.address,
+                arrow,
+                access.value
+            );
+        }
+        if let Some(trap) = &record.trap {
+            line += &format!("  trap: {}", trap);
+        }
+        let _ = writeln!(self.output, "{}", line);
+    }
+}
+
+/// Writes one JSON object per instruction (JSON lines).
+pub struct JsonTracer<W: Write> {
+    output: W,
+}
+
+impl<W: Write> JsonTracer<W> {
+    pub fn new(output: W) -> Self {
+        JsonTracer { output }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.output
+    }
+}
+
+impl<W: Write> Tracer for JsonTracer<W> {
+    fn trace(&mut self, record: &TraceRecord) {
+        let registers: Vec<_> = record
+            .register_writes
+            .iter()
+            .map(|write| match write {
+                RegisterWrite::Integer { register, value } => {
+                    json!({ "register": format!("x{}", register), "value": value })
+                }
+                RegisterWrite::Float { register, value } => {
+                    json!({ "register": format!("f{}", register), "value": value })
+                }
+            })
+            .collect();
+        let memory: Vec<_> = record
+            .memory_accesses
+            .iter()
+            .map(|access| {
+                let kind = match access.kind {
+                    AccessKind::Read => "read",
+                    AccessKind::Write => "write",
+                };
+                json!({
+                    "kind": kind,
+                    "address": access.address,
+                    "size": access.size,
+                    "value": access.value,
+                })
+            })
+            .collect();
+        let fields = &record.fields;
+        let line = json!({
+            "pc": record.pc,
+            "instruction": record.instruction,
+            "opcode": fields.opcode,
+            "rd": fields.rd,
+            "funct3": fields.funct3,
+            "rs1": fields.rs1,
+            "rs2": fields.rs2,
+            "funct7": fields.funct7,
+            "registers": registers,
+            "memory": memory,
+            "trap": record.trap.map(|trap| json!({
+                "cause": trap.cause(),
+                "value": trap.value(),
+                "description": trap.to_string(),
+            })),
+        });
+        let _ = writeln!(self.output, "{}", line);
+    }
+}
+
+/// Writes a commit log in the format of Spike's `--log-commits`, one line per
+/// retired instruction, so traces can be diffed against Spike or Sail.
+///
+/// The hart always runs in machine mode and writes to x0 are not logged.
+pub struct SpikeTracer<W: Write> {
+    output: W,
+    hart_id: u32,
+}
+
+impl<W: Write> SpikeTracer<W> {
+    pub fn new(output: W) -> Self {
+        SpikeTracer { output, hart_id: 0 }
+    }
+
+    pub fn with_hart_id(mut self, hart_id: u32) -> Self {
+        self.hart_id = hart_id;
+        self
+    }
+
+    pub fn into_inner(self) -> W {
+        self.output
+    }
+}
+
+// Values are printed zero padded to their width in bits
+fn spike_value(value: u32, bits: usize) -> String {
+    format!("0x{:01$x}", value, bits / 4)
+}
+
+impl<W: Write> Tracer for SpikeTracer<W> {
+    fn trace(&mut self, record: &TraceRecord) {
+        if record.trap.is_some() {
+            return;
+        }
+        let mut line = format!(
+            "core{:4}: 3 {} ({})",
+            self.hart_id,
+            spike_value(record.pc, 32),
+            spike_value(record.instruction, 32)
+        );
+        for write in &record.register_writes {
+            let (prefix, register, value) = match *write {
+                RegisterWrite::Integer { register, value } => ('x', register, value),
+                RegisterWrite::Float { register, value } => ('f', register, value.to_bits()),
+            };
+            line += &format!(" {}{:<2} {}", prefix, register, spike_value(value, 32));
+        }
+        for access in &record.memory_accesses {
+            match access.kind {
+                AccessKind::Read => line += &format!(" mem {}", spike_value(access.address, 32)),
+                AccessKind::Write => {
+                    line += &format!(
+                        " mem {} {}",
+                        spike_value(access.address, 32),
+                        spike_value(access.value, access.size * 8)
+                    )
+                }
+            }
+        }
+        let _ = writeln!(self.output, "{}", line);
+    }
+}
@@ -0,0 +1,117 @@
+use std::error::Error;
+use std::fmt;
+
+/// A synchronous exception raised while executing a guest instruction.
+///
+/// Every variant carries the pc of the faulting instruction and its raw
+/// instruction word so embedders can report or handle it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trap {
+    InstructionAddressMisaligned {
+        pc: u32,
+        instruction: u32,
+        address: u32,
+    },
+    InstructionAccessFault {
+        pc: u32,
+    },
+    IllegalInstruction {
+        pc: u32,
+        instruction: u32,
+    },
+    Breakpoint {
+        pc: u32,
+        instruction: u32,
+    },
+    LoadAccessFault {
+        pc: u32,
+        instruction: u32,
+        address: u32,
+    },
+    StoreAccessFault {
+        pc: u32,
+        instruction: u32,
+        address: u32,
+    },
+    EnvironmentCall {
+        pc: u32,
+        instruction: u32,
+    },
+}
+
+impl Trap {
+    pub fn pc(&self) -> u32 {
+        match *self {
+            Trap::InstructionAddressMisaligned { pc, .. }
+            | Trap::InstructionAccessFault { pc }
+            | Trap::IllegalInstruction { pc, .. }
+            | Trap::Breakpoint { pc, .. }
+            | Trap::LoadAccessFault { pc, .. }
+            | Trap::StoreAccessFault { pc, .. }
+            | Trap::EnvironmentCall { pc, .. } => pc,
+        }
+    }
+
+    pub fn instruction(&self) -> u32 {
+        match *self {
+            Trap::InstructionAddressMisaligned { instruction, .. }
+            | Trap::IllegalInstruction { instruction, .. }
+            | Trap::Breakpoint { instruction, .. }
+            | Trap::LoadAccessFault { instruction, .. }
+            | Trap::StoreAccessFault { instruction, .. }
+            | Trap::EnvironmentCall { instruction, .. } => instruction,
+            // the instruction word could not be fetched
+            Trap::InstructionAccessFault { .. } => 0,
+        }
+    }
+
+    /// The exception code as written to mcause.
+    pub fn cause(&self) -> u32 {
+        match self {
+            Trap::InstructionAddressMisaligned { .. } => 0,
+            Trap::InstructionAccessFault { .. } => 1,
+            Trap::IllegalInstruction { .. } => 2,
+            Trap::Breakpoint { .. } => 3,
+            Trap::LoadAccessFault { .. } => 5,
+            Trap::StoreAccessFault { .. } => 7,
+            Trap::EnvironmentCall { .. } => 11, // ecall from M-mode
+        }
+    }
+
+    /// The trap value as written to mtval.
+    pub fn value(&self) -> u32 {
+        match *self {
+            Trap::InstructionAddressMisaligned { address, .. }
+            | Trap::LoadAccessFault { address, .. }
+            | Trap::StoreAccessFault { address, .. } => address,
+            Trap::InstructionAccessFault { pc } => pc,
+            Trap::IllegalInstruction { instruction, .. } => instruction,
+            Trap::Breakpoint { pc, .. } => pc,
+            Trap::EnvironmentCall { .. } => 0,
+        }
+    }
+}
+
+impl fmt::Display for Trap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = match self {
+            Trap::InstructionAddressMisaligned { .. } => "instruction address misaligned",
+            Trap::InstructionAccessFault { .. } => "instruction access fault",
+            Trap::IllegalInstruction { .. } => "illegal instruction",
+            Trap::Breakpoint { .. } => "breakpoint",
+            Trap::LoadAccessFault { .. } => "load access fault",
+            Trap::StoreAccessFault { .. } => "store access fault",
+            Trap::EnvironmentCall { .. } => "environment call",
+        };
+        write!(
+            f,
+            "{} at pc {:#010x} (instruction {:#010x}, tval {:#010x})",
+            description,
+            self.pc(),
+            self.instruction(),
+            self.value()
+        )
+    }
+}
+
+impl Error for Trap {}
@@ -1,7 +1,7 @@
 use proc_macro::TokenStream;
 use quote::quote;
 use syn::{Ident, parse_macro_input, LitStr};
-use serde::{Serialize, Deserialize, Deserializer};
+use serde::{Serialize, Deserialize};
 
 #[derive(Serialize, Deserialize, Debug)]
 struct TestObject {
@@ -33,7 +33,7 @@ pub fn generate_tests(input: TokenStream) -> TokenStream {
     };
 
     let mut tests = Vec::new();
-    for (_, test) in input.into_iter().enumerate() {
+    for test in input {
         let test_name = Ident::new(test.name.as_str(), proc_macro2::Span::call_site());
         let code = test.code.as_str();
         let result_register = test.result.0;
@@ -42,9 +42,9 @@ pub fn generate_tests(input: TokenStream) -> TokenStream {
             #[test]
             fn #test_name() {
                 let binary: Vec<u8> = assemble(&String::from(#code));
-                println!("{:?}",binary);
                 let mut cpu_state = CPUState::new();
-                interpret_max_cycles(&binary, &mut cpu_state, 20);
+                let result = interpret_max_cycles(&binary, &mut cpu_state, 20);
+                assert_eq!(result.exit_code(), Some(0), "{}", result.outcome);
                 assert_eq!(
                     cpu_state.registers[#result_register as usize] as u32,
                     #result as u32
@@ -0,0 +1,144 @@
+extern crate toast_interpreter;
+
+mod common;
+
+use common::words_to_bytes;
+use toast_interpreter::{
+    decode_instruction, interpret_max_cycles, Bus, CPUState, Clint, Device, Memory, Rom,
+    RunOutcome, Trap, Uart,
+};
+
+const DEVICE_BASE: u32 = 0x1000_0000;
+
+const ECALL_EXIT: [u32; 2] = [
+    0x00A00893, // addi x17, x0, 10
+    0x00000073, // ecall
+];
+
+/// A peripheral defined outside the crate, every read returns one more than
+/// the last.
+#[derive(Clone)]
+struct Counter {
+    count: u32,
+}
+
+impl Device for Counter {
+    fn size(&self) -> u32 {
+        4
+    }
+
+    fn read(&mut self, _offset: u32, _size: usize) -> Option<u32> {
+        self.count += 1;
+        Some(self.count)
+    }
+
+    fn write(&mut self, _offset: u32, _size: usize, value: u32) -> Option<()> {
+        self.count = value;
+        Some(())
+    }
+}
+
+fn cpu_with_device<D: Device>(device: D) -> CPUState {
+    let mut bus = Bus::new();
+    bus.map(0, Memory::new(4096).unwrap());
+    bus.map(DEVICE_BASE, device);
+    let mut cpu_state = CPUState::with_bus(bus, 0);
+    cpu_state.registers[1] = DEVICE_BASE;
+    cpu_state
+}
+
+#[test]
+fn test_custom_device() {
+    let mut cpu_state = cpu_with_device(Counter { count: 0 });
+    let mut program = vec![
+        0x0000A183, // lw x3, 0(x1)
+        0x0000A203, // lw x4, 0(x1)
+    ];
+    program.extend_from_slice(&ECALL_EXIT);
+    let result = interpret_max_cycles(&words_to_bytes(&program), &mut cpu_state, 20);
+    assert_eq!(result.outcome, RunOutcome::Exited(0));
+    assert_eq!(cpu_state.registers[3], 1);
+    assert_eq!(cpu_state.registers[4], 2);
+    assert_eq!(cpu_state.bus.device::<Counter>().unwrap().count, 2);
+}
+
+#[test]
+fn test_rom_rejects_stores() {
+    let mut cpu_state = cpu_with_device(Rom::new(vec![0; 16]));
+    cpu_state
+        .bus
+        .load(DEVICE_BASE, &[0x78, 0x56, 0x34, 0x12])
+        .unwrap();
+    assert_eq!(cpu_state.read_mem(DEVICE_BASE), Some(0x12345678));
+    // sw x0, 0(x1)
+    let result = interpret_max_cycles(&words_to_bytes(&[0x0000A023]), &mut cpu_state, 20);
+    assert_eq!(
+        result.outcome,
+        RunOutcome::Trapped(Trap::StoreAccessFault {
+            pc: 0,
+            instruction: 0x0000A023,
+            address: DEVICE_BASE
+        })
+    );
+}
+
+#[test]
+fn test_map_over_shadows_memory() {
+    let mut bus = Bus::new();
+    bus.map(0, Memory::new(4096).unwrap());
+    bus.write(0x100, 4, 7).unwrap();
+    bus.write(0x104, 4, 9).unwrap();
+    bus.map_over(0x100, Counter { count: 0 });
+    assert_eq!(bus.read(0x100, 4), Some(1));
+    // only the device's range is shadowed
+    assert_eq!(bus.read(0x104, 4), Some(9));
+    // loads spanning past the device reach the memory beneath it
+    bus.load(0x100, &[1, 2, 3, 4, 5, 6, 7, 8]).unwrap();
+    assert_eq!(bus.read(0x104, 4), Some(0x08070605));
+}
+
+#[test]
+fn test_uart() {
+    let mut uart = Uart::new();
+    uart.push_input(b"x");
+    let mut cpu_state = cpu_with_device(uart);
+    let mut program = vec![
+        0x0050C183, // lbu x3, 5(x1)
+        0x0000C203, // lbu x4, 0(x1)
+    ];
+    program.extend_from_slice(&ECALL_EXIT);
+    let result = interpret_max_cycles(&words_to_bytes(&program), &mut cpu_state, 20);
+    assert_eq!(result.outcome, RunOutcome::Exited(0));
+    assert_eq!(cpu_state.registers[3], 0x61);
+    assert_eq!(cpu_state.registers[4], b'x' as u32);
+
+    cpu_state.bus.write(DEVICE_BASE, 1, b'!' as u32).unwrap();
+    assert_eq!(cpu_state.bus.device::<Uart>().unwrap().output(), b"!");
+}
+
+#[test]
+fn test_clint_timer_interrupt() {
+    let mut clint = Clint::new();
+    clint.mtimecmp = 20;
+    let mut cpu_state = cpu_with_device(clint);
+    let program = [
+        0x04000293, // addi x5, x0, 0x40
+        0x30529073, // csrw mtvec, x5
+        0x08000293, // addi x5, x0, 0x80
+        0x30429073, // csrw mie, x5
+        0x30046073, // csrsi mstatus, 8
+        0x10500073, // wfi
+    ];
+    for (i, word) in program.iter().enumerate() {
+        cpu_state.set_mem(i as u32 * 4, *word).unwrap();
+    }
+    cpu_state.set_mem(0x40, 0x342023F3).unwrap(); // csrr x7, mcause
+
+    for _ in 0..19 {
+        decode_instruction(&mut cpu_state).unwrap();
+    }
+    assert!(cpu_state.waiting_for_interrupt);
+    decode_instruction(&mut cpu_state).unwrap();
+    assert_eq!(cpu_state.pc, 0x44);
+    assert_eq!(cpu_state.registers[7], 0x8000_0007);
+}
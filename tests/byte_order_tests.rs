@@ -0,0 +1,81 @@
+extern crate toast_interpreter;
+
+mod common;
+
+use common::words_to_bytes;
+use toast_interpreter::assembler::assembler::assemble;
+use toast_interpreter::{
+    interpret_file, interpret_file_with_byte_order, ByteOrder, CPUState, RunOutcome,
+};
+
+const PROGRAM: [u32; 3] = [
+    0x00500093, // addi x1, x0, 5
+    0x00A00893, // addi x17, x0, 10
+    0x00000073, // ecall
+];
+
+#[test]
+fn test_assembler_emits_little_endian() {
+    let binary = assemble(&String::from("addi x1, x0, 5\nadd x3, x1, x2"));
+    assert_eq!(binary, words_to_bytes(&[0x00500093, 0x002081B3]));
+    assert_eq!(&binary[..4], &[0x93, 0x00, 0x50, 0x00]);
+}
+
+#[test]
+fn test_legacy_big_endian_conversion() {
+    let legacy: Vec<u8> = PROGRAM.iter().flat_map(|word| word.to_be_bytes()).collect();
+    assert_eq!(
+        ByteOrder::LegacyBigEndian.to_little_endian(&legacy),
+        words_to_bytes(&PROGRAM)
+    );
+    assert_eq!(ByteOrder::LittleEndian.to_little_endian(&legacy), legacy);
+}
+
+fn run_file(name: &str, bytes: &[u8], byte_order: ByteOrder) -> CPUState {
+    let path = std::env::temp_dir().join(name);
+    std::fs::write(&path, bytes).unwrap();
+    let mut cpu_state = CPUState::new();
+    let result =
+        interpret_file_with_byte_order(path.to_str().unwrap(), &mut cpu_state, byte_order, 0);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(result.unwrap().exit_code(), Some(0));
+    cpu_state
+}
+
+#[test]
+fn test_interpret_file_byte_orders() {
+    let cpu_state = run_file(
+        "toast_little_endian.bin",
+        &words_to_bytes(&PROGRAM),
+        ByteOrder::LittleEndian,
+    );
+    assert_eq!(cpu_state.registers[1], 5);
+
+    let legacy: Vec<u8> = PROGRAM.iter().flat_map(|word| word.to_be_bytes()).collect();
+    let cpu_state = run_file("toast_big_endian.bin", &legacy, ByteOrder::LegacyBigEndian);
+    assert_eq!(cpu_state.registers[1], 5);
+}
+
+#[test]
+fn test_interpret_file_cycle_limit() {
+    // counts x1 down from 20, well past any small fixed limit
+    let program = assemble(
+        "addi x1, x0, 20\nloop: addi x1, x1, -1\nbne x1, x0, loop\n\
+         addi x17, x0, 10\necall\n",
+    );
+    let path = std::env::temp_dir().join(format!("toast_cycles_{}.bin", std::process::id()));
+    std::fs::write(&path, &program).unwrap();
+    let file_name = path.to_str().unwrap();
+
+    let mut cpu_state = CPUState::new();
+    let result = interpret_file(file_name, &mut cpu_state).unwrap();
+    assert_eq!(result.outcome, RunOutcome::Exited(0));
+    assert_eq!(result.instructions, 43);
+
+    let mut cpu_state = CPUState::new();
+    let result =
+        interpret_file_with_byte_order(file_name, &mut cpu_state, ByteOrder::LittleEndian, 10)
+            .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(result.outcome, RunOutcome::CycleLimit);
+}
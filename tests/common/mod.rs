@@ -0,0 +1,145 @@
+// Helpers shared by the hand written integration tests
+
+use toast_interpreter::{CPUState, DecodeCache, Engine, Translation};
+
+#[allow(dead_code)]
+pub fn words_to_bytes(words: &[u32]) -> Vec<u8> {
+    words.iter().flat_map(|word| word.to_le_bytes()).collect()
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EngineKind {
+    Interpreter,
+    DecodeCache,
+    Bytecode,
+}
+
+#[allow(dead_code)]
+pub const ENGINES: [EngineKind; 3] = [
+    EngineKind::Interpreter,
+    EngineKind::DecodeCache,
+    EngineKind::Bytecode,
+];
+
+/// `cpu_state` running with the engine `kind`, the bytecode translates the
+/// `len` bytes of program at address 0.
+#[allow(dead_code)]
+pub fn with_engine(mut cpu_state: CPUState, kind: EngineKind, len: u32) -> CPUState {
+    cpu_state.engine = match kind {
+        EngineKind::Interpreter => Engine::Interpreter,
+        EngineKind::DecodeCache => Engine::DecodeCache(DecodeCache::new()),
+        EngineKind::Bytecode => Engine::Bytecode(Translation::new(&mut cpu_state.bus, 0, len)),
+    };
+    cpu_state
+}
+
+/// A PT_LOAD segment for `build_elf`, `memory_size` may exceed the data to
+/// describe a .bss region.
+#[allow(dead_code)]
+pub struct TestSegment {
+    pub address: u32,
+    pub data: Vec<u8>,
+    pub memory_size: u32,
+}
+
+/// Builds a minimal ELF32 RISC-V executable with one program header per
+/// segment and a symbol table containing `symbols`.
+#[allow(dead_code)]
+pub fn build_elf(entry: u32, segments: &[TestSegment], symbols: &[(&str, u32)]) -> Vec<u8> {
+    const EHDR_SIZE: u32 = 52;
+    const PHDR_SIZE: u32 = 32;
+    const SHDR_SIZE: u32 = 40;
+
+    let phoff = EHDR_SIZE;
+    let mut offset = phoff + PHDR_SIZE * segments.len() as u32;
+    let mut data_offsets = vec![];
+    for segment in segments {
+        data_offsets.push(offset);
+        offset += segment.data.len() as u32;
+    }
+
+    let mut strtab = vec![0u8];
+    let mut symtab = vec![0u8; 16]; // the null symbol
+    for (name, value) in symbols {
+        symtab.extend_from_slice(&(strtab.len() as u32).to_le_bytes());
+        symtab.extend_from_slice(&value.to_le_bytes());
+        symtab.extend_from_slice(&0u32.to_le_bytes()); // st_size
+        symtab.extend_from_slice(&[0x10, 0]); // STB_GLOBAL, STT_NOTYPE
+        symtab.extend_from_slice(&1u16.to_le_bytes()); // st_shndx
+        strtab.extend_from_slice(name.as_bytes());
+        strtab.push(0);
+    }
+    let symtab_offset = offset;
+    let strtab_offset = symtab_offset + symtab.len() as u32;
+    let shoff = strtab_offset + strtab.len() as u32;
+
+    let mut elf = vec![0x7F, b'E', b'L', b'F', 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    elf.extend_from_slice(&2u16.to_le_bytes()); // ET_EXEC
+    elf.extend_from_slice(&243u16.to_le_bytes()); // EM_RISCV
+    elf.extend_from_slice(&1u32.to_le_bytes());
+    elf.extend_from_slice(&entry.to_le_bytes());
+    elf.extend_from_slice(&phoff.to_le_bytes());
+    elf.extend_from_slice(&shoff.to_le_bytes());
+    elf.extend_from_slice(&0u32.to_le_bytes()); // e_flags
+    for half in [EHDR_SIZE, PHDR_SIZE, segments.len() as u32, SHDR_SIZE, 3, 0] {
+        elf.extend_from_slice(&(half as u16).to_le_bytes());
+    }
+
+    for (segment, data_offset) in segments.iter().zip(&data_offsets) {
+        let fields = [
+            1, // PT_LOAD
+            *data_offset,
+            segment.address,
+            segment.address,
+            segment.data.len() as u32,
+            segment.memory_size,
+            0b111, // RWX
+            4,
+        ];
+        for field in fields {
+            elf.extend_from_slice(&field.to_le_bytes());
+        }
+    }
+    for segment in segments {
+        elf.extend_from_slice(&segment.data);
+    }
+    elf.extend_from_slice(&symtab);
+    elf.extend_from_slice(&strtab);
+
+    let section_headers = [
+        [0; 10],
+        [0, 2, 0, 0, symtab_offset, symtab.len() as u32, 2, 1, 4, 16], // .symtab
+        [0, 3, 0, 0, strtab_offset, strtab.len() as u32, 0, 0, 1, 0],  // .strtab
+    ];
+    for header in section_headers {
+        for field in header {
+            elf.extend_from_slice(&field.to_le_bytes());
+        }
+    }
+    elf
+}
+
+/// A cloneable in-memory stream, one clone is handed to the code under test
+/// and the other is used to inspect what was written.
+#[allow(dead_code)]
+#[derive(Clone, Default)]
+pub struct SharedBuffer(pub std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+#[allow(dead_code)]
+impl SharedBuffer {
+    pub fn contents(&self) -> Vec<u8> {
+        self.0.borrow().clone()
+    }
+}
+
+impl std::io::Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,439 @@
+extern crate toast_interpreter;
+
+mod common;
+
+use common::{with_engine, EngineKind, ENGINES};
+use toast_interpreter::assembler::assembler::assemble;
+use toast_interpreter::{CPUState, RunOutcome, Trap};
+
+// Every instruction is checked against the RV32IMF semantics, including the
+// edge cases of overflow, shift amounts, division by zero and NaNs, once for
+// each engine.
+
+// The data of the load and store cases
+const DATA: u32 = 0x200;
+
+/// Executes the one instruction `text` at address 0 after `setup` with each
+/// engine, returning the final states.
+fn execute(text: &str, setup: impl Fn(&mut CPUState)) -> Vec<(EngineKind, CPUState)> {
+    let program = assemble(&format!("{}\n", text));
+    ENGINES
+        .into_iter()
+        .map(|kind| {
+            let mut cpu_state = CPUState::new();
+            cpu_state.load(&program).unwrap();
+            setup(&mut cpu_state);
+            let mut cpu_state = with_engine(cpu_state, kind, program.len() as u32);
+            assert_eq!(cpu_state.step(), None, "{} with {:?}", text, kind);
+            (kind, cpu_state)
+        })
+        .collect()
+}
+
+// x3 after the instruction with x1 and x2 set
+const INTEGER: &[(&str, u32, u32, u32)] = &[
+    ("add x3, x1, x2", 0xFFFF_FFFF, 1, 0),
+    ("add x3, x1, x2", 0x7FFF_FFFF, 1, 0x8000_0000),
+    ("sub x3, x1, x2", 0, 1, 0xFFFF_FFFF),
+    ("sub x3, x1, x2", 0x8000_0000, 1, 0x7FFF_FFFF),
+    ("sll x3, x1, x2", 1, 31, 0x8000_0000),
+    ("sll x3, x1, x2", 1, 33, 2),
+    ("srl x3, x1, x2", 0x8000_0000, 4, 0x0800_0000),
+    ("srl x3, x1, x2", 0x8000_0000, 36, 0x0800_0000),
+    ("sra x3, x1, x2", 0x8000_0000, 4, 0xF800_0000),
+    ("sra x3, x1, x2", 0x8000_0000, 0xFFFF_FFE4, 0xF800_0000),
+    ("sra x3, x1, x2", 0x4000_0000, 4, 0x0400_0000),
+    ("slt x3, x1, x2", 0xFFFF_FFFF, 1, 1),
+    ("slt x3, x1, x2", 1, 0xFFFF_FFFF, 0),
+    ("sltu x3, x1, x2", 0xFFFF_FFFF, 1, 0),
+    ("sltu x3, x1, x2", 1, 0xFFFF_FFFF, 1),
+    ("xor x3, x1, x2", 0xFF00_FF00, 0x0FF0_0FF0, 0xF0F0_F0F0),
+    ("or x3, x1, x2", 0xFF00_FF00, 0x0FF0_0FF0, 0xFFF0_FFF0),
+    ("and x3, x1, x2", 0xFF00_FF00, 0x0FF0_0FF0, 0x0F00_0F00),
+    ("mul x3, x1, x2", 0x8000_0000, 2, 0),
+    ("mul x3, x1, x2", 0xFFFF_FFFF, 0xFFFF_FFFF, 1),
+    ("mulh x3, x1, x2", 0xFFFF_FFFF, 0xFFFF_FFFF, 0),
+    ("mulh x3, x1, x2", 0x8000_0000, 0x8000_0000, 0x4000_0000),
+    ("mulhsu x3, x1, x2", 0xFFFF_FFFF, 0xFFFF_FFFF, 0xFFFF_FFFF),
+    ("mulhu x3, x1, x2", 0xFFFF_FFFF, 0xFFFF_FFFF, 0xFFFF_FFFE),
+    ("div x3, x1, x2", 0xFFFF_FFF9, 2, 0xFFFF_FFFD),
+    ("div x3, x1, x2", 7, 0, 0xFFFF_FFFF),
+    ("div x3, x1, x2", 0x8000_0000, 0xFFFF_FFFF, 0x8000_0000),
+    ("divu x3, x1, x2", 0xFFFF_FFF9, 2, 0x7FFF_FFFC),
+    ("divu x3, x1, x2", 7, 0, 0xFFFF_FFFF),
+    ("rem x3, x1, x2", 0xFFFF_FFF9, 2, 0xFFFF_FFFF),
+    ("rem x3, x1, x2", 7, 0, 7),
+    ("rem x3, x1, x2", 0x8000_0000, 0xFFFF_FFFF, 0),
+    ("remu x3, x1, x2", 0xFFFF_FFF9, 2, 1),
+    ("remu x3, x1, x2", 7, 0, 7),
+    ("addi x3, x1, -1", 0, 0, 0xFFFF_FFFF),
+    ("addi x3, x1, 1", 0xFFFF_FFFF, 0, 0),
+    ("addi x3, x1, -2048", 0, 0, 0xFFFF_F800),
+    ("slti x3, x1, -4", 0xFFFF_FFFB, 0, 1),
+    ("slti x3, x1, -4", 4, 0, 0),
+    ("sltiu x3, x1, -1", 5, 0, 1),
+    ("xori x3, x1, -1", 0x1234_5678, 0, 0xEDCB_A987),
+    ("ori x3, x1, -16", 5, 0, 0xFFFF_FFF5),
+    ("andi x3, x1, -16", 0xFFFF_FFFF, 0, 0xFFFF_FFF0),
+    ("slli x3, x1, 31", 3, 0, 0x8000_0000),
+    ("srli x3, x1, 31", 0x8000_0000, 0, 1),
+    ("srai x3, x1, 31", 0x8000_0000, 0, 0xFFFF_FFFF),
+    ("srai x3, x1, 4", 0x7000_0000, 0, 0x0700_0000),
+    ("lui x3, 0xFFFFF", 0, 0, 0xFFFF_F000),
+    ("auipc x3, 1", 0, 0, 0x1000),
+    // writes to x0 are discarded
+    ("addi x0, x1, 1", 1, 0, 0),
+];
+
+#[test]
+fn test_integer_instructions() {
+    for &(text, x1, x2, expected) in INTEGER {
+        for (kind, cpu_state) in execute(text, |cpu_state| {
+            cpu_state.registers[1] = x1;
+            cpu_state.registers[2] = x2;
+        }) {
+            let rd = if text.contains("x0,") { 0 } else { 3 };
+            assert_eq!(
+                cpu_state.registers[rd], expected,
+                "{} with x1 = {:#x}, x2 = {:#x} on {:?}",
+                text, x1, x2, kind
+            );
+        }
+    }
+}
+
+// x3 loaded from around DATA, holding the bytes 01 7F FF 80
+const LOADS: &[(&str, u32)] = &[
+    ("lb x3, -4(x1)", 0x0000_0001),
+    ("lb x3, -3(x1)", 0x0000_007F),
+    ("lb x3, -2(x1)", 0xFFFF_FFFF),
+    ("lb x3, -1(x1)", 0xFFFF_FF80),
+    ("lbu x3, -1(x1)", 0x0000_0080),
+    ("lh x3, -4(x1)", 0x0000_7F01),
+    ("lh x3, -2(x1)", 0xFFFF_80FF),
+    ("lhu x3, -2(x1)", 0x0000_80FF),
+    ("lw x3, -4(x1)", 0x80FF_7F01),
+];
+
+#[test]
+fn test_loads() {
+    for &(text, expected) in LOADS {
+        for (kind, cpu_state) in execute(text, |cpu_state| {
+            cpu_state.set_mem(DATA, 0x80FF_7F01).unwrap();
+            cpu_state.registers[1] = DATA + 4;
+        }) {
+            assert_eq!(cpu_state.registers[3], expected, "{} on {:?}", text, kind);
+        }
+    }
+}
+
+// The word at DATA after storing x2 = 0x12345678 over 0xAAAAAAAA
+const STORES: &[(&str, u32)] = &[
+    ("sb x2, -4(x1)", 0xAAAA_AA78),
+    ("sb x2, -1(x1)", 0x78AA_AAAA),
+    ("sh x2, -2(x1)", 0x5678_AAAA),
+    ("sw x2, -4(x1)", 0x1234_5678),
+];
+
+#[test]
+fn test_stores() {
+    for &(text, expected) in STORES {
+        for (kind, mut cpu_state) in execute(text, |cpu_state| {
+            cpu_state.set_mem(DATA, 0xAAAA_AAAA).unwrap();
+            cpu_state.registers[1] = DATA + 4;
+            cpu_state.registers[2] = 0x1234_5678;
+        }) {
+            assert_eq!(
+                cpu_state.read_mem(DATA),
+                Some(expected),
+                "{} on {:?}",
+                text,
+                kind
+            );
+        }
+    }
+}
+
+// Whether the branch to 16 is taken with x1 and x2 set
+const BRANCHES: &[(&str, u32, u32, bool)] = &[
+    ("beq x1, x2, 16", 5, 5, true),
+    ("beq x1, x2, 16", 5, 6, false),
+    ("bne x1, x2, 16", 5, 6, true),
+    ("bne x1, x2, 16", 5, 5, false),
+    ("blt x1, x2, 16", 0xFFFF_FFFF, 1, true),
+    ("blt x1, x2, 16", 1, 0xFFFF_FFFF, false),
+    ("bge x1, x2, 16", 1, 0xFFFF_FFFF, true),
+    ("bge x1, x2, 16", 5, 5, true),
+    ("bge x1, x2, 16", 0xFFFF_FFFF, 1, false),
+    ("bltu x1, x2, 16", 1, 0xFFFF_FFFF, true),
+    ("bltu x1, x2, 16", 0xFFFF_FFFF, 1, false),
+    ("bgeu x1, x2, 16", 0xFFFF_FFFF, 1, true),
+    ("bgeu x1, x2, 16", 1, 0xFFFF_FFFF, false),
+];
+
+#[test]
+fn test_branches() {
+    for &(text, x1, x2, taken) in BRANCHES {
+        for (kind, cpu_state) in execute(text, |cpu_state| {
+            cpu_state.registers[1] = x1;
+            cpu_state.registers[2] = x2;
+        }) {
+            let expected = if taken { 16 } else { 4 };
+            assert_eq!(cpu_state.pc, expected, "{} on {:?}", text, kind);
+        }
+    }
+}
+
+#[test]
+fn test_jumps() {
+    for (kind, cpu_state) in execute("jal x3, 12", |_| {}) {
+        assert_eq!(
+            (cpu_state.pc, cpu_state.registers[3]),
+            (12, 4),
+            "{:?}",
+            kind
+        );
+    }
+    // the lowest bit of the target is cleared
+    for (kind, cpu_state) in execute("jalr x3, -3(x1)", |cpu_state| {
+        cpu_state.registers[1] = 0x107
+    }) {
+        assert_eq!(
+            (cpu_state.pc, cpu_state.registers[3]),
+            (0x104, 4),
+            "{:?}",
+            kind
+        );
+    }
+}
+
+const ONE: u32 = 0x3F80_0000;
+const MINUS_ONE: u32 = 0xBF80_0000;
+const MINUS_TWO: u32 = 0xC000_0000;
+const ZERO: u32 = 0x0000_0000;
+const MINUS_ZERO: u32 = 0x8000_0000;
+const INFINITY: u32 = 0x7F80_0000;
+const MINUS_INFINITY: u32 = 0xFF80_0000;
+const QUIET_NAN: u32 = 0x7FC0_0000;
+const SIGNALING_NAN: u32 = 0x7F80_0001;
+const SUBNORMAL: u32 = 0x0000_0001;
+const ONE_AND_A_HALF: u32 = 0x3FC0_0000;
+const TWO_AND_A_HALF: u32 = 0x4020_0000;
+const MAX_FINITE: u32 = 0x7F7F_FFFF;
+
+// The bits of f3, or of x3 for instructions writing an integer register,
+// with f1, f2 and x1 set
+const FLOAT: &[(&str, u32, u32, u32, u32)] = &[
+    ("fadd.s f3, f1, f2", ONE, ONE, 0, 0x4000_0000),
+    ("fsub.s f3, f1, f2", ONE, ONE, 0, ZERO),
+    ("fmul.s f3, f1, f2", MINUS_ONE, MINUS_TWO, 0, 0x4000_0000),
+    ("fdiv.s f3, f1, f2", ONE, ZERO, 0, INFINITY),
+    ("fsqrt.s f3, f1", 0x4080_0000, 0, 0, 0x4000_0000),
+    ("fsgnj.s f3, f1, f2", ONE, MINUS_ZERO, 0, MINUS_ONE),
+    ("fsgnjn.s f3, f1, f2", ONE, MINUS_ZERO, 0, ONE),
+    ("fsgnjx.s f3, f1, f2", MINUS_ONE, MINUS_ONE, 0, ONE),
+    ("fsgnj.s f3, f1, f2", QUIET_NAN, MINUS_ONE, 0, 0xFFC0_0000),
+    ("fmin.s f3, f1, f2", ZERO, MINUS_ZERO, 0, MINUS_ZERO),
+    ("fmax.s f3, f1, f2", MINUS_ZERO, ZERO, 0, ZERO),
+    ("fmin.s f3, f1, f2", QUIET_NAN, ONE, 0, ONE),
+    ("fmax.s f3, f1, f2", ONE, QUIET_NAN, 0, ONE),
+    ("fmin.s f3, f1, f2", QUIET_NAN, SIGNALING_NAN, 0, QUIET_NAN),
+    ("feq.s x3, f1, f2", ONE, ONE, 0, 1),
+    ("feq.s x3, f1, f2", QUIET_NAN, QUIET_NAN, 0, 0),
+    ("flt.s x3, f1, f2", MINUS_ONE, ONE, 0, 1),
+    ("fle.s x3, f1, f2", ZERO, MINUS_ZERO, 0, 1),
+    ("fcvt.w.s x3, f1", MINUS_TWO, 0, 0, 0xFFFF_FFFE),
+    ("fcvt.w.s x3, f1", QUIET_NAN, 0, 0, 0x7FFF_FFFF),
+    ("fcvt.w.s x3, f1", MINUS_INFINITY, 0, 0, 0x8000_0000),
+    ("fcvt.wu.s x3, f1", MINUS_ONE, 0, 0, 0),
+    ("fcvt.wu.s x3, f1", QUIET_NAN, 0, 0, 0xFFFF_FFFF),
+    ("fcvt.s.w f3, x1", 0, 0, 0xFFFF_FFFE, MINUS_TWO),
+    ("fcvt.s.wu f3, x1", 0, 0, 1, ONE),
+    ("fmv.x.w x3, f1", MINUS_ONE, 0, 0, MINUS_ONE),
+    ("fmv.w.x f3, x1", 0, 0, SIGNALING_NAN, SIGNALING_NAN),
+    ("fclass.s x3, f1", MINUS_INFINITY, 0, 0, 1 << 0),
+    ("fclass.s x3, f1", MINUS_ONE, 0, 0, 1 << 1),
+    ("fclass.s x3, f1", SUBNORMAL | MINUS_ZERO, 0, 0, 1 << 2),
+    ("fclass.s x3, f1", MINUS_ZERO, 0, 0, 1 << 3),
+    ("fclass.s x3, f1", ZERO, 0, 0, 1 << 4),
+    ("fclass.s x3, f1", SUBNORMAL, 0, 0, 1 << 5),
+    ("fclass.s x3, f1", ONE, 0, 0, 1 << 6),
+    ("fclass.s x3, f1", INFINITY, 0, 0, 1 << 7),
+    ("fclass.s x3, f1", SIGNALING_NAN, 0, 0, 1 << 8),
+    ("fclass.s x3, f1", QUIET_NAN, 0, 0, 1 << 9),
+    ("fmadd.s f3, f1, f2, f1", ONE, MINUS_TWO, 0, MINUS_ONE),
+    ("fmsub.s f3, f1, f2, f1", ONE, MINUS_TWO, 0, 0xC040_0000),
+    ("fnmsub.s f3, f1, f2, f1", ONE, MINUS_TWO, 0, 0x4040_0000),
+    ("fnmadd.s f3, f1, f2, f1", ONE, MINUS_TWO, 0, ONE),
+];
+
+#[test]
+fn test_float_instructions() {
+    for &(text, f1, f2, x1, expected) in FLOAT {
+        for (kind, cpu_state) in execute(text, |cpu_state| {
+            cpu_state.floating_point_registers[1] = f32::from_bits(f1);
+            cpu_state.floating_point_registers[2] = f32::from_bits(f2);
+            cpu_state.registers[1] = x1;
+        }) {
+            let result = if text.contains(" x3") {
+                cpu_state.registers[3]
+            } else {
+                cpu_state.floating_point_registers[3].to_bits()
+            };
+            assert_eq!(
+                result, expected,
+                "{} with f1 = {:#x}, f2 = {:#x}, x1 = {:#x} on {:?}",
+                text, f1, f2, x1, kind
+            );
+        }
+    }
+}
+
+#[test]
+fn test_float_loads_and_stores_keep_bits() {
+    for (kind, cpu_state) in execute("flw f3, 0(x1)", |cpu_state| {
+        cpu_state.set_mem(DATA, SIGNALING_NAN).unwrap();
+        cpu_state.registers[1] = DATA;
+    }) {
+        assert_eq!(
+            cpu_state.floating_point_registers[3].to_bits(),
+            SIGNALING_NAN,
+            "{:?}",
+            kind
+        );
+    }
+    for (kind, mut cpu_state) in execute("fsw f2, 4(x1)", |cpu_state| {
+        cpu_state.floating_point_registers[2] = f32::from_bits(MINUS_ONE);
+        cpu_state.registers[1] = DATA - 4;
+    }) {
+        assert_eq!(cpu_state.read_mem(DATA), Some(MINUS_ONE), "{:?}", kind);
+    }
+}
+
+// Rounding modes
+const RNE: u32 = 0;
+const RTZ: u32 = 1;
+const RDN: u32 = 2;
+const RUP: u32 = 3;
+const RMM: u32 = 4;
+
+// Exception flags
+const NX: u32 = 1 << 0;
+const UF: u32 = 1 << 1;
+const OF: u32 = 1 << 2;
+const DZ: u32 = 1 << 3;
+const NV: u32 = 1 << 4;
+
+// As FLOAT, with frm set first and the fflags the instruction raises. The
+// instructions without a rounding mode use the one in frm.
+#[rustfmt::skip]
+const ROUNDING: &[(&str, u32, u32, u32, u32, u32, u32)] = &[
+    ("fcvt.w.s x3, f1", RNE, ONE_AND_A_HALF, 0, 0, 2, NX),
+    ("fcvt.w.s x3, f1", RNE, TWO_AND_A_HALF, 0, 0, 2, NX),
+    ("fcvt.w.s x3, f1", RMM, TWO_AND_A_HALF, 0, 0, 3, NX),
+    ("fcvt.w.s x3, f1, rmm", RNE, TWO_AND_A_HALF, 0, 0, 3, NX),
+    ("fcvt.w.s x3, f1, rtz", RNE, ONE_AND_A_HALF, 0, 0, 1, NX),
+    ("fcvt.w.s x3, f1, rdn", RNE, ONE_AND_A_HALF | MINUS_ZERO, 0, 0, 0xFFFF_FFFE, NX),
+    ("fcvt.w.s x3, f1, rup", RNE, 0x3FA0_0000, 0, 0, 2, NX),
+    ("fcvt.w.s x3, f1", RTZ, MINUS_TWO, 0, 0, 0xFFFF_FFFE, 0),
+    ("fcvt.w.s x3, f1", RNE, SIGNALING_NAN, 0, 0, 0x7FFF_FFFF, NV),
+    ("fcvt.w.s x3, f1", RNE, 0x4F00_0000, 0, 0, 0x7FFF_FFFF, NV),
+    ("fcvt.w.s x3, f1", RNE, 0xCF00_0000, 0, 0, 0x8000_0000, 0),
+    ("fcvt.wu.s x3, f1", RNE, ONE_AND_A_HALF, 0, 0, 2, NX),
+    ("fcvt.wu.s x3, f1, rtz", RNE, 0xBF00_0000, 0, 0, 0, NX),
+    ("fcvt.wu.s x3, f1", RNE, MINUS_ONE, 0, 0, 0, NV),
+    ("fcvt.s.w f3, x1", RNE, 0, 0, 0x0100_0001, 0x4B80_0000, NX),
+    ("fcvt.s.w f3, x1", RUP, 0, 0, 0x0100_0001, 0x4B80_0001, NX),
+    ("fcvt.s.wu f3, x1, rtz", RNE, 0, 0, 0xFFFF_FFFF, 0x4F7F_FFFF, NX),
+    // 1 + 2^-24 is halfway between 1 and the next float
+    ("fadd.s f3, f1, f2", RNE, ONE, 0x3380_0000, 0, ONE, NX),
+    ("fadd.s f3, f1, f2, rup", RNE, ONE, 0x3380_0000, 0, 0x3F80_0001, NX),
+    ("fadd.s f3, f1, f2", RNE, ONE, ONE, 0, 0x4000_0000, 0),
+    ("fsub.s f3, f1, f2, rdn", RNE, ONE, ONE, 0, MINUS_ZERO, 0),
+    ("fadd.s f3, f1, f2", RNE, SIGNALING_NAN, ONE, 0, QUIET_NAN, NV),
+    ("fadd.s f3, f1, f2", RNE, INFINITY, MINUS_INFINITY, 0, QUIET_NAN, NV),
+    ("fmul.s f3, f1, f2", RNE, MAX_FINITE, MINUS_TWO, 0, MINUS_INFINITY, OF | NX),
+    ("fmul.s f3, f1, f2", RTZ, MAX_FINITE, 0x4000_0000, 0, MAX_FINITE, OF | NX),
+    ("fmul.s f3, f1, f2", RNE, 0x0080_0001, 0x3F00_0000, 0, 0x0040_0000, UF | NX),
+    ("fmul.s f3, f1, f2", RNE, 0x0080_0000, 0x3F00_0000, 0, 0x0040_0000, 0),
+    ("fmul.s f3, f1, f2", RNE, INFINITY, ZERO, 0, QUIET_NAN, NV),
+    ("fdiv.s f3, f1, f2", RNE, ONE, MINUS_ZERO, 0, MINUS_INFINITY, DZ),
+    ("fdiv.s f3, f1, f2", RNE, ZERO, ZERO, 0, QUIET_NAN, NV),
+    ("fdiv.s f3, f1, f2", RNE, ONE, 0x4040_0000, 0, 0x3EAA_AAAB, NX),
+    ("fdiv.s f3, f1, f2", RTZ, ONE, 0x4040_0000, 0, 0x3EAA_AAAA, NX),
+    ("fsqrt.s f3, f1", RNE, MINUS_ONE, 0, 0, QUIET_NAN, NV),
+    ("fsqrt.s f3, f1", RNE, MINUS_ZERO, 0, 0, MINUS_ZERO, 0),
+    ("fsqrt.s f3, f1", RNE, 0x4000_0000, 0, 0, 0x3FB5_04F3, NX),
+    ("fsqrt.s f3, f1", RDN, 0x4000_0000, 0, 0, 0x3FB5_04F3, NX),
+    ("fsqrt.s f3, f1", RUP, 0x4000_0000, 0, 0, 0x3FB5_04F4, NX),
+    ("fmadd.s f3, f1, f2, f1", RNE, INFINITY, ZERO, 0, QUIET_NAN, NV),
+    ("fmadd.s f3, f1, f2, f2", RNE, 0x3F80_0001, 0x3F80_0001, 0, 0x4000_0002, NX),
+    ("feq.s x3, f1, f2", RNE, QUIET_NAN, ONE, 0, 0, 0),
+    ("feq.s x3, f1, f2", RNE, SIGNALING_NAN, ONE, 0, 0, NV),
+    ("flt.s x3, f1, f2", RNE, QUIET_NAN, ONE, 0, 0, NV),
+    ("fle.s x3, f1, f2", RNE, ONE, QUIET_NAN, 0, 0, NV),
+    ("fmin.s f3, f1, f2", RNE, SIGNALING_NAN, ONE, 0, ONE, NV),
+    ("fmax.s f3, f1, f2", RNE, QUIET_NAN, ONE, 0, ONE, 0),
+    ("fsgnj.s f3, f1, f2", RNE, SIGNALING_NAN, ONE, 0, SIGNALING_NAN, 0),
+];
+
+#[test]
+fn test_float_rounding_and_flags() {
+    for &(text, frm, f1, f2, x1, expected, flags) in ROUNDING {
+        for (kind, cpu_state) in execute(text, |cpu_state| {
+            cpu_state.csrs.fcsr = frm << 5;
+            cpu_state.floating_point_registers[1] = f32::from_bits(f1);
+            cpu_state.floating_point_registers[2] = f32::from_bits(f2);
+            cpu_state.registers[1] = x1;
+        }) {
+            let result = if text.contains(" x3") {
+                cpu_state.registers[3]
+            } else {
+                cpu_state.floating_point_registers[3].to_bits()
+            };
+            assert_eq!(
+                (result, cpu_state.csrs.fcsr),
+                (expected, frm << 5 | flags),
+                "{} with frm = {}, f1 = {:#x}, f2 = {:#x}, x1 = {:#x} on {:?}",
+                text,
+                frm,
+                f1,
+                f2,
+                x1,
+                kind
+            );
+        }
+    }
+}
+
+#[test]
+fn test_float_flags_accrue() {
+    for (kind, cpu_state) in execute("fdiv.s f3, f1, f2", |cpu_state| {
+        cpu_state.csrs.fcsr = DZ;
+        cpu_state.floating_point_registers[1] = f32::from_bits(ONE);
+        cpu_state.floating_point_registers[2] = f32::from_bits(0x4040_0000);
+    }) {
+        assert_eq!(cpu_state.csrs.fcsr, DZ | NX, "{:?}", kind);
+    }
+}
+
+#[test]
+fn test_reserved_frm_is_illegal() {
+    let program = assemble(&String::from("fadd.s f3, f1, f2\n"));
+    let word = u32::from_le_bytes(program[..4].try_into().unwrap());
+    for kind in ENGINES {
+        let mut cpu_state = CPUState::new();
+        cpu_state.load(&program).unwrap();
+        cpu_state.csrs.fcsr = 5 << 5;
+        let mut cpu_state = with_engine(cpu_state, kind, program.len() as u32);
+        assert_eq!(
+            cpu_state.step(),
+            Some(RunOutcome::Trapped(Trap::IllegalInstruction {
+                pc: 0,
+                instruction: word,
+            })),
+            "{:?}",
+            kind
+        );
+    }
+}
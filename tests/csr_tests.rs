@@ -0,0 +1,79 @@
+extern crate toast_interpreter;
+
+mod common;
+
+use common::words_to_bytes;
+use toast_interpreter::csr::{MISA, MSCRATCH};
+use toast_interpreter::{interpret_max_cycles, CPUState, RunOutcome, Trap};
+
+const ECALL_EXIT: [u32; 2] = [
+    0x00A00893, // addi x17, x0, 10
+    0x00000073, // ecall
+];
+
+#[test]
+fn test_csr_read_write_set_clear() {
+    let mut program = vec![
+        0x05500113, // addi x2, x0, 0x55
+        0x340110F3, // csrrw x1, mscratch, x2
+        0x340021F3, // csrrs x3, mscratch, x0
+        0x34016273, // csrrsi x4, mscratch, 2
+        0x3400F2F3, // csrrci x5, mscratch, 1
+        0x34002373, // csrrs x6, mscratch, x0
+        0x301023F3, // csrrs x7, misa, x0
+    ];
+    program.extend_from_slice(&ECALL_EXIT);
+    let mut cpu_state = CPUState::new();
+    let result = interpret_max_cycles(&words_to_bytes(&program), &mut cpu_state, 20);
+    assert_eq!(result.outcome, RunOutcome::Exited(0));
+    assert_eq!(cpu_state.registers[1], 0);
+    assert_eq!(cpu_state.registers[3], 0x55);
+    assert_eq!(cpu_state.registers[4], 0x55);
+    assert_eq!(cpu_state.registers[5], 0x57);
+    assert_eq!(cpu_state.registers[6], 0x56);
+    assert_eq!(cpu_state.registers[7], cpu_state.csrs.read(MISA).unwrap());
+    assert_eq!(cpu_state.csrs.read(MSCRATCH), Some(0x56));
+}
+
+#[test]
+fn test_counters() {
+    let mut program = vec![
+        0x00000013, // nop
+        0xC0002473, // rdcycle x8
+        0xC02024F3, // rdinstret x9
+    ];
+    program.extend_from_slice(&ECALL_EXIT);
+    let mut cpu_state = CPUState::new();
+    let result = interpret_max_cycles(&words_to_bytes(&program), &mut cpu_state, 20);
+    assert_eq!(result.outcome, RunOutcome::Exited(0));
+    assert_eq!(cpu_state.registers[8], 1);
+    assert_eq!(cpu_state.registers[9], 2);
+}
+
+#[test]
+fn test_read_only_csr_write_traps() {
+    let mut cpu_state = CPUState::new();
+    // csrrw x0, cycle, x1
+    let result = interpret_max_cycles(&words_to_bytes(&[0xC0009073]), &mut cpu_state, 20);
+    assert_eq!(
+        result.outcome,
+        RunOutcome::Trapped(Trap::IllegalInstruction {
+            pc: 0,
+            instruction: 0xC0009073
+        })
+    );
+}
+
+#[test]
+fn test_unknown_csr_traps() {
+    let mut cpu_state = CPUState::new();
+    // csrrs x10, 0x7c0, x0
+    let result = interpret_max_cycles(&words_to_bytes(&[0x7C002573]), &mut cpu_state, 20);
+    assert_eq!(
+        result.outcome,
+        RunOutcome::Trapped(Trap::IllegalInstruction {
+            pc: 0,
+            instruction: 0x7C002573
+        })
+    );
+}
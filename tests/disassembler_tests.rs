@@ -0,0 +1,113 @@
+extern crate toast_interpreter;
+
+mod common;
+
+use common::words_to_bytes;
+use toast_interpreter::{disassemble, disassemble_at};
+
+// Encodings produced by llvm-mc -triple=riscv32 -mattr=+m,+f
+const INSTRUCTIONS: [(u32, &str); 43] = [
+    (0x00500513, "addi a0,zero,5"),
+    (0xFF010113, "addi sp,sp,-16"),
+    (0xFFF32293, "slti t0,t1,-1"),
+    (0x7FF33293, "sltiu t0,t1,2047"),
+    (0x80064593, "xori a1,a2,-2048"),
+    (0x40355513, "srai a0,a0,3"),
+    (0x01F49413, "slli s0,s1,31"),
+    (0x12345537, "lui a0,0x12345"),
+    (0xFFFFF097, "auipc ra,0xfffff"),
+    (0x00412503, "lw a0,4(sp)"),
+    (0xFFF7CF83, "lbu t6,-1(a5)"),
+    (0x00112623, "sw ra,12(sp)"),
+    (0xFEAD8023, "sb a0,-32(s11)"),
+    (0x000500E7, "jalr ra,0(a0)"),
+    (0x00C58533, "add a0,a1,a2"),
+    (0x41EE8E33, "sub t3,t4,t5"),
+    (0x4149D933, "sra s2,s3,s4"),
+    (0x02C58533, "mul a0,a1,a2"),
+    (0x02C5A533, "mulhsu a0,a1,a2"),
+    (0x03FDFD33, "remu s10,s11,t6"),
+    (0x00000073, "ecall"),
+    (0x00100073, "ebreak"),
+    (0x30200073, "mret"),
+    (0x10500073, "wfi"),
+    (0x0FF0000F, "fence iorw,iorw"),
+    (0x0210000F, "fence r,w"),
+    (0x30059573, "csrrw a0,mstatus,a1"),
+    (0x7C0022F3, "csrrs t0,0x7c0,zero"),
+    (0x30447073, "csrrci zero,mie,8"),
+    (0x00812507, "flw fa0,8(sp)"),
+    (0xFFF52E27, "fsw ft11,-4(a0)"),
+    (0x00C5F553, "fadd.s fa0,fa1,fa2"),
+    (0x00C59553, "fadd.s fa0,fa1,fa2,rtz"),
+    (0x2124A453, "fsgnjx.s fs0,fs1,fs2"),
+    (0xA0B52553, "feq.s a0,fa0,fa1"),
+    (0x5800F053, "fsqrt.s ft0,ft1"),
+    (0xC0051553, "fcvt.w.s a0,fa0,rtz"),
+    (0xD015F553, "fcvt.s.wu fa0,a1"),
+    (0xE0000553, "fmv.x.w a0,ft0"),
+    (0xF0050053, "fmv.w.x ft0,a0"),
+    (0xE00D92D3, "fclass.s t0,fs11"),
+    (0x68C5F543, "fmadd.s fa0,fa1,fa2,fa3"),
+    (0xF9EE8E4B, "fnmsub.s ft8,ft9,ft10,ft11,rne"),
+];
+
+#[test]
+fn test_instructions() {
+    for (word, expected) in INSTRUCTIONS {
+        let lines = disassemble(&word.to_le_bytes());
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].to_string(), expected, "{:#010x}", word);
+    }
+}
+
+#[test]
+fn test_branch_labels() {
+    let program = [
+        0x00300513, // addi a0, zero, 3
+        0xFFF50513, // addi a0, a0, -1
+        0xFE051EE3, // bne a0, zero, -4
+        0x008000EF, // jal ra, 8
+        0xFE0008E3, // beq zero, zero, -16
+        0x00000073, // ecall
+    ];
+    let lines = disassemble(&words_to_bytes(&program));
+    let listing: Vec<String> = lines.iter().map(|line| line.to_string()).collect();
+    assert_eq!(
+        listing.join("\n"),
+        "L0:\naddi a0,zero,3\n\
+         L1:\naddi a0,a0,-1\n\
+         bne a0,zero,L1\n\
+         jal ra,L2\n\
+         beq zero,zero,L0\n\
+         L2:\necall"
+    );
+    assert_eq!(lines[2].target, Some(4));
+    assert_eq!(lines[3].target, Some(0x14));
+    assert_eq!(lines[5].address, 0x14);
+}
+
+#[test]
+fn test_target_outside_listing() {
+    let lines = disassemble_at(&0xFF9FF06Fu32.to_le_bytes(), 0x1000);
+    assert_eq!(lines[0].address, 0x1000);
+    assert_eq!(lines[0].label, None);
+    assert_eq!(lines[0].target, Some(0xFF8));
+    assert_eq!(lines[0].to_string(), "jal zero,0xff8");
+}
+
+#[test]
+fn test_undecodable() {
+    let mut bytes = 0xFFFFFFFFu32.to_le_bytes().to_vec();
+    // fadd.s with the reserved rounding mode 5
+    bytes.extend(0x00C5D553u32.to_le_bytes());
+    bytes.extend([0x13, 0x05]);
+    let lines = disassemble(&bytes);
+    let listing: Vec<String> = lines.iter().map(|line| line.to_string()).collect();
+    assert_eq!(
+        listing,
+        [".word 0xffffffff", ".word 0x00c5d553", ".byte 0x13,0x05"]
+    );
+    assert_eq!(lines[2].address, 8);
+    assert_eq!(lines[2].word, 0x0513);
+}
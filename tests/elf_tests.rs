@@ -0,0 +1,96 @@
+extern crate toast_interpreter;
+
+mod common;
+
+use common::{build_elf, words_to_bytes, TestSegment};
+use toast_interpreter::{interpret, CPUState, Elf, ElfError, MemoryConfig, RunOutcome};
+
+const BASE: u32 = 0x8000_0000;
+const DATA: u32 = BASE + 0x1000;
+
+fn test_program() -> Vec<u8> {
+    let text = words_to_bytes(&[
+        0x00001097, // auipc x1, 1
+        0x0000A183, // lw x3, 0(x1)
+        0x0040A203, // lw x4, 4(x1)
+        0x00A00893, // addi x17, x0, 10
+        0x00000073, // ecall
+    ]);
+    build_elf(
+        BASE,
+        &[
+            TestSegment {
+                address: BASE,
+                memory_size: text.len() as u32,
+                data: text,
+            },
+            // value is initialised, counter lives in .bss
+            TestSegment {
+                address: DATA,
+                data: words_to_bytes(&[0x1234_5678]),
+                memory_size: 8,
+            },
+        ],
+        &[("_start", BASE), ("value", DATA), ("counter", DATA + 4)],
+    )
+}
+
+fn cpu_state() -> CPUState {
+    CPUState::with_memory(MemoryConfig {
+        base: BASE,
+        size: 0x10000,
+    })
+    .unwrap()
+}
+
+#[test]
+fn test_elf_load_and_run() {
+    let elf = Elf::parse(&test_program()).unwrap();
+    let mut cpu_state = cpu_state();
+    cpu_state.pc = 0;
+    // stale contents in .bss must be cleared by the loader
+    cpu_state.set_mem(DATA + 4, 0xFFFF_FFFF).unwrap();
+    elf.load(&mut cpu_state).unwrap();
+    assert_eq!(cpu_state.pc, BASE);
+
+    let result = interpret(&[], &mut cpu_state);
+    assert_eq!(result.outcome, RunOutcome::Exited(0));
+    assert_eq!(cpu_state.registers[3], 0x1234_5678);
+    assert_eq!(cpu_state.registers[4], 0);
+}
+
+#[test]
+fn test_elf_symbols() {
+    let elf = Elf::parse(&test_program()).unwrap();
+    assert_eq!(elf.entry, BASE);
+    assert_eq!(elf.symbol("_start"), Some(BASE));
+    assert_eq!(elf.symbol("counter"), Some(DATA + 4));
+    assert_eq!(elf.symbol("missing"), None);
+    assert_eq!(elf.symbols().count(), 3);
+}
+
+#[test]
+fn test_elf_rejects_bad_input() {
+    assert_eq!(Elf::parse(b"not an elf").unwrap_err(), ElfError::NotElf);
+    let program = test_program();
+    assert_eq!(Elf::parse(&program[..40]).unwrap_err(), ElfError::Truncated);
+    let mut big_endian = program.clone();
+    big_endian[5] = 2;
+    assert!(matches!(
+        Elf::parse(&big_endian),
+        Err(ElfError::Unsupported(_))
+    ));
+}
+
+#[test]
+fn test_elf_segment_outside_memory() {
+    let elf = Elf::parse(&test_program()).unwrap();
+    let mut cpu_state = CPUState::new();
+    assert_eq!(
+        elf.load(&mut cpu_state),
+        Err(ElfError::SegmentOutOfMemory {
+            address: BASE,
+            size: 20
+        })
+    );
+}
@@ -0,0 +1,214 @@
+extern crate toast_interpreter;
+
+mod common;
+
+use common::{with_engine, words_to_bytes, EngineKind, ENGINES};
+use toast_interpreter::{CPUState, Engine, FusionCounts, RunOutcome, RunResult, Trap};
+
+// Stores a running xor into a ring buffer at 0x400 and reads it back
+const LOOP: [u32; 16] = [
+    0x000002B7, // lui t0, 0
+    0x3E828293, // addi t0, t0, 1000
+    0x00000513, // addi a0, zero, 0
+    0x40000413, // addi s0, zero, 0x400
+    0x00350593, // addi a1, a0, 3
+    0x00B54533, // xor a0, a0, a1
+    0x00259613, // slli a2, a1, 2
+    0x3FC67613, // andi a2, a2, 0x3fc
+    0x00860633, // add a2, a2, s0
+    0x00A62023, // sw a0, 0(a2)
+    0x00062683, // lw a3, 0(a2)
+    0x0056C533, // xor a0, a3, t0
+    0xFFF28293, // addi t0, t0, -1
+    0xFC029EE3, // bne t0, zero, -36
+    0x00A00893, // addi a7, zero, 10
+    0x00000073, // ecall
+];
+
+const FAST_ENGINES: [EngineKind; 2] = [EngineKind::DecodeCache, EngineKind::Bytecode];
+
+/// A hart running `program` from address 0, the bytecode covers the program.
+fn cpu_state(program: &[u32], kind: EngineKind) -> CPUState {
+    let mut cpu_state = CPUState::new();
+    let bytes = words_to_bytes(program);
+    cpu_state.load(&bytes).unwrap();
+    with_engine(cpu_state, kind, bytes.len() as u32)
+}
+
+fn run(program: &[u32], kind: EngineKind) -> (CPUState, RunResult) {
+    let mut cpu_state = cpu_state(program, kind);
+    let result = cpu_state.run(100_000);
+    (cpu_state, result)
+}
+
+#[test]
+fn test_matches_interpreter() {
+    let (mut interpreted, interpreted_result) = run(&LOOP, EngineKind::Interpreter);
+    assert_eq!(interpreted_result.outcome, RunOutcome::Exited(0));
+    for kind in FAST_ENGINES {
+        let (mut fast, fast_result) = run(&LOOP, kind);
+        assert_eq!(fast_result, interpreted_result, "{:?}", kind);
+        assert_eq!(fast.registers, interpreted.registers, "{:?}", kind);
+        assert_eq!(fast.pc, interpreted.pc, "{:?}", kind);
+        assert_eq!(
+            fast.read_bytes(0, 0x800),
+            interpreted.read_bytes(0, 0x800),
+            "{:?}",
+            kind
+        );
+    }
+}
+
+#[test]
+fn test_self_modifying_code() {
+    let program = [
+        0x00200313, // addi t1, zero, 2
+        0x064503B7, // lui t2, 0x6450
+        0x51338393, // addi t2, t2, 0x513 (t2 = addi a0, a0, 100)
+        0x00150513, // addi a0, a0, 1
+        0x00702623, // sw t2, 12(zero)
+        0xFFF30313, // addi t1, t1, -1
+        0xFE031AE3, // bne t1, zero, -12
+        0x00A00893, // addi a7, zero, 10
+        0x00000073, // ecall
+    ];
+    for kind in ENGINES {
+        let (cpu_state, result) = run(&program, kind);
+        assert_eq!(result.outcome, RunOutcome::Exited(0));
+        assert_eq!(cpu_state.registers[10], 101, "{:?}", kind);
+    }
+}
+
+#[test]
+fn test_load_invalidates() {
+    for kind in FAST_ENGINES {
+        // addi a0, a0, 1; ebreak
+        let mut cpu_state = cpu_state(&[0x00150513, 0x00100073], kind);
+        assert_eq!(cpu_state.run(10).outcome, RunOutcome::Breakpoint { pc: 4 });
+        cpu_state.pc = 0;
+        cpu_state
+            .load(&words_to_bytes(&[0x06450513])) // addi a0, a0, 100
+            .unwrap();
+        assert_eq!(cpu_state.run(10).outcome, RunOutcome::Breakpoint { pc: 4 });
+        assert_eq!(cpu_state.registers[10], 101, "{:?}", kind);
+    }
+}
+
+#[test]
+fn test_traps_match_interpreter() {
+    let misaligned = [
+        0x00150513, // addi a0, a0, 1
+        0x00600067, // jalr zero, 6(zero)
+    ];
+    let illegal = [0x00150513, 0xFFFFFFFF];
+    for program in [&misaligned[..], &illegal[..]] {
+        let (_, interpreted) = run(program, EngineKind::Interpreter);
+        for kind in FAST_ENGINES {
+            assert_eq!(run(program, kind).1, interpreted, "{:?}", kind);
+        }
+    }
+    for kind in FAST_ENGINES {
+        assert_eq!(
+            run(&illegal, kind).1.outcome,
+            RunOutcome::Trapped(Trap::IllegalInstruction {
+                pc: 4,
+                instruction: 0xFFFFFFFF
+            })
+        );
+    }
+}
+
+#[test]
+fn test_bytecode_outside_translation() {
+    let program = [
+        0x00150513, // addi a0, a0, 1
+        0x00150513, // addi a0, a0, 1
+        0x00A00893, // addi a7, zero, 10
+        0x00000073, // ecall
+    ];
+    let mut cpu_state = CPUState::new();
+    cpu_state.load(&words_to_bytes(&program)).unwrap();
+    // only the first instruction is translated
+    let mut cpu_state = with_engine(cpu_state, EngineKind::Bytecode, 4);
+    assert_eq!(cpu_state.run(10).outcome, RunOutcome::Exited(0));
+    assert_eq!(cpu_state.registers[10], 2);
+}
+
+fn fusion_counts(cpu_state: &CPUState) -> FusionCounts {
+    match &cpu_state.engine {
+        Engine::Bytecode(translation) => translation.fusion_counts(),
+        _ => panic!("not running bytecode"),
+    }
+}
+
+#[test]
+fn test_superinstructions() {
+    let program = [
+        0x00500293, // addi t0, zero, 5
+        0x12345537, // lui a0, 0x12345
+        0x67850513, // addi a0, a0, 0x678
+        0x0032A313, // slti t1, t0, 3
+        0x00031463, // bne t1, zero, 8
+        0x00158593, // addi a1, a1, 1
+        0xFFF28293, // addi t0, t0, -1
+        0xFE0294E3, // bne t0, zero, -24
+        0x00000097, // auipc ra, 0
+        0x00C084E7, // jalr s1, 12(ra)
+        0x06300613, // addi a2, zero, 99
+        0x00A00893, // addi a7, zero, 10
+        0x00000073, // ecall
+    ];
+    let (interpreted, interpreted_result) = run(&program, EngineKind::Interpreter);
+    let (fused, fused_result) = run(&program, EngineKind::Bytecode);
+    assert_eq!(fused_result, interpreted_result);
+    assert_eq!(fused.registers, interpreted.registers);
+    assert_eq!(fused.csrs.cycle, interpreted.csrs.cycle);
+    assert_eq!(fused.registers[10], 0x12345678);
+    assert_eq!(fused.registers[11], 3);
+    assert_eq!(fused.registers[12], 0);
+    assert_eq!(
+        fusion_counts(&fused),
+        FusionCounts {
+            lui_addi: 5,
+            auipc_jalr: 1,
+            compare_branch: 5,
+        }
+    );
+
+    // stepping and run_until execute one instruction at a time
+    let mut stepped = cpu_state(&program, EngineKind::Bytecode);
+    while stepped.step().is_none() {}
+    let mut until = cpu_state(&program, EngineKind::Bytecode);
+    until.run_until(|_| false);
+    for cpu_state in [stepped, until] {
+        assert_eq!(cpu_state.registers, interpreted.registers);
+        assert_eq!(fusion_counts(&cpu_state).total(), 0);
+    }
+}
+
+#[test]
+fn test_jump_into_superinstruction() {
+    let program = [
+        0x00C00293, // addi t0, zero, 12
+        0x00028067, // jalr zero, 0(t0)
+        0x12345537, // lui a0, 0x12345
+        0x67850513, // addi a0, a0, 0x678
+        0x00A00893, // addi a7, zero, 10
+        0x00000073, // ecall
+    ];
+    let (cpu_state, result) = run(&program, EngineKind::Bytecode);
+    assert_eq!(result.outcome, RunOutcome::Exited(0));
+    assert_eq!(cpu_state.registers[10], 0x678);
+    assert_eq!(fusion_counts(&cpu_state).total(), 0);
+}
+
+#[test]
+fn test_superinstruction_cycle_budget() {
+    // lui a0, 0x12345; addi a0, a0, 0x678
+    let mut cpu_state = cpu_state(&[0x12345537, 0x67850513], EngineKind::Bytecode);
+    let result = cpu_state.run(1);
+    assert_eq!(result.outcome, RunOutcome::CycleLimit);
+    assert_eq!(result.instructions, 1);
+    assert_eq!(cpu_state.pc, 4);
+    assert_eq!(cpu_state.registers[10], 0x12345000);
+}
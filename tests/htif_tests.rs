@@ -0,0 +1,186 @@
+extern crate toast_interpreter;
+
+mod common;
+
+use common::{build_elf, with_engine, SharedBuffer, TestSegment, ENGINES};
+use toast_interpreter::assembler::assembler::assemble;
+use toast_interpreter::syscall::{SYS_EXIT, SYS_WRITE};
+use toast_interpreter::{
+    interpret_elf_file, interpret_max_cycles, CPUState, Htif, LinuxSyscalls, MemoryConfig,
+    RunOutcome,
+};
+
+const TOHOST: u32 = 0x1000;
+const FROMHOST: u32 = 0x1040;
+const BLOCKS: u32 = 0x1100;
+const BUFFER: u32 = 0x1200;
+
+const ENOSYS: u64 = -38i64 as u64;
+
+// Exits with status 3, writing the low word of the command first
+const EXIT_3: &str = "lui t0, 1\naddi t1, zero, 7\nsw t1, 0(t0)\nsw zero, 4(t0)\njal zero, 0\n";
+
+// The registers sit over RAM, as in a linked program
+fn cpu_state() -> CPUState {
+    let mut cpu_state = CPUState::with_memory(MemoryConfig {
+        base: 0,
+        size: 0x2000,
+    })
+    .unwrap();
+    cpu_state.attach_htif(Htif::new(TOHOST, Some(FROMHOST)));
+    cpu_state
+}
+
+fn htif(cpu_state: &CPUState) -> &Htif {
+    cpu_state.bus.device::<Htif>().unwrap()
+}
+
+/// Writes a system call block of 64 bit words at `address`.
+fn syscall_block(cpu_state: &mut CPUState, address: u32, words: &[u64]) {
+    let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+    cpu_state.write_bytes(address, &bytes).unwrap();
+}
+
+fn read_u64(cpu_state: &mut CPUState, address: u32) -> u64 {
+    let bytes = cpu_state.read_bytes(address, 8).unwrap();
+    u64::from_le_bytes(bytes.try_into().unwrap())
+}
+
+#[test]
+fn test_exit_stops_run() {
+    let program = assemble(EXIT_3);
+    for kind in ENGINES {
+        let mut cpu_state = cpu_state();
+        cpu_state.load(&program).unwrap();
+        let mut cpu_state = with_engine(cpu_state, kind, program.len() as u32);
+        let result = interpret_max_cycles(&program, &mut cpu_state, 1000);
+        assert_eq!(result.outcome, RunOutcome::Exited(3), "{:?}", kind);
+        assert_eq!(result.instructions, 4, "{:?}", kind);
+        // the command is consumed
+        assert_eq!(cpu_state.read_mem(TOHOST), Some(0));
+    }
+}
+
+#[test]
+fn test_command_waits_for_high_word() {
+    let mut cpu_state = cpu_state();
+    cpu_state.load(&assemble(EXIT_3)).unwrap();
+    for _ in 0..3 {
+        assert_eq!(cpu_state.step(), None);
+    }
+    assert_eq!(cpu_state.read_mem(TOHOST), Some(7));
+    assert_eq!(cpu_state.step(), Some(RunOutcome::Exited(3)));
+}
+
+#[test]
+fn test_console() {
+    let mut cpu_state = cpu_state();
+    cpu_state.bus.device_mut::<Htif>().unwrap().push_input(b"x");
+    let program = "lui t0, 1\nlui t1, 0x1010\naddi t2, zero, 104\nsw t2, 0(t0)\nsw t1, 4(t0)\n\
+                   lw s0, 68(t0)\nsw zero, 68(t0)\naddi t2, zero, 105\nsw t2, 0(t0)\n\
+                   sw t1, 4(t0)\nlui t1, 0x1000\nsw zero, 0(t0)\nsw t1, 4(t0)\nlw s1, 64(t0)\n\
+                   addi t2, zero, 1\nsw t2, 0(t0)\nsw zero, 4(t0)\n";
+    let result = interpret_max_cycles(&assemble(program), &mut cpu_state, 1000);
+    assert_eq!(result.outcome, RunOutcome::Exited(0));
+    assert_eq!(htif(&cpu_state).output(), b"hi");
+    // putchar is acknowledged, getchar replies with the character
+    assert_eq!(cpu_state.registers[8], 0x0101_0000);
+    assert_eq!(cpu_state.registers[9], b'x' as u32);
+}
+
+#[test]
+fn test_registers_far_apart() {
+    let mut cpu_state = CPUState::with_memory(MemoryConfig {
+        base: 0,
+        size: 0x2000,
+    })
+    .unwrap();
+    cpu_state.attach_htif(Htif::new(TOHOST, Some(0x1700)));
+    cpu_state.write_bytes(BUFFER, b"ram").unwrap();
+    // putchar, then a store and load between the registers
+    let program = "lui t0, 1\nlui t1, 0x1010\naddi t2, zero, 104\nsw t2, 0(t0)\nsw t1, 4(t0)\n\
+                   lw s0, 1796(t0)\naddi t2, zero, 42\nsw t2, 256(t0)\nlw s1, 256(t0)\n\
+                   addi t2, zero, 1\nsw t2, 0(t0)\nsw zero, 4(t0)\n";
+    let result = interpret_max_cycles(&assemble(program), &mut cpu_state, 1000);
+    assert_eq!(result.outcome, RunOutcome::Exited(0));
+    assert_eq!(htif(&cpu_state).output(), b"h");
+    assert_eq!(cpu_state.registers[8], 0x0101_0000);
+    // the memory between the registers is not shadowed
+    assert_eq!(cpu_state.registers[9], 42);
+    assert_eq!(cpu_state.read_bytes(BUFFER, 3).unwrap(), b"ram");
+}
+
+#[test]
+fn test_syscall_proxy() {
+    let mut cpu_state = cpu_state();
+    cpu_state.write_bytes(BUFFER, b"ok\n").unwrap();
+    syscall_block(
+        &mut cpu_state,
+        BLOCKS,
+        &[SYS_WRITE as u64, 1, BUFFER as u64, 3],
+    );
+    syscall_block(&mut cpu_state, BLOCKS + 0x40, &[999]);
+    syscall_block(&mut cpu_state, BLOCKS + 0x80, &[SYS_EXIT as u64, 5]);
+    let program = "lui t0, 1\naddi t1, t0, 256\nsw t1, 0(t0)\nsw zero, 4(t0)\nlw s0, 64(t0)\n\
+                   sw zero, 64(t0)\naddi t1, t0, 320\nsw t1, 0(t0)\nsw zero, 4(t0)\n\
+                   addi t1, t0, 384\nsw t1, 0(t0)\nsw zero, 4(t0)\n";
+    let result = interpret_max_cycles(&assemble(program), &mut cpu_state, 1000);
+    assert_eq!(result.outcome, RunOutcome::Exited(5));
+    assert_eq!(htif(&cpu_state).output(), b"ok\n");
+    assert_eq!(cpu_state.registers[8], 1);
+    assert_eq!(read_u64(&mut cpu_state, BLOCKS), 3);
+    assert_eq!(read_u64(&mut cpu_state, BLOCKS + 0x40), ENOSYS);
+}
+
+#[test]
+fn test_syscall_proxy_uses_handler() {
+    let stdout = SharedBuffer::default();
+    let mut cpu_state = cpu_state();
+    cpu_state.syscall_handler = Some(Box::new(
+        LinuxSyscalls::new(std::env::temp_dir()).with_stdout(stdout.clone()),
+    ));
+    cpu_state.write_bytes(BUFFER, b"handled\n").unwrap();
+    syscall_block(
+        &mut cpu_state,
+        BLOCKS,
+        &[SYS_WRITE as u64, 1, BUFFER as u64, 8],
+    );
+    let program = "lui t0, 1\naddi a0, zero, 42\naddi t1, t0, 256\nsw t1, 0(t0)\nsw zero, 4(t0)\n\
+                   addi t1, zero, 1\nsw t1, 0(t0)\nsw zero, 4(t0)\n";
+    let result = interpret_max_cycles(&assemble(program), &mut cpu_state, 1000);
+    assert_eq!(result.outcome, RunOutcome::Exited(0));
+    assert_eq!(stdout.contents(), b"handled\n");
+    assert_eq!(read_u64(&mut cpu_state, BLOCKS), 8);
+    // the registers the call was made in are restored
+    assert_eq!(cpu_state.registers[10], 42);
+}
+
+#[test]
+fn test_elf_with_tohost() {
+    let base = 0x8000_0000;
+    let elf = build_elf(
+        base,
+        &[
+            TestSegment {
+                address: base,
+                data: assemble(&String::from(
+                    "lui t0, 0x80001\naddi t1, zero, 1\nsw t1, 0(t0)\nsw zero, 4(t0)\n\
+                     jal zero, 0\n",
+                )),
+                memory_size: 0x1000,
+            },
+            TestSegment {
+                address: base + 0x1000,
+                data: vec![],
+                memory_size: 0x48,
+            },
+        ],
+        &[("tohost", base + 0x1000), ("fromhost", base + 0x1040)],
+    );
+    let path = std::env::temp_dir().join(format!("toast_htif_{}.elf", std::process::id()));
+    std::fs::write(&path, elf).unwrap();
+    let mut cpu_state = CPUState::with_memory(MemoryConfig { base, size: 0x2000 }).unwrap();
+    let result = interpret_elf_file(path.to_str().unwrap(), &mut cpu_state).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(result.outcome, RunOutcome::Exited(0));
+}
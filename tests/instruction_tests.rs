@@ -0,0 +1,340 @@
+extern crate toast_interpreter;
+
+use toast_interpreter::assembler::assembler::assemble;
+use toast_interpreter::instruction::{
+    BranchOp, CsrOp, FloatArithmeticOp, FloatBinaryOp, FloatBitsOp, FloatUnaryOp, FusedOp,
+    ImmediateOp, LoadOp, RegisterOp, ShiftOp, StoreOp,
+};
+use toast_interpreter::{disassemble, CPUState, DecodeError, Instruction, RunOutcome, Trap};
+
+// xorshift32, so failures reproduce
+struct Random(u32);
+
+impl Random {
+    fn next(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0
+    }
+
+    fn pick<T: Copy>(&mut self, values: &[T]) -> T {
+        values[self.next() as usize % values.len()]
+    }
+
+    fn register(&mut self) -> u8 {
+        (self.next() & 0b11111) as u8
+    }
+
+    // A sign-extended immediate of `bits` bits
+    fn signed(&mut self, bits: u32) -> i32 {
+        ((self.next() << (32 - bits)) as i32) >> (32 - bits)
+    }
+
+    fn rounding_mode(&mut self) -> u8 {
+        self.pick(&[0, 1, 2, 3, 4, 7])
+    }
+}
+
+// Every supported instruction with random operands
+fn random_instructions(random: &mut Random) -> Vec<Instruction> {
+    let mut instructions = vec![
+        Instruction::Lui {
+            rd: random.register(),
+            imm: random.next() & 0xFFFFF,
+        },
+        Instruction::Auipc {
+            rd: random.register(),
+            imm: random.next() & 0xFFFFF,
+        },
+        Instruction::Jal {
+            rd: random.register(),
+            offset: random.signed(21) & !1,
+        },
+        Instruction::Jalr {
+            rd: random.register(),
+            rs1: random.register(),
+            offset: random.signed(12),
+        },
+        Instruction::Fence {
+            pred: (random.next() & 0b1111) as u8,
+            succ: (random.next() & 0b1111) as u8,
+        },
+        Instruction::Ecall,
+        Instruction::Ebreak,
+        Instruction::Mret,
+        Instruction::Wfi,
+        Instruction::Flw {
+            rd: random.register(),
+            rs1: random.register(),
+            offset: random.signed(12),
+        },
+        Instruction::Fsw {
+            rs1: random.register(),
+            rs2: random.register(),
+            offset: random.signed(12),
+        },
+    ];
+    for &op in RegisterOp::ALL {
+        instructions.push(Instruction::Register {
+            op,
+            rd: random.register(),
+            rs1: random.register(),
+            rs2: random.register(),
+        });
+    }
+    for &op in ImmediateOp::ALL {
+        instructions.push(Instruction::Immediate {
+            op,
+            rd: random.register(),
+            rs1: random.register(),
+            imm: random.signed(12),
+        });
+    }
+    for &op in ShiftOp::ALL {
+        instructions.push(Instruction::Shift {
+            op,
+            rd: random.register(),
+            rs1: random.register(),
+            shamt: random.register(),
+        });
+    }
+    for &op in LoadOp::ALL {
+        instructions.push(Instruction::Load {
+            op,
+            rd: random.register(),
+            rs1: random.register(),
+            offset: random.signed(12),
+        });
+    }
+    for &op in StoreOp::ALL {
+        instructions.push(Instruction::Store {
+            op,
+            rs1: random.register(),
+            rs2: random.register(),
+            offset: random.signed(12),
+        });
+    }
+    for &op in BranchOp::ALL {
+        instructions.push(Instruction::Branch {
+            op,
+            rs1: random.register(),
+            rs2: random.register(),
+            offset: random.signed(13) & !1,
+        });
+    }
+    for &op in CsrOp::ALL {
+        instructions.push(Instruction::Csr {
+            op,
+            rd: random.register(),
+            source: random.register(),
+            csr: (random.next() & 0xFFF) as u16,
+        });
+    }
+    for &op in FloatArithmeticOp::ALL {
+        instructions.push(Instruction::FloatArithmetic {
+            op,
+            rd: random.register(),
+            rs1: random.register(),
+            rs2: random.register(),
+            rm: random.rounding_mode(),
+        });
+    }
+    for &op in FloatBinaryOp::ALL {
+        instructions.push(Instruction::FloatBinary {
+            op,
+            rd: random.register(),
+            rs1: random.register(),
+            rs2: random.register(),
+        });
+    }
+    for &op in FloatUnaryOp::ALL {
+        instructions.push(Instruction::FloatUnary {
+            op,
+            rd: random.register(),
+            rs1: random.register(),
+            rm: random.rounding_mode(),
+        });
+    }
+    for &op in FloatBitsOp::ALL {
+        instructions.push(Instruction::FloatBits {
+            op,
+            rd: random.register(),
+            rs1: random.register(),
+        });
+    }
+    for &op in FusedOp::ALL {
+        instructions.push(Instruction::FloatFused {
+            op,
+            rd: random.register(),
+            rs1: random.register(),
+            rs2: random.register(),
+            rs3: random.register(),
+            rm: random.rounding_mode(),
+        });
+    }
+    instructions
+}
+
+#[test]
+fn test_round_trip() {
+    let mut random = Random(0x2545_F491);
+    for _ in 0..1000 {
+        for instruction in random_instructions(&mut random) {
+            let word = instruction.encode();
+            assert_eq!(Instruction::decode(word), Ok(instruction), "{:#010x}", word);
+        }
+    }
+}
+
+#[test]
+fn test_decode_encode_random_words() {
+    let mut random = Random(0x9E37_79B9);
+    for _ in 0..100_000 {
+        let word = random.next();
+        if let Ok(instruction) = Instruction::decode(word) {
+            // only the reserved fields of fence are not preserved
+            if !matches!(instruction, Instruction::Fence { .. }) {
+                assert_eq!(instruction.encode(), word, "{:?}", instruction);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_decode() {
+    assert_eq!(
+        Instruction::decode(0xFF010113), // addi sp, sp, -16
+        Ok(Instruction::Immediate {
+            op: ImmediateOp::Addi,
+            rd: 2,
+            rs1: 2,
+            imm: -16
+        })
+    );
+    assert_eq!(
+        Instruction::decode(0x00512423), // sw t0, 8(sp)
+        Ok(Instruction::Store {
+            op: StoreOp::Sw,
+            rs1: 2,
+            rs2: 5,
+            offset: 8
+        })
+    );
+    assert_eq!(
+        Instruction::decode(0x4075D513), // srai a0, a1, 7
+        Ok(Instruction::Shift {
+            op: ShiftOp::Srai,
+            rd: 10,
+            rs1: 11,
+            shamt: 7
+        })
+    );
+    assert_eq!(
+        Instruction::decode(0xFFFFFFFF),
+        Err(DecodeError {
+            instruction: 0xFFFFFFFF
+        })
+    );
+}
+
+#[test]
+fn test_destination_registers() {
+    let destinations = |text: &str| {
+        let word = u32::from_le_bytes(assemble(text).try_into().unwrap());
+        let instruction = Instruction::decode(word).unwrap();
+        (
+            instruction.integer_destination(),
+            instruction.float_destination(),
+        )
+    };
+    assert_eq!(destinations("addi x5, x6, 1"), (Some(5), None));
+    assert_eq!(destinations("addi x0, x6, 1"), (None, None));
+    assert_eq!(destinations("csrrs x7, 0x300, x0"), (Some(7), None));
+    assert_eq!(destinations("sw x5, 8(x2)"), (None, None));
+    assert_eq!(destinations("flw f3, 0(x2)"), (None, Some(3)));
+    assert_eq!(destinations("fsw f3, 0(x2)"), (None, None));
+    // the float instructions that write an integer register
+    assert_eq!(destinations("feq.s x8, f1, f2"), (Some(8), None));
+    assert_eq!(destinations("fmin.s f8, f1, f2"), (None, Some(8)));
+    assert_eq!(destinations("fcvt.w.s x9, f1"), (Some(9), None));
+    assert_eq!(destinations("fcvt.s.w f9, x1"), (None, Some(9)));
+    assert_eq!(destinations("fmv.x.w x10, f1"), (Some(10), None));
+    assert_eq!(destinations("fclass.s x11, f1"), (Some(11), None));
+    assert_eq!(destinations("fmv.w.x f12, x1"), (None, Some(12)));
+    assert_eq!(destinations("fmadd.s f13, f1, f2, f3"), (None, Some(13)));
+}
+
+#[test]
+fn test_assembler_encodes_with_instruction() {
+    let binary = assemble(&String::from(
+        "sw x5, 8(x2)\nsrai x10, x11, 7\naddi x2, x2, -16\n",
+    ));
+    let words: Vec<u32> = binary
+        .chunks(4)
+        .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+        .collect();
+    assert_eq!(words, [0x00512423, 0x4075D513, 0xFF010113]);
+}
+
+#[test]
+fn test_assembler_resolves_labels() {
+    // branches and jumps take the offset to a label, .word its address, and
+    // li rounds the upper part up when the low 12 bits are negative
+    let binary = assemble(&String::from(
+        "start: beq x1, x2, end\nli x5, 0x12345FFF\nend: jal x0, start\n.word end\n\
+         li x6, 0x7FFFF800\n",
+    ));
+    let words: Vec<u32> = binary
+        .chunks(4)
+        .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+        .collect();
+    assert_eq!(
+        words,
+        [0x00208663, 0x123462B7, 0xFFF28293, 0xFF5FF06F, 12, 0x80000337, 0x80030313]
+    );
+}
+
+#[test]
+fn test_assembler_accepts_disassembly() {
+    let mut random = Random(0x6A09_E667);
+    for _ in 0..50 {
+        for instruction in random_instructions(&mut random) {
+            let word = instruction.encode();
+            let line = &disassemble(&word.to_le_bytes())[0];
+            // the assembler takes offsets where targets are printed
+            let mut operands: Vec<String> = line.operands.split(',').map(String::from).collect();
+            if let Some(target) = line.target {
+                *operands.last_mut().unwrap() = (target as i32).to_string();
+            }
+            let text = format!("{} {}", line.mnemonic, operands.join(", "));
+            let binary = assemble(&text);
+            assert_eq!(binary, word.to_le_bytes(), "{}", text);
+        }
+    }
+}
+
+#[test]
+fn test_assembler_encodes_float_instructions() {
+    let binary = assemble(&String::from(
+        "fadd.s f1, f2, f3\nfadd.s f1, f2, f3, rtz\nflw fa0, -4(sp)\nfcvt.w.s a0, ft0\n",
+    ));
+    let words: Vec<u32> = binary
+        .chunks(4)
+        .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+        .collect();
+    assert_eq!(words, [0x003170D3, 0x003110D3, 0xFFC12507, 0xC0007553]);
+}
+
+#[test]
+fn test_interpreter_rejects_what_decode_rejects() {
+    let mut cpu_state = CPUState::new();
+    cpu_state.load(&0xFFFFFFFFu32.to_le_bytes()).unwrap();
+    assert_eq!(
+        cpu_state.step(),
+        Some(RunOutcome::Trapped(Trap::IllegalInstruction {
+            pc: 0,
+            instruction: 0xFFFFFFFF
+        }))
+    );
+}
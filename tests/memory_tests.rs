@@ -0,0 +1,126 @@
+extern crate toast_interpreter;
+
+mod common;
+
+use common::words_to_bytes;
+use toast_interpreter::{
+    interpret_max_cycles, CPUState, Memory, MemoryConfig, MemoryTooLarge, RunOutcome, Trap,
+};
+
+const BASE: u32 = 0x8000_0000;
+const SIZE: usize = 16 << 20;
+
+const LOAD_PROGRAM: [u32; 3] = [
+    0x0000A183, // lw x3, 0(x1)
+    0x00A00893, // addi x17, x0, 10
+    0x00000073, // ecall
+];
+
+fn large_memory() -> CPUState {
+    CPUState::with_memory(MemoryConfig {
+        base: BASE,
+        size: SIZE,
+    })
+    .unwrap()
+}
+
+#[test]
+fn test_memory_at_base_address() {
+    let mut cpu_state = large_memory();
+    assert_eq!(cpu_state.pc, BASE);
+    cpu_state
+        .set_mem(BASE + SIZE as u32 - 4, 0xDEADBEEF)
+        .unwrap();
+    cpu_state.registers[1] = BASE + SIZE as u32 - 4;
+    let result = interpret_max_cycles(&words_to_bytes(&LOAD_PROGRAM), &mut cpu_state, 20);
+    assert_eq!(result.outcome, RunOutcome::Exited(0));
+    assert_eq!(cpu_state.registers[3], 0xDEADBEEF);
+}
+
+#[test]
+fn test_load_past_end_of_memory_faults() {
+    let mut cpu_state = large_memory();
+    cpu_state.registers[1] = BASE + SIZE as u32 - 2;
+    let result = interpret_max_cycles(&words_to_bytes(&LOAD_PROGRAM), &mut cpu_state, 20);
+    assert_eq!(
+        result.outcome,
+        RunOutcome::Trapped(Trap::LoadAccessFault {
+            pc: BASE,
+            instruction: 0x0000A183,
+            address: BASE + SIZE as u32 - 2
+        })
+    );
+}
+
+#[test]
+fn test_store_below_base_faults() {
+    let mut cpu_state = large_memory();
+    cpu_state.registers[1] = 0x100;
+    // sw x0, 0(x1)
+    let result = interpret_max_cycles(&words_to_bytes(&[0x0000A023]), &mut cpu_state, 20);
+    assert_eq!(
+        result.outcome,
+        RunOutcome::Trapped(Trap::StoreAccessFault {
+            pc: BASE,
+            instruction: 0x0000A023,
+            address: 0x100
+        })
+    );
+}
+
+#[test]
+fn test_fetch_outside_memory_faults() {
+    let mut cpu_state = CPUState::new();
+    cpu_state.pc = 0x1000;
+    let result = interpret_max_cycles(&[], &mut cpu_state, 20);
+    assert_eq!(
+        result.outcome,
+        RunOutcome::Trapped(Trap::InstructionAccessFault { pc: 0x1000 })
+    );
+}
+
+#[test]
+fn test_pc_wraps_at_top_of_memory() {
+    let mut cpu_state = CPUState::with_memory(MemoryConfig {
+        base: 0xFFFF_F000,
+        size: 0x1000,
+    })
+    .unwrap();
+    cpu_state.set_mem(0xFFFF_FFF8, 0x00100293).unwrap(); // addi x5, x0, 1
+    cpu_state.set_mem(0xFFFF_FFFC, 0xFFDFF0EF).unwrap(); // jal x1, -4
+    cpu_state.pc = 0xFFFF_FFFC;
+    assert_eq!(cpu_state.step(), None);
+    assert_eq!(cpu_state.registers[1], 0);
+    assert_eq!(cpu_state.pc, 0xFFFF_FFF8);
+    assert_eq!(cpu_state.step(), None);
+    assert_eq!(cpu_state.registers[5], 1);
+    assert_eq!(cpu_state.pc, 0xFFFF_FFFC);
+    // an instruction in the last word falls through to address 0
+    cpu_state.set_mem(0xFFFF_FFFC, 0x00100293).unwrap();
+    assert_eq!(cpu_state.step(), None);
+    assert_eq!(cpu_state.pc, 0);
+    assert_eq!(
+        cpu_state.step(),
+        Some(RunOutcome::Trapped(Trap::InstructionAccessFault { pc: 0 }))
+    );
+}
+
+#[test]
+fn test_oversized_program_is_not_run() {
+    let mut cpu_state = CPUState::new();
+    cpu_state.pc = 0xFFC;
+    let result = interpret_max_cycles(&words_to_bytes(&LOAD_PROGRAM), &mut cpu_state, 20);
+    assert_eq!(result.outcome, RunOutcome::LoadFailed);
+    assert_eq!(result.instructions, 0);
+    assert_eq!(cpu_state.pc, 0xFFC);
+}
+
+#[test]
+fn test_memory_beyond_address_space_is_rejected() {
+    let size = u32::MAX as usize + 1;
+    assert_eq!(Memory::new(size).err(), Some(MemoryTooLarge { size }));
+    assert!(CPUState::with_memory(MemoryConfig { base: 0, size }).is_err());
+    let mut memory = Memory::new(16).unwrap();
+    assert_eq!(memory.resize(size), Err(MemoryTooLarge { size }));
+    assert_eq!(memory.bytes().len(), 16);
+}
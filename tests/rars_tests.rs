@@ -0,0 +1,93 @@
+extern crate toast_interpreter;
+
+mod common;
+
+use std::io::Cursor;
+
+use common::{words_to_bytes, SharedBuffer};
+use toast_interpreter::rars::{PRINT_INT_HEX, READ_STRING, SBRK};
+use toast_interpreter::{interpret, CPUState, RarsSyscalls, RunOutcome, SyscallHandler, Trap};
+
+fn rars_cpu(input: &str, stdout: &SharedBuffer) -> CPUState {
+    let mut cpu_state = CPUState::new();
+    cpu_state.syscall_handler = Some(Box::new(
+        RarsSyscalls::new()
+            .with_stdin(Cursor::new(input.as_bytes().to_vec()))
+            .with_stdout(stdout.clone()),
+    ));
+    cpu_state
+}
+
+#[test]
+fn test_rars_program() {
+    let stdout = SharedBuffer::default();
+    let mut cpu_state = rars_cpu("41\n", &stdout);
+    cpu_state.write_bytes(0x100, b"done\0").unwrap();
+    let program = [
+        0x00500893, // addi a7, x0, 5 (read_int)
+        0x00000073, // ecall
+        0x00150513, // addi a0, a0, 1
+        0x00100893, // addi a7, x0, 1 (print_int)
+        0x00000073, // ecall
+        0x00A00513, // addi a0, x0, '\n'
+        0x00B00893, // addi a7, x0, 11 (print_char)
+        0x00000073, // ecall
+        0x10000513, // addi a0, x0, 0x100
+        0x00400893, // addi a7, x0, 4 (print_string)
+        0x00000073, // ecall
+        0x00300513, // addi a0, x0, 3
+        0x05D00893, // addi a7, x0, 93 (exit2)
+        0x00000073, // ecall
+    ];
+    let result = interpret(&words_to_bytes(&program), &mut cpu_state);
+    assert_eq!(result.outcome, RunOutcome::Exited(3));
+    assert_eq!(stdout.contents(), b"42\ndone");
+}
+
+#[test]
+fn test_rars_unknown_call_traps() {
+    let stdout = SharedBuffer::default();
+    let mut cpu_state = rars_cpu("", &stdout);
+    let program = [
+        0x06300893, // addi a7, x0, 99
+        0x00000073, // ecall
+    ];
+    let result = interpret(&words_to_bytes(&program), &mut cpu_state);
+    assert_eq!(
+        result.outcome,
+        RunOutcome::Trapped(Trap::EnvironmentCall {
+            pc: 4,
+            instruction: 0x00000073
+        })
+    );
+    assert_eq!(cpu_state.pc, 4);
+}
+
+#[test]
+fn test_rars_read_string_sbrk_and_hex() {
+    let stdout = SharedBuffer::default();
+    let mut cpu_state = CPUState::new();
+    let mut handler = RarsSyscalls::new()
+        .with_stdin(Cursor::new(b"hello world\n".to_vec()))
+        .with_stdout(stdout.clone())
+        .with_heap_start(0x800);
+
+    cpu_state.registers[17] = READ_STRING;
+    cpu_state.registers[10] = 0x100;
+    cpu_state.registers[11] = 6;
+    handler.handle(&mut cpu_state);
+    assert_eq!(cpu_state.read_bytes(0x100, 6).unwrap(), b"hello\0");
+
+    cpu_state.registers[17] = SBRK;
+    cpu_state.registers[10] = 5;
+    handler.handle(&mut cpu_state);
+    assert_eq!(cpu_state.registers[10], 0x800);
+    cpu_state.registers[10] = 4;
+    handler.handle(&mut cpu_state);
+    assert_eq!(cpu_state.registers[10], 0x808);
+
+    cpu_state.registers[17] = PRINT_INT_HEX;
+    cpu_state.registers[10] = 0xBEEF;
+    handler.handle(&mut cpu_state);
+    assert_eq!(stdout.contents(), b"0x0000beef");
+}
@@ -0,0 +1,207 @@
+extern crate toast_interpreter;
+
+mod common;
+
+use std::fs;
+use std::path::Path;
+
+use common::{build_elf, TestSegment};
+use toast_interpreter::assembler::assembler::assemble;
+use toast_interpreter::compliance::{self, format_signature, TestOutcome};
+use toast_interpreter::{Elf, ElfError};
+
+// The upstream suites are vendored as binaries: the rv32ui-p-*, rv32um-p-*
+// and rv32uf-p-* ELFs of riscv-tests in tests/riscv-tests, and the
+// riscv-arch-test ELFs in tests/riscv-arch-test next to their
+// <name>.reference_output files. Until they are checked in the two suite
+// tests are ignored, run them with `cargo test --test riscv_tests --
+// --include-ignored`; an empty directory fails.
+const RISCV_TESTS: &str = "tests/riscv-tests";
+const ARCH_TESTS: &str = "tests/riscv-arch-test";
+const SUITES: [&str; 3] = ["rv32ui-p-", "rv32um-p-", "rv32uf-p-"];
+
+const MAX_CYCLES: u64 = 1_000_000;
+
+const BASE: u32 = 0x8000_0000;
+const TOHOST: u32 = BASE + 0x1000;
+const SIGNATURE: u32 = BASE + 0x2000;
+
+// The files in `directory` accepted by `filter`, sorted by name
+fn binaries(directory: &str, filter: impl Fn(&str) -> bool) -> Vec<(String, Vec<u8>)> {
+    let Ok(entries) = fs::read_dir(directory) else {
+        return vec![];
+    };
+    let mut binaries: Vec<_> = entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let name = path.file_name()?.to_str()?.to_string();
+            filter(&name).then(|| Some((name, fs::read(&path).ok()?)))?
+        })
+        .collect();
+    binaries.sort();
+    binaries
+}
+
+fn run(bytes: &[u8]) -> Result<TestOutcome, ElfError> {
+    let elf = Elf::parse(bytes)?;
+    let mut cpu_state = compliance::load(&elf)?;
+    Ok(compliance::run_test(&mut cpu_state, MAX_CYCLES))
+}
+
+#[test]
+#[ignore = "needs the riscv-tests binaries in tests/riscv-tests"]
+fn test_riscv_tests() {
+    let binaries = binaries(RISCV_TESTS, |name| {
+        SUITES.iter().any(|suite| name.starts_with(suite)) && !name.ends_with(".dump")
+    });
+    assert!(
+        !binaries.is_empty(),
+        "no riscv-tests binaries in {}",
+        RISCV_TESTS
+    );
+    let mut failures = vec![];
+    for (name, bytes) in &binaries {
+        let outcome = run(bytes);
+        if outcome != Ok(TestOutcome::Passed) {
+            failures.push(format!("{}: {:?}", name, outcome));
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "{} of {} failed: {:?}",
+        failures.len(),
+        binaries.len(),
+        failures
+    );
+}
+
+#[test]
+#[ignore = "needs the riscv-arch-test binaries in tests/riscv-arch-test"]
+fn test_riscv_arch_tests() {
+    let binaries = binaries(ARCH_TESTS, |name| name.ends_with(".elf"));
+    assert!(
+        !binaries.is_empty(),
+        "no riscv-arch-test binaries in {}",
+        ARCH_TESTS
+    );
+    let mut failures = vec![];
+    for (name, bytes) in &binaries {
+        let reference = Path::new(ARCH_TESTS).join(name.replace(".elf", ".reference_output"));
+        let elf = Elf::parse(bytes).unwrap();
+        let mut cpu_state = compliance::load(&elf).unwrap();
+        let outcome = compliance::run_test(&mut cpu_state, MAX_CYCLES);
+        let signature =
+            compliance::signature(&mut cpu_state, &elf).map(|words| format_signature(&words));
+        let matches = match (&signature, fs::read_to_string(&reference)) {
+            (Ok(signature), Ok(reference)) => *signature == reference,
+            _ => false,
+        };
+        if outcome != TestOutcome::Passed || !matches {
+            failures.push(format!(
+                "{}: {:?}, signature matches: {}",
+                name, outcome, matches
+            ));
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "{} of {} failed: {:?}",
+        failures.len(),
+        binaries.len(),
+        failures
+    );
+}
+
+// A program at BASE with a tohost word and an 8 byte signature
+fn program(text: &str) -> Vec<u8> {
+    build_elf(
+        BASE,
+        &[
+            TestSegment {
+                address: BASE,
+                data: assemble(text),
+                memory_size: 0x100,
+            },
+            TestSegment {
+                address: TOHOST,
+                data: vec![],
+                memory_size: 8,
+            },
+            TestSegment {
+                address: SIGNATURE,
+                data: vec![],
+                memory_size: 8,
+            },
+        ],
+        &[
+            ("_start", BASE),
+            ("tohost", TOHOST),
+            ("begin_signature", SIGNATURE),
+            ("end_signature", SIGNATURE + 8),
+        ],
+    )
+}
+
+#[test]
+fn test_tohost_results() {
+    let pass =
+        program("lui t0, 0x80001\naddi t1, zero, 1\nsw t1, 0(t0)\nsw zero, 4(t0)\njal zero, 0\n");
+    assert_eq!(run(&pass), Ok(TestOutcome::Passed));
+
+    // case 3 failed
+    let fail =
+        program("lui t0, 0x80001\naddi t1, zero, 7\nsw t1, 0(t0)\nsw zero, 4(t0)\njal zero, 0\n");
+    assert_eq!(run(&fail), Ok(TestOutcome::Failed(3)));
+
+    let spin = program("jal zero, 0\n");
+    assert_eq!(run(&spin), Ok(TestOutcome::Timeout));
+
+    // without a handler in mtvec the trap faults again at address 0 forever
+    let exit = program("addi a7, zero, 10\necall\n");
+    assert_eq!(run(&exit), Ok(TestOutcome::Timeout));
+}
+
+#[test]
+fn test_ecall_enters_trap_handler() {
+    // the handler at 16 reports the pass, as RVTEST_PASS does
+    let program = program(
+        "auipc t0, 0\naddi t0, t0, 16\ncsrrw zero, mtvec, t0\necall\n\
+         lui t0, 0x80001\naddi t1, zero, 1\nsw t1, 0(t0)\nsw zero, 4(t0)\njal zero, 0\n",
+    );
+    assert_eq!(run(&program), Ok(TestOutcome::Passed));
+}
+
+#[test]
+fn test_signature() {
+    let elf = Elf::parse(&program(
+        "lui t0, 0x80002\nlui t1, 0xDEADC\naddi t1, t1, -273\nsw t1, 0(t0)\naddi t1, zero, 1\n\
+         sw t1, 4(t0)\nlui t0, 0x80001\nsw t1, 0(t0)\nsw zero, 4(t0)\njal zero, 0\n",
+    ))
+    .unwrap();
+    let mut cpu_state = compliance::load(&elf).unwrap();
+    assert_eq!(
+        compliance::run_test(&mut cpu_state, MAX_CYCLES),
+        TestOutcome::Passed
+    );
+    let signature = compliance::signature(&mut cpu_state, &elf).unwrap();
+    assert_eq!(signature, [0xDEAD_BEEF, 1]);
+    assert_eq!(format_signature(&signature), "deadbeef\n00000001\n");
+}
+
+#[test]
+fn test_missing_tohost() {
+    let elf = Elf::parse(&build_elf(
+        BASE,
+        &[TestSegment {
+            address: BASE,
+            data: assemble(&String::from("jal zero, 0\n")),
+            memory_size: 4,
+        }],
+        &[("_start", BASE)],
+    ))
+    .unwrap();
+    assert_eq!(
+        compliance::load(&elf).err(),
+        Some(ElfError::MissingSymbol("tohost"))
+    );
+}
@@ -0,0 +1,77 @@
+extern crate toast_interpreter;
+
+mod common;
+
+use common::words_to_bytes;
+use toast_interpreter::{CPUState, RunOutcome};
+
+const PROGRAM: [u32; 5] = [
+    0x00100113, // addi x2, x0, 1
+    0x00110113, // addi x2, x2, 1
+    0x00110113, // addi x2, x2, 1
+    0x00A00893, // addi x17, x0, 10
+    0x00000073, // ecall
+];
+
+fn loaded() -> CPUState {
+    let mut cpu_state = CPUState::new();
+    cpu_state.load(&words_to_bytes(&PROGRAM)).unwrap();
+    cpu_state
+}
+
+#[test]
+fn test_step() {
+    let mut cpu_state = loaded();
+    for expected in 1..=3 {
+        assert_eq!(cpu_state.step(), None);
+        assert_eq!(cpu_state.registers[2], expected);
+        assert_eq!(cpu_state.pc, expected * 4);
+    }
+    assert_eq!(cpu_state.step(), None);
+    assert_eq!(cpu_state.step(), Some(RunOutcome::Exited(0)));
+}
+
+#[test]
+fn test_run_resumes() {
+    let mut cpu_state = loaded();
+    let result = cpu_state.run(2);
+    assert_eq!(result.outcome, RunOutcome::CycleLimit);
+    assert_eq!(result.cycles, 2);
+    assert_eq!(cpu_state.registers[2], 2);
+
+    let result = cpu_state.run(100);
+    assert_eq!(result.outcome, RunOutcome::Exited(0));
+    assert_eq!(result.instructions, 3);
+    assert_eq!(cpu_state.registers[2], 3);
+}
+
+#[test]
+fn test_run_until() {
+    let mut cpu_state = loaded();
+    let result = cpu_state.run_until(|cpu_state| cpu_state.registers[2] == 2);
+    assert_eq!(result.outcome, RunOutcome::Stopped);
+    assert_eq!(cpu_state.pc, 8);
+
+    // the predicate is checked before each instruction
+    let result = cpu_state.run_until(|cpu_state| cpu_state.pc == 8);
+    assert_eq!(result.outcome, RunOutcome::Stopped);
+    assert_eq!(result.cycles, 0);
+
+    let result = cpu_state.run_until(|cpu_state| cpu_state.pc == 0x100);
+    assert_eq!(result.outcome, RunOutcome::Exited(0));
+}
+
+#[test]
+fn test_clone_snapshots_hart() {
+    let mut cpu_state = loaded();
+    cpu_state.run(2);
+    let mut snapshot = cpu_state.clone();
+    assert_eq!(cpu_state.run(100).outcome, RunOutcome::Exited(0));
+    assert_eq!(cpu_state.registers[2], 3);
+    // memory is copied too, so the snapshot does not see this store
+    cpu_state.set_mem(0x100, 7).unwrap();
+    assert_eq!(snapshot.registers[2], 2);
+    assert_eq!(snapshot.read_mem(0x100), Some(0));
+    assert_eq!(snapshot.run(100).outcome, RunOutcome::Exited(0));
+    assert_eq!(snapshot.registers[2], 3);
+}
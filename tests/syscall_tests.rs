@@ -0,0 +1,236 @@
+extern crate toast_interpreter;
+
+mod common;
+
+use std::path::PathBuf;
+
+use common::{words_to_bytes, SharedBuffer};
+use toast_interpreter::syscall::{
+    SYS_BRK, SYS_CLOSE, SYS_FSTAT, SYS_LSEEK, SYS_OPENAT, SYS_READ, SYS_WRITE,
+};
+use toast_interpreter::{
+    interpret, CPUState, LinuxSyscalls, Rom, RunOutcome, SyscallAction, SyscallHandler,
+};
+
+const AT_FDCWD: u32 = -100i32 as u32;
+const O_WRONLY_CREAT: u32 = 0o101;
+const ENOENT: u32 = -2i32 as u32;
+const EBADF: u32 = -9i32 as u32;
+const EACCES: u32 = -13i32 as u32;
+const EFAULT: u32 = -14i32 as u32;
+const ENOSYS: u32 = -38i32 as u32;
+const ELOOP: u32 = -40i32 as u32;
+
+fn sandbox(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("toast_sandbox_{}_{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    std::fs::create_dir_all(&path).unwrap();
+    path
+}
+
+/// Places `args` in a0.. and `number` in a7 then invokes the handler.
+fn syscall(
+    handler: &mut LinuxSyscalls,
+    cpu_state: &mut CPUState,
+    number: u32,
+    args: &[u32],
+) -> u32 {
+    cpu_state.registers[17] = number;
+    for (i, arg) in args.iter().enumerate() {
+        cpu_state.registers[10 + i] = *arg;
+    }
+    assert_eq!(handler.handle(cpu_state), SyscallAction::Continue);
+    cpu_state.registers[10]
+}
+
+#[test]
+fn test_write_and_exit() {
+    let stdout = SharedBuffer::default();
+    let mut cpu_state = CPUState::new();
+    cpu_state.syscall_handler = Some(Box::new(
+        LinuxSyscalls::new(sandbox("exit")).with_stdout(stdout.clone()),
+    ));
+    cpu_state.write_bytes(0x100, b"hi\n").unwrap();
+    let program = [
+        0x00100513, // addi a0, x0, 1
+        0x10000593, // addi a1, x0, 0x100
+        0x00300613, // addi a2, x0, 3
+        0x04000893, // addi a7, x0, 64
+        0x00000073, // ecall
+        0x02A00513, // addi a0, x0, 42
+        0x05D00893, // addi a7, x0, 93
+        0x00000073, // ecall
+    ];
+    let result = interpret(&words_to_bytes(&program), &mut cpu_state);
+    assert_eq!(result.outcome, RunOutcome::Exited(42));
+    assert_eq!(stdout.contents(), b"hi\n");
+    assert_eq!(cpu_state.pc, 32);
+}
+
+#[test]
+fn test_file_round_trip() {
+    let root = sandbox("files");
+    let mut handler = LinuxSyscalls::new(&root);
+    let mut cpu_state = CPUState::new();
+    cpu_state.write_bytes(0x100, b"/out.txt\0").unwrap();
+    cpu_state.write_bytes(0x200, b"hello").unwrap();
+
+    let fd = syscall(
+        &mut handler,
+        &mut cpu_state,
+        SYS_OPENAT,
+        &[AT_FDCWD, 0x100, O_WRONLY_CREAT, 0o644],
+    );
+    assert_eq!(fd, 3);
+    assert_eq!(
+        syscall(&mut handler, &mut cpu_state, SYS_WRITE, &[fd, 0x200, 5]),
+        5
+    );
+    assert_eq!(syscall(&mut handler, &mut cpu_state, SYS_CLOSE, &[fd]), 0);
+    assert_eq!(std::fs::read(root.join("out.txt")).unwrap(), b"hello");
+
+    let fd = syscall(
+        &mut handler,
+        &mut cpu_state,
+        SYS_OPENAT,
+        &[AT_FDCWD, 0x100, 0, 0],
+    );
+    assert_eq!(
+        syscall(&mut handler, &mut cpu_state, SYS_LSEEK, &[fd, 1, 0]),
+        1
+    );
+    assert_eq!(
+        syscall(&mut handler, &mut cpu_state, SYS_READ, &[fd, 0x300, 16]),
+        4
+    );
+    assert_eq!(cpu_state.read_bytes(0x300, 4).unwrap(), b"ello");
+
+    assert_eq!(
+        syscall(&mut handler, &mut cpu_state, SYS_FSTAT, &[fd, 0x400]),
+        0
+    );
+    let stat = cpu_state.read_bytes(0x400, 128).unwrap();
+    assert_eq!(
+        u32::from_le_bytes(stat[16..20].try_into().unwrap()) & 0o170000,
+        0o100000
+    );
+    assert_eq!(u64::from_le_bytes(stat[48..56].try_into().unwrap()), 5);
+
+    assert_eq!(syscall(&mut handler, &mut cpu_state, SYS_CLOSE, &[fd]), 0);
+    assert_eq!(
+        syscall(&mut handler, &mut cpu_state, SYS_CLOSE, &[fd]),
+        EBADF
+    );
+}
+
+#[test]
+fn test_read_is_bounded_by_guest_buffer() {
+    let root = sandbox("bounded");
+    std::fs::write(root.join("digits.txt"), b"0123456789").unwrap();
+    let mut handler = LinuxSyscalls::new(&root);
+    let mut cpu_state = CPUState::new();
+    cpu_state.bus.map(0x1000, Rom::new(vec![0; 16]));
+    cpu_state.write_bytes(0x100, b"/digits.txt\0").unwrap();
+    let fd = syscall(
+        &mut handler,
+        &mut cpu_state,
+        SYS_OPENAT,
+        &[AT_FDCWD, 0x100, 0, 0],
+    );
+
+    // only the four bytes of RAM below the ROM are filled
+    assert_eq!(
+        syscall(
+            &mut handler,
+            &mut cpu_state,
+            SYS_READ,
+            &[fd, 0xFFC, u32::MAX]
+        ),
+        4
+    );
+    assert_eq!(cpu_state.read_bytes(0xFFC, 4).unwrap(), b"0123");
+    // nothing is consumed when none of the buffer can be stored to
+    assert_eq!(
+        syscall(&mut handler, &mut cpu_state, SYS_READ, &[fd, 0x1000, 4]),
+        EFAULT
+    );
+    assert_eq!(
+        syscall(&mut handler, &mut cpu_state, SYS_READ, &[fd, 0x2000, 4]),
+        EFAULT
+    );
+    assert_eq!(
+        syscall(&mut handler, &mut cpu_state, SYS_READ, &[fd, 0x200, 16]),
+        6
+    );
+    assert_eq!(cpu_state.read_bytes(0x200, 6).unwrap(), b"456789");
+}
+
+#[test]
+fn test_sandbox_escape_is_rejected() {
+    let mut handler = LinuxSyscalls::new(sandbox("escape"));
+    let mut cpu_state = CPUState::new();
+    cpu_state.write_bytes(0x100, b"../secret\0").unwrap();
+    cpu_state.write_bytes(0x200, b"missing\0").unwrap();
+    assert_eq!(
+        syscall(
+            &mut handler,
+            &mut cpu_state,
+            SYS_OPENAT,
+            &[AT_FDCWD, 0x100, 0, 0]
+        ),
+        EACCES
+    );
+    assert_eq!(
+        syscall(
+            &mut handler,
+            &mut cpu_state,
+            SYS_OPENAT,
+            &[AT_FDCWD, 0x200, 0, 0]
+        ),
+        ENOENT
+    );
+}
+
+#[test]
+fn test_dangling_symlink_is_not_created() {
+    let root = sandbox("dangling");
+    let outside = std::env::temp_dir().join(format!("toast_outside_{}", std::process::id()));
+    let _ = std::fs::remove_file(&outside);
+    std::os::unix::fs::symlink(&outside, root.join("link")).unwrap();
+    let mut handler = LinuxSyscalls::new(root);
+    let mut cpu_state = CPUState::new();
+    cpu_state.write_bytes(0x100, b"link\0").unwrap();
+    assert_eq!(
+        syscall(
+            &mut handler,
+            &mut cpu_state,
+            SYS_OPENAT,
+            &[AT_FDCWD, 0x100, O_WRONLY_CREAT, 0o644]
+        ),
+        ELOOP
+    );
+    assert!(!outside.exists());
+}
+
+#[test]
+fn test_brk_and_unknown_syscalls() {
+    let mut handler = LinuxSyscalls::new(sandbox("brk")).with_program_break(0x800);
+    let mut cpu_state = CPUState::new();
+    assert_eq!(syscall(&mut handler, &mut cpu_state, SYS_BRK, &[0]), 0x800);
+    assert_eq!(
+        syscall(&mut handler, &mut cpu_state, SYS_BRK, &[0x900]),
+        0x900
+    );
+    // past the end of RAM and below the initial break both fail
+    assert_eq!(
+        syscall(&mut handler, &mut cpu_state, SYS_BRK, &[0x10_0000]),
+        0x900
+    );
+    assert_eq!(
+        syscall(&mut handler, &mut cpu_state, SYS_BRK, &[0x400]),
+        0x900
+    );
+    assert_eq!(handler.program_break(), 0x900);
+
+    assert_eq!(syscall(&mut handler, &mut cpu_state, 1234, &[]), ENOSYS);
+}
@@ -0,0 +1,106 @@
+extern crate toast_interpreter;
+
+mod common;
+
+use common::{build_elf, words_to_bytes, SharedBuffer, TestSegment};
+use toast_interpreter::{
+    interpret, CPUState, Elf, JsonTracer, MemoryConfig, RunOutcome, SpikeTracer, TextTracer,
+};
+
+const PROGRAM: [u32; 4] = [
+    0x10000113, // addi x2, x0, 0x100
+    0x00012183, // lw x3, 0(x2)
+    0x00A00893, // addi x17, x0, 10
+    0x00000073, // ecall
+];
+
+fn run_traced(tracer: impl toast_interpreter::Tracer + 'static) {
+    let mut cpu_state = CPUState::new();
+    cpu_state.set_mem(0x100, 0xCAFEF00D).unwrap();
+    cpu_state.tracer = Some(Box::new(tracer));
+    let result = interpret(&words_to_bytes(&PROGRAM), &mut cpu_state);
+    assert_eq!(result.outcome, RunOutcome::Exited(0));
+}
+
+#[test]
+fn test_text_tracer() {
+    let output = SharedBuffer::default();
+    run_traced(TextTracer::new(output.clone()));
+    let text = String::from_utf8(output.contents()).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(
+        lines,
+        [
+            "0x00000000: 0x10000113  x2 <- 0x00000100",
+            "0x00000004: 0x00012183  x3 <- 0xcafef00d  mem32[0x00000100] -> 0xcafef00d",
+            "0x00000008: 0x00a00893  x17 <- 0x0000000a",
+            "0x0000000c: 0x00000073  trap: environment call at pc 0x0000000c \
+             (instruction 0x00000073, tval 0x00000000)",
+        ]
+    );
+}
+
+#[test]
+fn test_json_tracer() {
+    let output = SharedBuffer::default();
+    run_traced(JsonTracer::new(output.clone()));
+    let text = String::from_utf8(output.contents()).unwrap();
+    let records: Vec<serde_json::Value> = text
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(records.len(), 4);
+
+    let load = &records[1];
+    assert_eq!(load["pc"], 4);
+    assert_eq!(load["opcode"], 0b0000011);
+    assert_eq!(load["registers"][0]["register"], "x3");
+    assert_eq!(load["registers"][0]["value"], 0xCAFEF00Du32);
+    assert_eq!(load["memory"][0]["kind"], "read");
+    assert_eq!(load["memory"][0]["address"], 0x100);
+    assert!(load["trap"].is_null());
+
+    assert_eq!(records[3]["trap"]["cause"], 11);
+}
+
+#[test]
+fn test_spike_commit_log() {
+    let base = 0x8000_0000;
+    let text = words_to_bytes(&[
+        0x00000117, // auipc x2, 0
+        0x0FD10113, // addi x2, x2, 0xFD
+        0x05500193, // addi x3, x0, 0x55
+        0x003121A3, // sw x3, 3(x2)
+        0x00312203, // lw x4, 3(x2)
+        0x00310223, // sb x3, 4(x2)
+        0x00A00893, // addi x17, x0, 10
+        0x00000073, // ecall
+    ]);
+    let elf = build_elf(
+        base,
+        &[TestSegment {
+            address: base,
+            memory_size: 0x200,
+            data: text,
+        }],
+        &[],
+    );
+    let mut cpu_state = CPUState::with_memory(MemoryConfig { base, size: 0x1000 }).unwrap();
+    Elf::parse(&elf).unwrap().load(&mut cpu_state).unwrap();
+    let output = SharedBuffer::default();
+    cpu_state.tracer = Some(Box::new(SpikeTracer::new(output.clone())));
+    let result = cpu_state.run(100);
+    assert_eq!(result.outcome, RunOutcome::Exited(0));
+
+    let log = String::from_utf8(output.contents()).unwrap();
+    assert_eq!(
+        log,
+        "core   0: 3 0x80000000 (0x00000117) x2  0x80000000\n\
+         core   0: 3 0x80000004 (0x0fd10113) x2  0x800000fd\n\
+         core   0: 3 0x80000008 (0x05500193) x3  0x00000055\n\
+         core   0: 3 0x8000000c (0x003121a3) mem 0x80000100 0x00000055\n\
+         core   0: 3 0x80000010 (0x00312203) x4  0x00000055 mem 0x80000100\n\
+         core   0: 3 0x80000014 (0x00310223) mem 0x80000101 0x55\n\
+         core   0: 3 0x80000018 (0x00a00893) x17 0x0000000a\n"
+    );
+}
@@ -0,0 +1,148 @@
+extern crate toast_interpreter;
+
+mod common;
+
+use common::words_to_bytes;
+use toast_interpreter::csr::{MCAUSE_INTERRUPT, MIP_MTIP, MSTATUS_MIE, MSTATUS_MPIE};
+use toast_interpreter::{decode_instruction, interpret_max_cycles, CPUState, RunOutcome, Trap};
+
+#[test]
+fn test_illegal_instruction() {
+    // addi x1, x0, 1; <illegal>
+    let binary = words_to_bytes(&[0x00100093, 0xFFFFFFFF]);
+    let mut cpu_state = CPUState::new();
+    let result = interpret_max_cycles(&binary, &mut cpu_state, 20);
+    assert_eq!(
+        result.outcome,
+        RunOutcome::Trapped(Trap::IllegalInstruction {
+            pc: 4,
+            instruction: 0xFFFFFFFF
+        })
+    );
+    assert_eq!(cpu_state.pc, 4);
+}
+
+#[test]
+fn test_ebreak() {
+    let binary = words_to_bytes(&[0x00100073]);
+    let mut cpu_state = CPUState::new();
+    let result = interpret_max_cycles(&binary, &mut cpu_state, 20);
+    assert_eq!(result.outcome, RunOutcome::Breakpoint { pc: 0 });
+}
+
+#[test]
+fn test_misaligned_jump() {
+    // jalr x1, 2(x0)
+    let binary = words_to_bytes(&[0x002000E7]);
+    let mut cpu_state = CPUState::new();
+    let result = interpret_max_cycles(&binary, &mut cpu_state, 20);
+    assert_eq!(
+        result.outcome,
+        RunOutcome::Trapped(Trap::InstructionAddressMisaligned {
+            pc: 0,
+            instruction: 0x002000E7,
+            address: 2
+        })
+    );
+    // the link register must not be written when the jump traps
+    assert_eq!(cpu_state.registers[1], 0);
+}
+
+fn load_words(cpu_state: &mut CPUState, address: u32, words: &[u32]) {
+    for (i, word) in words.iter().enumerate() {
+        cpu_state.set_mem(address + i as u32 * 4, *word).unwrap();
+    }
+}
+
+#[test]
+fn test_trap_vectored_to_handler() {
+    let mut cpu_state = CPUState::new();
+    cpu_state.vector_traps = true;
+    load_words(
+        &mut cpu_state,
+        0x00,
+        &[
+            0x04000293, // addi x5, x0, 0x40
+            0x30529073, // csrw mtvec, x5
+            0x00000000, // illegal
+            0x00100313, // addi x6, x0, 1
+        ],
+    );
+    load_words(
+        &mut cpu_state,
+        0x40,
+        &[
+            0x342023F3, // csrr x7, mcause
+            0x34102473, // csrr x8, mepc
+            0x00440413, // addi x8, x8, 4
+            0x34141073, // csrw mepc, x8
+            0x30200073, // mret
+        ],
+    );
+    for _ in 0..9 {
+        decode_instruction(&mut cpu_state).unwrap();
+    }
+    assert_eq!(cpu_state.registers[7], 2);
+    assert_eq!(cpu_state.csrs.mtval, 0);
+    assert_eq!(cpu_state.pc, 0x10);
+    assert_eq!(cpu_state.registers[6], 1);
+}
+
+#[test]
+fn test_timer_interrupt_wakes_wfi() {
+    let mut cpu_state = CPUState::new();
+    load_words(
+        &mut cpu_state,
+        0x00,
+        &[
+            0x04100293, // addi x5, x0, 0x41
+            0x30529073, // csrw mtvec, x5 (vectored)
+            0x08000293, // addi x5, x0, 0x80
+            0x30429073, // csrw mie, x5
+            0x30046073, // csrsi mstatus, 8
+            0x10500073, // wfi
+            0x00100313, // addi x6, x0, 1
+        ],
+    );
+    load_words(&mut cpu_state, 0x5C, &[0x00000013]); // nop
+    for _ in 0..10 {
+        decode_instruction(&mut cpu_state).unwrap();
+    }
+    assert!(cpu_state.waiting_for_interrupt);
+    assert_eq!(cpu_state.pc, 0x18);
+
+    cpu_state.csrs.mip |= MIP_MTIP;
+    decode_instruction(&mut cpu_state).unwrap();
+    assert!(!cpu_state.waiting_for_interrupt);
+    assert_eq!(cpu_state.csrs.mcause, MCAUSE_INTERRUPT | 7);
+    assert_eq!(cpu_state.csrs.mepc, 0x18);
+    // the first instruction of the timer vector has already executed
+    assert_eq!(cpu_state.pc, 0x40 + 4 * 7 + 4);
+    assert_eq!(cpu_state.csrs.mstatus & MSTATUS_MIE, 0);
+    assert_ne!(cpu_state.csrs.mstatus & MSTATUS_MPIE, 0);
+}
+
+#[test]
+fn test_cycle_limit() {
+    // jal x0, 0
+    let binary = words_to_bytes(&[0x0000006F]);
+    let mut cpu_state = CPUState::new();
+    let result = interpret_max_cycles(&binary, &mut cpu_state, 20);
+    assert_eq!(result.outcome, RunOutcome::CycleLimit);
+    assert_eq!(result.cycles, 20);
+    assert_eq!(result.instructions, 20);
+}
+
+#[test]
+fn test_run_counts() {
+    let binary = words_to_bytes(&[
+        0x00500093, // addi x1, x0, 5
+        0x00A00893, // addi x17, x0, 10
+        0x00000073, // ecall
+    ]);
+    let mut cpu_state = CPUState::new();
+    let result = interpret_max_cycles(&binary, &mut cpu_state, 20);
+    assert_eq!(result.exit_code(), Some(0));
+    assert_eq!(result.cycles, 3);
+    assert_eq!(result.instructions, 3);
+}
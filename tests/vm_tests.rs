@@ -0,0 +1,208 @@
+extern crate virtual_machine;
+
+use virtual_machine::vm;
+
+#[derive(Debug, PartialEq)]
+enum Op {
+    Load { rd: u8, imm: i8 },
+    Jump { offset: i16 },
+    Add { rd: u8, rs: u8 },
+    Sub { rd: u8, rs: u8 },
+    Halt,
+}
+
+// A 16 bit encoding with a 4 bit opcode
+vm! {
+    fields {
+        opcode: u16 = 12..16,
+        rd: u8 = 8..12,
+        rs: u8 = 4..8,
+        funct: u16 = 0..4,
+        imm: i8 = signed(0..8),
+        // split across the word and scaled by two
+        offset: i16 = signed(0..4, 4..12) << 1,
+    }
+
+    #[encodings(ENCODINGS)]
+    fn decode(word: u16) -> Option<Op> {
+        // written `load imm, rd`
+        load(imm, rd): opcode == 0x1 => Some(Op::Load { rd, imm }),
+        jump: opcode == 0x2 => Some(Op::Jump { offset }),
+        add: opcode == 0x3 && funct == 0 => Some(Op::Add { rd, rs }),
+        // subtracting a register from itself is reserved
+        sub: opcode == 0x3 && funct == 1 if rd != rs => Some(Op::Sub { rd, rs }),
+        halt: word == 0xFFFF => Some(Op::Halt),
+        _ => None,
+    }
+
+    // the same instructions dispatched differently
+    #[profile(add = 50, sub = 10, halt = 0)]
+    fn decode_profiled(word: u16) -> Option<Op> {
+        load: opcode == 0x1 => Some(Op::Load { rd, imm }),
+        jump: opcode == 0x2 => Some(Op::Jump { offset }),
+        add: opcode == 0x3 && funct == 0 => Some(Op::Add { rd, rs }),
+        sub: opcode == 0x3 && funct == 1 if rd != rs => Some(Op::Sub { rd, rs }),
+        halt: word == 0xFFFF => Some(Op::Halt),
+        _ => None,
+    }
+
+    #[jump_table]
+    fn decode_table(word: u16) -> Option<Op> {
+        load: opcode == 0x1 => Some(Op::Load { rd, imm }),
+        jump: opcode == 0x2 => Some(Op::Jump { offset }),
+        add: opcode == 0x3 && funct == 0 => Some(Op::Add { rd, rs }),
+        sub: opcode == 0x3 && funct == 1 if rd != rs => Some(Op::Sub { rd, rs }),
+        halt: word == 0xFFFF => Some(Op::Halt),
+        _ => None,
+    }
+
+    #[jump_table]
+    fn execute(word: u16, registers: &mut [u16; 16]) -> bool {
+        load: opcode == 0x1 => {
+            registers[rd as usize] = imm as u16;
+            true
+        },
+        add: opcode == 0x3 && funct == 0 => {
+            registers[rd as usize] = registers[rd as usize].wrapping_add(registers[rs as usize]);
+            true
+        },
+        _ => false,
+    }
+}
+
+// A machine with a register file
+vm! {
+    machine Counter: u8 {
+        registers: [u8; 4],
+        steps: u32,
+    }
+
+    fields {
+        opcode: u8 = 6..8,
+        register: u8 = 0..2,
+    }
+
+    fn step(&mut self, word: u8) -> bool {
+        increment: opcode == 0b01 => {
+            self.registers[register as usize] += 1;
+            self.steps += 1;
+            true
+        },
+        clear: opcode == 0b10 => {
+            self.registers[register as usize] = 0;
+            self.steps += 1;
+            true
+        },
+        _ => false,
+    }
+}
+
+#[test]
+fn test_fields() {
+    assert_eq!(decode(0x1A7F), Some(Op::Load { rd: 0xA, imm: 0x7F }));
+    assert_eq!(decode(0x1A80), Some(Op::Load { rd: 0xA, imm: -128 }));
+    // the low four bits of the word are the top of the offset
+    assert_eq!(decode(0x2123), Some(Op::Jump { offset: 0x312 << 1 }));
+    assert_eq!(
+        decode(0x2FF8),
+        Some(Op::Jump {
+            offset: -0x701 << 1
+        })
+    );
+}
+
+#[test]
+fn test_patterns() {
+    assert_eq!(decode(0x3120), Some(Op::Add { rd: 1, rs: 2 }));
+    assert_eq!(decode(0x3121), Some(Op::Sub { rd: 1, rs: 2 }));
+    assert_eq!(decode(0x3122), None);
+    assert_eq!(decode(0xFFFF), Some(Op::Halt));
+    assert_eq!(decode(0xFFFE), None);
+    assert_eq!(decode(0x0000), None);
+}
+
+#[test]
+fn test_guard() {
+    assert_eq!(decode(0x3111), None);
+    assert_eq!(decode(0x3211), Some(Op::Sub { rd: 2, rs: 1 }));
+}
+
+#[test]
+fn test_parameters() {
+    let mut registers = [0; 16];
+    assert!(execute(0x1105, &mut registers));
+    assert!(execute(0x12FF, &mut registers));
+    assert!(execute(0x3120, &mut registers));
+    assert_eq!(registers[1], 4);
+    assert!(!execute(0x2000, &mut registers));
+}
+
+#[test]
+fn test_dispatch_strategies_agree() {
+    for word in 0..=u16::MAX {
+        let op = decode(word);
+        assert_eq!(decode_profiled(word), op, "{word:#06x}");
+        assert_eq!(decode_table(word), op, "{word:#06x}");
+    }
+}
+
+#[test]
+fn test_machine() {
+    let mut counter = Counter::new();
+    assert_eq!(counter.registers, [0; 4]);
+    assert!(counter.step(0b01_000010));
+    assert!(counter.step(0b01_000010));
+    assert!(counter.step(0b01_000011));
+    assert!(counter.step(0b10_000011));
+    assert!(!counter.step(0b11_000000));
+    assert_eq!(counter.registers, [0, 0, 2, 0]);
+    assert_eq!(counter.steps, 4);
+}
+
+#[test]
+fn test_encodings() {
+    let mnemonics: Vec<_> = ENCODINGS.iter().map(|encoding| encoding.mnemonic).collect();
+    assert_eq!(mnemonics, ["load", "jump", "add", "sub", "halt"]);
+    let operands = |index: usize| -> Vec<_> {
+        ENCODINGS[index]
+            .operands
+            .iter()
+            .map(|operand| operand.name)
+            .collect()
+    };
+    assert_eq!(operands(0), ["imm", "rd"]);
+    assert_eq!(operands(2), ["rd", "rs"]);
+    assert_eq!(operands(4), [] as [&str; 0]);
+
+    for (index, values) in [
+        (0, &[-128, 0xA][..]),
+        (1, &[-0x701 << 1]),
+        (2, &[1, 2]),
+        (3, &[2, 1]),
+        (4, &[]),
+    ] {
+        let word = ENCODINGS[index].encode(values).unwrap() as u16;
+        assert!(decode(word).is_some(), "{word:#06x}");
+        assert_eq!(
+            ENCODINGS
+                .iter()
+                .find(|encoding| word as u64 & encoding.mask == encoding.bits),
+            Some(&ENCODINGS[index])
+        );
+    }
+    assert_eq!(ENCODINGS[1].encode(&[0x312 << 1]), Some(0x2123));
+    let word = ENCODINGS[0].encode(&[-128, 0xA]).unwrap() as u16;
+    assert_eq!(decode(word), Some(Op::Load { rd: 0xA, imm: -128 }));
+}
+
+#[test]
+fn test_encoding_ranges() {
+    let load = &ENCODINGS[0];
+    assert_eq!(load.encode(&[127, 0]), Some(0x107F));
+    assert_eq!(load.encode(&[128, 0]), None);
+    assert_eq!(load.encode(&[-129, 0]), None);
+    assert_eq!(load.encode(&[0, 16]), None);
+    assert_eq!(load.encode(&[0]), None);
+    // offsets are even
+    assert_eq!(ENCODINGS[1].encode(&[3]), None);
+}
@@ -1,15 +0,0 @@
-
-
-
-
-pub struct ASTParameter {
-    name : String,
-    start_range: usize,
-    end_range: usize,
-}
-
-pub struct ASTValue {
-    name : String,
-    start_range: usize,
-    end_range: usize,
-}
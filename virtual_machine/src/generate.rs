@@ -0,0 +1,742 @@
+// Code generation for a `vm!` description
+//
+// Every arm's constraints reduce to a mask and value over the word, which
+// `tree` arranges into nested matches on the fields. Whatever a match leaves
+// untested is checked with a single masked compare.
+
+use proc_macro2::{Ident, Literal, TokenStream, TokenTree};
+use quote::{format_ident, quote};
+use syn::{Error, Result, Type};
+
+use crate::parse::{Arm, Decoder, Description, Field, Machine, State};
+use crate::tree::{Pattern, Planner, Tree};
+
+// Jump tables index on at most this many bits.
+const MAX_TABLE_BITS: u32 = 12;
+
+pub fn generate(description: &Description) -> Result<TokenStream> {
+    let mut names = Vec::<&Ident>::new();
+    for field in &description.fields {
+        if names.contains(&&field.name) {
+            return Err(Error::new(field.name.span(), "duplicate field"));
+        }
+        names.push(&field.name);
+    }
+    let mut output = description
+        .machine
+        .as_ref()
+        .map(machine)
+        .unwrap_or_default();
+    if description
+        .decoders
+        .iter()
+        .any(|decoder| decoder.encodings.is_some())
+    {
+        output.extend(encoding_types());
+    }
+    let mut methods = TokenStream::new();
+    for decoder in &description.decoders {
+        let generator = Generator::new(&description.fields, decoder)?;
+        output.extend(generator.encodings());
+        match (&decoder.receiver, &description.machine) {
+            (None, _) => output.extend(generator.decoder(None)?),
+            (Some(_), Some(machine)) if machine.word_type == decoder.word_type => {
+                methods.extend(generator.decoder(Some(machine))?)
+            }
+            (Some(_), Some(machine)) => {
+                return Err(Error::new(
+                    decoder.word_type.span(),
+                    format!("`{}` has {} words", machine.name, machine.word_type),
+                ))
+            }
+            (Some(_), None) => {
+                return Err(Error::new(
+                    decoder.name.span(),
+                    "methods need a `machine` to belong to",
+                ))
+            }
+        }
+    }
+    if let Some(machine) = &description.machine {
+        let name = &machine.name;
+        output.extend(quote! {
+            impl #name {
+                #methods
+            }
+        });
+    }
+    Ok(output)
+}
+
+// The state struct, starting with every register zeroed.
+fn machine(machine: &Machine) -> TokenStream {
+    let Machine {
+        attrs,
+        vis,
+        name,
+        state,
+        ..
+    } = machine;
+    let fields = state.iter().map(|state| {
+        let State {
+            attrs,
+            vis,
+            name,
+            ty,
+        } = state;
+        quote!(#(#attrs)* #vis #name: #ty)
+    });
+    let initial = state.iter().map(|state| {
+        let name = &state.name;
+        match &state.ty {
+            Type::Array(array) => {
+                let length = &array.len;
+                quote!(#name: [::core::default::Default::default(); #length])
+            }
+            _ => quote!(#name: ::core::default::Default::default()),
+        }
+    });
+    quote! {
+        #(#attrs)*
+        #vis struct #name {
+            #(#fields,)*
+        }
+
+        impl #name {
+            #vis fn new() -> Self {
+                #name {
+                    #(#initial,)*
+                }
+            }
+        }
+
+        impl ::core::default::Default for #name {
+            fn default() -> Self {
+                Self::new()
+            }
+        }
+    }
+}
+
+/// A field checked against the width of the word.
+struct Layout<'a> {
+    field: &'a Field,
+    width: u32,
+    /// The bits of the word, set when the field is a single unsigned range
+    /// that patterns can match on.
+    mask: Option<u64>,
+}
+
+struct Generator<'a> {
+    decoder: &'a Decoder,
+    bits: u32,
+    layouts: Vec<Layout<'a>>,
+    patterns: Vec<Pattern>,
+    weights: Vec<u64>,
+    /// The layouts of each arm's operands, in the order they are written.
+    operands: Vec<Vec<usize>>,
+}
+
+impl<'a> Generator<'a> {
+    fn new(fields: &'a [Field], decoder: &'a Decoder) -> Result<Self> {
+        let bits = match decoder.word_type.to_string().as_str() {
+            "u8" => 8,
+            "u16" => 16,
+            "u32" => 32,
+            "u64" => 64,
+            _ => {
+                return Err(Error::new(
+                    decoder.word_type.span(),
+                    "the word must be an unsigned integer",
+                ))
+            }
+        };
+
+        let mut layouts = Vec::new();
+        for field in fields {
+            let mut width = 0;
+            for part in &field.parts {
+                if part.low >= part.high || part.high > bits {
+                    return Err(Error::new(
+                        part.span,
+                        format!("expected a non-empty range of bits below {bits}"),
+                    ));
+                }
+                width += part.high - part.low;
+            }
+            let mask = match field.parts[..] {
+                [ref part] if !field.signed && field.shift == 0 => {
+                    Some(ones(part.high - part.low) << part.low)
+                }
+                _ => None,
+            };
+            layouts.push(Layout {
+                field,
+                width: width + field.shift,
+                mask,
+            });
+        }
+
+        let mut patterns = Vec::new();
+        for (index, arm) in decoder.arms.iter().enumerate() {
+            if let Some(other) = decoder.arms[..index]
+                .iter()
+                .find(|other| other.name == arm.name)
+            {
+                let mut error = Error::new(arm.name.span(), "duplicate instruction");
+                error.combine(Error::new(other.name.span(), "first declared here"));
+                return Err(error);
+            }
+            let mut pattern = Pattern { mask: 0, value: 0 };
+            for constraint in &arm.constraints {
+                let (low, width) = if constraint.field == decoder.word {
+                    (0, bits)
+                } else {
+                    let layout = layouts
+                        .iter()
+                        .find(|layout| layout.field.name == constraint.field)
+                        .ok_or_else(|| Error::new(constraint.field.span(), "unknown field"))?;
+                    if layout.mask.is_none() {
+                        return Err(Error::new(
+                            constraint.field.span(),
+                            "only fields of one unsigned range can be matched",
+                        ));
+                    }
+                    (layout.field.parts[0].low, layout.width)
+                };
+                let value: u64 = constraint.value.base10_parse()?;
+                if value > ones(width) {
+                    return Err(Error::new(
+                        constraint.value.span(),
+                        format!("the value does not fit in {width} bits"),
+                    ));
+                }
+                let mask = ones(width) << low;
+                if (pattern.value ^ value << low) & pattern.mask & mask != 0 {
+                    return Err(Error::new(
+                        constraint.field.span(),
+                        "conflicts with an earlier constraint, the arm can never match",
+                    ));
+                }
+                pattern.mask |= mask;
+                pattern.value |= value << low;
+            }
+            for (other, &earlier) in decoder.arms.iter().zip(&patterns) {
+                if let Some(word) = pattern.overlap(earlier) {
+                    let digits = bits as usize / 4;
+                    let mut error = Error::new(
+                        arm.name.span(),
+                        format!(
+                            "`{}` overlaps `{}`, both match {word:#0width$x}",
+                            arm.name,
+                            other.name,
+                            width = digits + 2,
+                        ),
+                    );
+                    error.combine(Error::new(other.name.span(), "overlapping instruction"));
+                    return Err(error);
+                }
+            }
+            patterns.push(pattern);
+        }
+
+        let mut weights = vec![1; patterns.len()];
+        for (name, weight) in &decoder.profile {
+            let arm = decoder
+                .arms
+                .iter()
+                .position(|arm| arm.name == *name)
+                .ok_or_else(|| Error::new(name.span(), "no instruction of this name"))?;
+            weights[arm] = weight.base10_parse()?;
+        }
+
+        let operands = decoder
+            .arms
+            .iter()
+            .zip(&patterns)
+            .map(|(arm, &pattern)| operands(arm, pattern, &layouts))
+            .collect::<Result<_>>()?;
+
+        Ok(Generator {
+            decoder,
+            bits,
+            layouts,
+            patterns,
+            weights,
+            operands,
+        })
+    }
+
+    // The table of `#[encodings]`, an `Encoding` for each arm.
+    fn encodings(&self) -> Option<TokenStream> {
+        let (vis, name) = self.decoder.encodings.as_ref()?;
+        let encodings = self.decoder.arms.iter().enumerate().map(|(index, arm)| {
+            let mnemonic = arm.name.to_string().replace('_', ".");
+            let operands = self.operands[index].iter().map(|&layout| {
+                let field = self.layouts[layout].field;
+                let name = field.name.to_string();
+                let parts = field.parts.iter().map(|part| {
+                    let (low, high) = (part.low, part.high);
+                    quote!((#low, #high))
+                });
+                let signed = field.signed;
+                let shift = field.shift;
+                quote! {
+                    Operand {
+                        name: #name,
+                        parts: &[#(#parts),*],
+                        signed: #signed,
+                        shift: #shift,
+                    }
+                }
+            });
+            let mask = hex(self.patterns[index].mask);
+            let bits = hex(self.patterns[index].value);
+            quote! {
+                Encoding {
+                    mnemonic: #mnemonic,
+                    operands: &[#(#operands),*],
+                    mask: #mask,
+                    bits: #bits,
+                }
+            }
+        });
+        Some(quote! {
+            #vis static #name: &[Encoding] = &[#(#encodings),*];
+        })
+    }
+
+    // The decoder function, or a method of `machine` along with the methods
+    // its jump table calls.
+    fn decoder(&self, machine: Option<&Machine>) -> Result<TokenStream> {
+        let Decoder {
+            attrs,
+            vis,
+            name,
+            receiver,
+            word,
+            word_type,
+            params,
+            output,
+            fallback,
+            ..
+        } = self.decoder;
+        let masks: Vec<_> = self.layouts.iter().map(|layout| layout.mask).collect();
+        let arms: Vec<usize> = (0..self.patterns.len()).collect();
+        let mut planner = Planner::new(&masks, &self.patterns, &self.weights);
+        let tree = if self.decoder.jump_table {
+            planner.plan_switch(&arms).ok_or_else(|| {
+                Error::new(
+                    name.span(),
+                    "a jump table needs a field that every instruction matches on",
+                )
+            })?
+        } else {
+            planner.plan(&arms, 0)
+        };
+        let names: Vec<_> = params.iter().map(|(name, _)| name).collect();
+        let types: Vec<_> = params.iter().map(|(_, ty)| ty).collect();
+        let receiver = receiver.as_ref().map(|receiver| quote!(#receiver,));
+        let signature = quote!((#receiver #word: #word_type #(, #names: #types)*) -> #output);
+
+        let (field, cases) = match tree {
+            Tree::Switch { field, cases } if self.decoder.jump_table => (field, cases),
+            tree => {
+                let body = self.dispatch_or_fallback(&tree, 0);
+                return Ok(quote! {
+                    #(#attrs)*
+                    #vis fn #name #signature {
+                        #body
+                    }
+                });
+            }
+        };
+
+        let layout = &self.layouts[field];
+        if layout.width > MAX_TABLE_BITS {
+            return Err(Error::new(
+                name.span(),
+                format!(
+                    "`{}` is the first field matched and too wide for a jump table",
+                    layout.field.name
+                ),
+            ));
+        }
+        let low = layout.field.parts[0].low;
+        let mask = layout.mask.unwrap();
+        // methods call the machine's other methods, functions call functions
+        // nested in them
+        let (path, receiver_type, this) = match (machine, &self.decoder.receiver) {
+            (Some(machine), Some(receiver)) => {
+                let name = &machine.name;
+                let receiver_type = replace_self(receiver, name);
+                (
+                    quote!(#name::),
+                    Some(quote!(#receiver_type,)),
+                    Some(quote!(self,)),
+                )
+            }
+            _ => (TokenStream::new(), None, None),
+        };
+        let no_match = format_ident!("__{}_no_match", name);
+        let mut entries = vec![quote!(#path #no_match); 1 << layout.width];
+        let mut handlers = quote! {
+            #[allow(unused_variables)]
+            fn #no_match #signature {
+                #fallback
+            }
+        };
+        for (value, tree) in &cases {
+            let handler = format_ident!("__{}_case_{}", name, value >> low);
+            entries[(value >> low) as usize] = quote!(#path #handler);
+            let dispatch = self.dispatch_or_fallback(tree, mask);
+            handlers.extend(quote! {
+                #[allow(unused_variables)]
+                fn #handler #signature {
+                    #dispatch
+                }
+            });
+        }
+        let size = entries.len();
+        let index = self.slice(low, layout.width);
+        let call = quote! {
+            static __TABLE: [fn(#receiver_type #word_type #(, #types)*) -> #output; #size] =
+                [#(#entries),*];
+            __TABLE[#index as usize](#this #word #(, #names)*)
+        };
+        Ok(if machine.is_some() {
+            quote! {
+                #(#attrs)*
+                #vis fn #name #signature {
+                    #call
+                }
+                #handlers
+            }
+        } else {
+            quote! {
+                #(#attrs)*
+                #vis fn #name #signature {
+                    #handlers
+                    #call
+                }
+            }
+        })
+    }
+
+    // Evaluates the fallback if no arm in `tree` matches.
+    fn dispatch_or_fallback(&self, tree: &Tree, tested: u64) -> TokenStream {
+        let dispatch = self.dispatch(tree, tested);
+        if self.always_returns(tree, tested) {
+            return dispatch;
+        }
+        let fallback = &self.decoder.fallback;
+        quote! {
+            #dispatch
+            #fallback
+        }
+    }
+
+    fn always_returns(&self, tree: &Tree, tested: u64) -> bool {
+        match tree {
+            Tree::Switch { field, cases } => {
+                let layout = &self.layouts[*field];
+                let mask = layout.mask.unwrap();
+                self.exhaustive(layout, cases.len())
+                    && cases
+                        .iter()
+                        .all(|(_, tree)| self.always_returns(tree, tested | mask))
+            }
+            Tree::Sequence(arms) => arms.iter().any(|&arm| {
+                self.patterns[arm].mask & !tested == 0 && self.decoder.arms[arm].guard.is_none()
+            }),
+        }
+    }
+
+    // The compiler only knows a match is exhaustive when the field is the
+    // whole word.
+    fn exhaustive(&self, layout: &Layout, cases: usize) -> bool {
+        layout.width == self.bits && cases as u64 - 1 == ones(self.bits)
+    }
+
+    // Returns from the function if an arm in `tree` matches. The bits in
+    // `tested` are already known to match every arm.
+    fn dispatch(&self, tree: &Tree, tested: u64) -> TokenStream {
+        let (field, cases) = match tree {
+            Tree::Switch { field, cases } => (&self.layouts[*field], cases),
+            Tree::Sequence(arms) => {
+                return arms.iter().map(|&arm| self.test(arm, tested)).collect()
+            }
+        };
+        let mask = field.mask.unwrap();
+        let low = field.field.parts[0].low;
+        let arms = cases.iter().map(|(value, tree)| {
+            let value = Literal::u64_unsuffixed(value >> low);
+            let inner = self.dispatch(tree, tested | mask);
+            quote!(#value => { #inner })
+        });
+        let value = self.slice(low, field.width);
+        let default = (!self.exhaustive(field, cases.len())).then(|| quote!(_ => {}));
+        quote! {
+            match #value {
+                #(#arms)*
+                #default
+            }
+        }
+    }
+
+    // Tests the constraints of `arm` outside `tested` and runs it.
+    fn test(&self, arm: usize, tested: u64) -> TokenStream {
+        let word = &self.decoder.word;
+        let pattern = self.patterns[arm];
+        let run = self.run(arm);
+        let mask = pattern.mask & !tested;
+        if mask == 0 {
+            return run;
+        }
+        let value = hex(pattern.value & mask);
+        let mask = hex(mask);
+        quote! {
+            if #word & #mask == #value {
+                #run
+            }
+        }
+    }
+
+    // Binds the fields `arm` uses and returns its body.
+    fn run(&self, arm: usize) -> TokenStream {
+        let arm = &self.decoder.arms[arm];
+        let mut tokens = arm.body.clone();
+        tokens.extend(arm.guard.clone());
+        let idents = idents(&tokens);
+        let bindings = self
+            .layouts
+            .iter()
+            .filter(|layout| idents.contains(&layout.field.name))
+            .map(|layout| {
+                let name = &layout.field.name;
+                let ty = &layout.field.ty;
+                let value = self.extract(layout);
+                quote!(let #name: #ty = #value;)
+            });
+        let body = &arm.body;
+        let result = match &arm.guard {
+            Some(guard) => quote! {
+                if #guard {
+                    return #body;
+                }
+            },
+            None => quote!(return #body;),
+        };
+        quote! {
+            #(#bindings)*
+            #result
+        }
+    }
+
+    fn extract(&self, layout: &Layout) -> TokenStream {
+        let field = layout.field;
+        let ty = &field.ty;
+        let mut offset = layout.width;
+        let parts = field.parts.iter().map(|part| {
+            let width = part.high - part.low;
+            offset -= width;
+            let slice = self.slice(part.low, width);
+            if offset == 0 {
+                slice
+            } else {
+                let offset = Literal::u32_unsuffixed(offset);
+                quote!((#slice << #offset))
+            }
+        });
+        let parts: Vec<_> = parts.collect();
+        let raw = match &parts[..] {
+            [part] => part.clone(),
+            _ => quote!((#(#parts)|*)),
+        };
+        let word_type = &self.decoder.word_type;
+        let (value, value_type) = if field.signed {
+            let signed = format_ident!("i{}", self.bits);
+            let unused = self.bits - layout.width;
+            let value = if unused == 0 {
+                quote!((#raw as #signed))
+            } else {
+                let unused = Literal::u32_unsuffixed(unused);
+                quote!((((#raw << #unused) as #signed) >> #unused))
+            };
+            (value, signed)
+        } else {
+            (raw, word_type.clone())
+        };
+        if value_type == quote!(#ty).to_string() {
+            value
+        } else {
+            quote!(#value as #ty)
+        }
+    }
+
+    // `width` bits of the word from `low`.
+    fn slice(&self, low: u32, width: u32) -> TokenStream {
+        let word = &self.decoder.word;
+        let shifted = if low == 0 {
+            quote!(#word)
+        } else {
+            let low = Literal::u32_unsuffixed(low);
+            quote!((#word >> #low))
+        };
+        if low + width == self.bits {
+            shifted
+        } else {
+            let mask = hex(ones(width));
+            quote!((#shifted & #mask))
+        }
+    }
+}
+
+// `&mut self` becomes `&mut Machine`.
+fn replace_self(tokens: &TokenStream, name: &Ident) -> TokenStream {
+    tokens
+        .clone()
+        .into_iter()
+        .map(|token| match token {
+            TokenTree::Ident(ident) if ident == "self" => TokenTree::Ident(name.clone()),
+            token => token,
+        })
+        .collect()
+}
+
+fn ones(width: u32) -> u64 {
+    u64::MAX >> (64 - width)
+}
+
+fn hex(value: u64) -> Literal {
+    format!("{value:#x}").parse().unwrap()
+}
+
+// Every identifier in `tokens`, in order.
+fn idents(tokens: &TokenStream) -> Vec<Ident> {
+    let mut idents = Vec::new();
+    for token in tokens.clone() {
+        match token {
+            TokenTree::Ident(ident) => idents.push(ident),
+            TokenTree::Group(group) => idents.extend(self::idents(&group.stream())),
+            _ => {}
+        }
+    }
+    idents
+}
+
+// The fields the assembler writes for `arm`: those it lists, or else the
+// fields its body mentions that the pattern leaves free.
+fn operands(arm: &Arm, pattern: Pattern, layouts: &[Layout]) -> Result<Vec<usize>> {
+    let free = |layout: &Layout| {
+        let bits = layout.field.parts.iter().fold(0, |bits, part| {
+            bits | ones(part.high - part.low) << part.low
+        });
+        bits & pattern.mask == 0
+    };
+    let Some(names) = &arm.operands else {
+        let mut tokens = arm.body.clone();
+        tokens.extend(arm.guard.clone());
+        let mut operands = Vec::new();
+        for ident in idents(&tokens) {
+            let position = layouts.iter().position(|layout| layout.field.name == ident);
+            if let Some(position) = position {
+                if free(&layouts[position]) && !operands.contains(&position) {
+                    operands.push(position);
+                }
+            }
+        }
+        return Ok(operands);
+    };
+    let mut operands = Vec::new();
+    for name in names {
+        let position = layouts
+            .iter()
+            .position(|layout| layout.field.name == *name)
+            .ok_or_else(|| Error::new(name.span(), "unknown field"))?;
+        if operands.contains(&position) {
+            return Err(Error::new(name.span(), "duplicate operand"));
+        }
+        if !free(&layouts[position]) {
+            return Err(Error::new(
+                name.span(),
+                "the pattern fixes bits of this field",
+            ));
+        }
+        operands.push(position);
+    }
+    Ok(operands)
+}
+
+// The types of the tables generated by `#[encodings]`.
+fn encoding_types() -> TokenStream {
+    quote! {
+        /// How to assemble an instruction: the bits its pattern fixes and
+        /// the fields written from its operands.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub struct Encoding {
+            pub mnemonic: &'static str,
+            /// In the order they are written.
+            pub operands: &'static [Operand],
+            pub mask: u64,
+            pub bits: u64,
+        }
+
+        impl Encoding {
+            /// The word with each operand set to its value, or `None` when
+            /// a value is missing or does not fit.
+            pub fn encode(&self, values: &[i64]) -> ::core::option::Option<u64> {
+                if values.len() != self.operands.len() {
+                    return ::core::option::Option::None;
+                }
+                self.operands
+                    .iter()
+                    .zip(values)
+                    .try_fold(self.bits, |word, (operand, &value)| operand.insert(word, value))
+            }
+        }
+
+        /// A field of the word holding an operand.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub struct Operand {
+            pub name: &'static str,
+            /// Bit ranges `(low, high)` concatenated from most significant.
+            pub parts: &'static [(u32, u32)],
+            pub signed: bool,
+            /// Zero bits below the parts.
+            pub shift: u32,
+        }
+
+        impl Operand {
+            /// The bits of a value, including the zeros below the parts.
+            pub fn width(&self) -> u32 {
+                self.parts.iter().map(|&(low, high)| high - low).sum::<u32>() + self.shift
+            }
+
+            /// Sets the operand's bits of `word` to `value`, or returns
+            /// `None` when it is out of range or has bits below the shift.
+            pub fn insert(&self, word: u64, value: i64) -> ::core::option::Option<u64> {
+                let width = self.width();
+                let (min, end) = if self.signed {
+                    (-(1i128 << (width - 1)), 1i128 << (width - 1))
+                } else {
+                    (0, 1i128 << width)
+                };
+                if (value as i128) < min
+                    || value as i128 >= end
+                    || value & ((1i64 << self.shift) - 1) != 0
+                {
+                    return ::core::option::Option::None;
+                }
+                let mut raw = (value >> self.shift) as u64;
+                let mut word = word;
+                for &(low, high) in self.parts.iter().rev() {
+                    let mask = (u64::MAX >> (64 - (high - low))) << low;
+                    word = word & !mask | (raw << low) & mask;
+                    raw >>= high - low;
+                }
+                ::core::option::Option::Some(word)
+            }
+        }
+    }
+}
@@ -1,71 +1,153 @@
 extern crate proc_macro;
 
+mod generate;
+mod parse;
+mod tree;
+
 use proc_macro::TokenStream;
-use proc_macro2::{TokenStream as TokenStream2, TokenTree};
-use quote::quote;
 use syn::parse_macro_input;
 
+/// Generates instruction decoders from a description of the encoding.
+///
+/// A description declares the fields of the instruction word, then one or
+/// more functions taking the word. Each arm of a function names an
+/// instruction, the field values that select it and the expression it
+/// evaluates to; the `_` arm is evaluated when no instruction matches.
+///
+/// ```ignore
+/// vm! {
+///     fields {
+///         opcode: u32 = 0..7,
+///         rd: u8 = 7..12,
+///         funct3: u32 = 12..15,
+///         // concatenated from most significant, then shifted and sign-extended
+///         imm_b: i32 = signed(31..32, 7..8, 25..31, 8..12) << 1,
+///     }
+///
+///     fn decode(word: u32) -> Option<Op> {
+///         addi: opcode == 0b0010011 && funct3 == 0 => Some(Op::Addi(rd)),
+///         beq: opcode == 0b1100011 && funct3 == 0 => Some(Op::Beq(imm_b)),
+///         ecall: word == 0x73 => Some(Op::Ecall),
+///         _ => None,
+///     }
+/// }
+/// ```
+///
+/// Only fields of one unsigned range can appear in patterns, along with the
+/// word itself. An arm may add a guard with `if`, and a word failing the
+/// guard matches nothing. The fields an arm mentions are bound to their
+/// values before its guard and body run. Functions may take further
+/// parameters after the word for the bodies to use.
+///
+/// An arm whose constraints contradict each other can never match and is
+/// rejected:
+///
+/// ```compile_fail
+/// virtual_machine::vm! {
+///     fields {
+///         opcode: u32 = 0..7,
+///     }
+///
+///     fn decode(word: u32) -> Option<u32> {
+///         never: opcode == 1 && opcode == 2 => Some(0),
+///         _ => None,
+///     }
+/// }
+/// ```
+///
+/// No two instructions may match the same word, here both arms match `0x13`:
+///
+/// ```compile_fail
+/// virtual_machine::vm! {
+///     fields {
+///         opcode: u32 = 0..7,
+///         funct3: u32 = 12..15,
+///     }
+///
+///     fn decode(word: u32) -> Option<u32> {
+///         addi: opcode == 0b0010011 && funct3 == 0 => Some(0),
+///         op_imm: opcode == 0b0010011 => Some(1),
+///         _ => None,
+///     }
+/// }
+/// ```
+///
+/// The generated function
+/// matches on fields, nesting matches in the order that reaches instructions
+/// in the fewest comparisons, so decoding costs a few jumps rather than a
+/// test per instruction. A profile of how often instructions run makes the
+/// common ones cheaper, instructions it leaves out weigh 1:
+///
+/// ```ignore
+/// #[profile(addi = 20, beq = 5)]
+/// fn decode(word: u32) -> Option<Op> { ... }
+/// ```
+///
+/// With `#[jump_table]` the first match becomes an index into a table of
+/// functions, one for each value of the field. The field can be at most 12
+/// bits wide:
+///
+/// ```compile_fail
+/// virtual_machine::vm! {
+///     fields {
+///         opcode: u32 = 0..16,
+///     }
+///
+///     #[jump_table]
+///     fn decode(word: u32) -> Option<u32> {
+///         first: opcode == 0 => Some(0),
+///         second: opcode == 1 => Some(1),
+///         _ => None,
+///     }
+/// }
+/// ```
+///
+/// With `#[encodings(vis NAME)]` a function also gets a static table
+/// `NAME: &[Encoding]` for assemblers, giving each instruction's mnemonic,
+/// the bits its pattern fixes and the fields written from its operands. The
+/// mnemonic is the arm's name with underscores as dots, so `fadd_s` is
+/// `fadd.s`. Operands are the free fields the body mentions in order, or
+/// those listed after the name:
+///
+/// ```ignore
+/// #[encodings(pub ENCODINGS)]
+/// fn decode(word: u32) -> Option<Op> {
+///     // sw rs2, imm(rs1)
+///     sw(rs2, imm_s, rs1): opcode == 0b0100011 && funct3 == 2 => ...,
+///     ...
+/// }
+/// ```
+///
+/// A description may declare the machine its instructions run on, naming
+/// the width of its words and its registers, with arrays for register files.
+/// This generates the struct along with a `new` function zeroing every
+/// register, and functions taking `self` become its methods:
+///
+/// ```ignore
+/// vm! {
+///     pub machine Accumulator: u16 {
+///         pub acc: u16,
+///         pub memory: [u16; 4096],
+///     }
+///
+///     fields {
+///         opcode: u16 = 12..16,
+///         address: u16 = 0..12,
+///     }
+///
+///     pub fn execute(&mut self, word: u16) -> bool {
+///         load: opcode == 0 => {
+///             self.acc = self.memory[address as usize];
+///             true
+///         },
+///         _ => false,
+///     }
+/// }
+/// ```
 #[proc_macro]
 pub fn vm(input: TokenStream) -> TokenStream {
-    // Convert the input TokenStream into a TokenStream2
-    let tokens = parse_macro_input!(input as proc_macro2::TokenStream);
-    // let input: TokenStream2 = input.into();
-    // Iterate through each token
-    // for token in tokens {
-    //     // Here you can manipulate or process each token as needed
-    //     // For example, printing each token
-    //     println!("{}", token);
-    //     match token {
-    //         TokenTree::Ident(ident) if ident.to_string() == "range" => {
-    //             // Token is "range", do something
-    //             println!("Encountered 'range' token");
-    //         }
-    //         _ => {
-    //             // Token is not "range", do something else
-    //             // For example, printing each token
-    //             println!("{}", token);
-    //         }
-    //     }
-    // }
-
-    // Vector to store tokens in range
-    let mut range_tokens = Vec::new();
-    let mut in_range_mode = false;
-    // Iterate through each token
-    for token in tokens {
-        if in_range_mode {
-            // If we're in range mode, we collect tokens until we find ";"
-            if let TokenTree::Punct(punct) = &token {
-                if punct.to_string() == ";" {
-                    // We've reached the end of the range, exit range mode
-                    in_range_mode = false;
-                } else {
-                    // Collect the token in range
-                    range_tokens.push(token);
-                }
-            } else {
-                // Collect the token in range
-                range_tokens.push(token);
-            }
-        } else {
-            // Check if the token is "range"
-            if let TokenTree::Ident(ident) = &token {
-                if ident.to_string() == "range" {
-                    // We've encountered the "range" token, enter range mode
-                    in_range_mode = true;
-                }
-            }
-            // For simplicity, just print tokens outside the range mode
-            println!("{}", token);
-        }
-    }
-
-    // Echo back the input tokens
-    // input.into(s)
-
-    let output = quote! {
-        #&tokens
-    };
-    // Convert the `proc_macro2::TokenStream` back to `proc_macro::TokenStream`
-    output.into()
+    let description = parse_macro_input!(input as parse::Description);
+    generate::generate(&description)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
 }
@@ -0,0 +1,397 @@
+// Syntax tree of a `vm!` description and its parser
+
+use proc_macro2::{Span, TokenStream, TokenTree};
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
+use syn::{
+    braced, parenthesized, token, Attribute, Ident, LitInt, Result, Token, Type, Visibility,
+};
+
+mod keyword {
+    syn::custom_keyword!(fields);
+    syn::custom_keyword!(machine);
+    syn::custom_keyword!(signed);
+}
+
+pub struct Description {
+    pub machine: Option<Machine>,
+    pub fields: Vec<Field>,
+    pub decoders: Vec<Decoder>,
+}
+
+/// The state of the machine the instructions run on.
+pub struct Machine {
+    pub attrs: Vec<Attribute>,
+    pub vis: Visibility,
+    pub name: Ident,
+    pub word_type: Ident,
+    pub state: Vec<State>,
+}
+
+/// A register or register file, `name: [type; size]` for a file.
+pub struct State {
+    pub attrs: Vec<Attribute>,
+    pub vis: Visibility,
+    pub name: Ident,
+    pub ty: Type,
+}
+
+/// A named slice of the instruction word.
+pub struct Field {
+    pub name: Ident,
+    pub ty: Type,
+    /// Bit ranges concatenated from most significant.
+    pub parts: Vec<Bits>,
+    pub signed: bool,
+    /// Zero bits appended below the parts.
+    pub shift: u32,
+}
+
+/// The bits `low..high` of the word.
+pub struct Bits {
+    pub low: u32,
+    pub high: u32,
+    pub span: Span,
+}
+
+/// A function dispatching on an instruction word.
+pub struct Decoder {
+    pub attrs: Vec<Attribute>,
+    /// Relative frequencies of instructions from `#[profile(name = weight)]`.
+    pub profile: Vec<(Ident, LitInt)>,
+    /// Set by `#[jump_table]`.
+    pub jump_table: bool,
+    /// The table of encodings named by `#[encodings(vis NAME)]`.
+    pub encodings: Option<(Visibility, Ident)>,
+    pub vis: Visibility,
+    pub name: Ident,
+    /// `&mut self`, `&self` or `self` for methods of the machine.
+    pub receiver: Option<TokenStream>,
+    pub word: Ident,
+    pub word_type: Ident,
+    /// The parameters after the word.
+    pub params: Vec<(Ident, Type)>,
+    pub output: TokenStream,
+    pub arms: Vec<Arm>,
+    /// Evaluated when no arm matches.
+    pub fallback: TokenStream,
+}
+
+pub struct Arm {
+    pub name: Ident,
+    /// The fields written by the assembler, `name(a, b):`.
+    pub operands: Option<Vec<Ident>>,
+    pub constraints: Vec<Constraint>,
+    pub guard: Option<TokenStream>,
+    pub body: TokenStream,
+}
+
+/// `field == value`
+pub struct Constraint {
+    pub field: Ident,
+    pub value: LitInt,
+}
+
+impl Parse for Description {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let mut machine = None;
+        let mut fields = None;
+        let mut decoders = Vec::new();
+        while !input.is_empty() {
+            let fork = input.fork();
+            fork.call(Attribute::parse_outer)?;
+            fork.parse::<Visibility>()?;
+            if fork.peek(keyword::machine) {
+                let description: Machine = input.parse()?;
+                if machine.is_some() {
+                    return Err(syn::Error::new(
+                        description.name.span(),
+                        "a description has one machine",
+                    ));
+                }
+                machine = Some(description);
+            } else if input.peek(keyword::fields) {
+                let keyword: keyword::fields = input.parse()?;
+                if fields.is_some() {
+                    return Err(syn::Error::new(keyword.span, "fields are already declared"));
+                }
+                let content;
+                braced!(content in input);
+                fields = Some(content.parse_terminated(Field::parse, Token![,])?);
+            } else {
+                decoders.push(input.parse()?);
+            }
+        }
+        Ok(Description {
+            machine,
+            fields: fields.into_iter().flatten().collect(),
+            decoders,
+        })
+    }
+}
+
+impl Parse for Machine {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let attrs = input.call(Attribute::parse_outer)?;
+        let vis = input.parse()?;
+        input.parse::<keyword::machine>()?;
+        let name = input.parse()?;
+        input.parse::<Token![:]>()?;
+        let word_type = input.parse()?;
+        let content;
+        braced!(content in input);
+        let state = content.parse_terminated(State::parse, Token![,])?;
+        Ok(Machine {
+            attrs,
+            vis,
+            name,
+            word_type,
+            state: state.into_iter().collect(),
+        })
+    }
+}
+
+impl Parse for State {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let attrs = input.call(Attribute::parse_outer)?;
+        let vis = input.parse()?;
+        let name = input.parse()?;
+        input.parse::<Token![:]>()?;
+        Ok(State {
+            attrs,
+            vis,
+            name,
+            ty: input.parse()?,
+        })
+    }
+}
+
+impl Parse for Field {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let name = input.parse()?;
+        input.parse::<Token![:]>()?;
+        let ty = input.parse()?;
+        input.parse::<Token![=]>()?;
+        let signed = input.peek(keyword::signed);
+        let parts = if signed || input.peek(token::Paren) {
+            if signed {
+                input.parse::<keyword::signed>()?;
+            }
+            let content;
+            parenthesized!(content in input);
+            content
+                .parse_terminated(Bits::parse, Token![,])?
+                .into_iter()
+                .collect()
+        } else {
+            vec![input.parse()?]
+        };
+        let shift = if input.peek(Token![<<]) {
+            input.parse::<Token![<<]>()?;
+            input.parse::<LitInt>()?.base10_parse()?
+        } else {
+            0
+        };
+        Ok(Field {
+            name,
+            ty,
+            parts,
+            signed,
+            shift,
+        })
+    }
+}
+
+impl Parse for Bits {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let low: LitInt = input.parse()?;
+        input.parse::<Token![..]>()?;
+        let high: LitInt = input.parse()?;
+        let span = low.span().join(high.span()).unwrap_or(low.span());
+        Ok(Bits {
+            low: low.base10_parse()?,
+            high: high.base10_parse()?,
+            span,
+        })
+    }
+}
+
+impl Parse for Decoder {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let mut attrs = Vec::new();
+        let mut profile = Vec::new();
+        let mut jump_table = false;
+        let mut encodings = None;
+        for attr in input.call(Attribute::parse_outer)? {
+            if attr.path().is_ident("profile") {
+                profile.extend(
+                    attr.parse_args_with(Punctuated::<Weight, Token![,]>::parse_terminated)?,
+                );
+            } else if attr.path().is_ident("jump_table") {
+                attr.meta.require_path_only()?;
+                jump_table = true;
+            } else if attr.path().is_ident("encodings") {
+                encodings =
+                    Some(attr.parse_args_with(|input: ParseStream| {
+                        Ok((input.parse()?, input.parse()?))
+                    })?);
+            } else {
+                attrs.push(attr);
+            }
+        }
+        let vis = input.parse()?;
+        input.parse::<Token![fn]>()?;
+        let name = input.parse()?;
+        let params;
+        parenthesized!(params in input);
+        let receiver = if params.peek(Token![&]) || params.peek(Token![self]) {
+            let mut receiver = TokenStream::new();
+            while !params.peek(Token![,]) {
+                if params.is_empty() {
+                    return Err(params.error("expected the word after the receiver"));
+                }
+                receiver.extend([params.parse::<TokenTree>()?]);
+            }
+            params.parse::<Token![,]>()?;
+            Some(receiver)
+        } else {
+            None
+        };
+        let word = params.parse()?;
+        params.parse::<Token![:]>()?;
+        let word_type = params.parse()?;
+        let mut rest = Vec::new();
+        while !params.is_empty() {
+            params.parse::<Token![,]>()?;
+            if params.is_empty() {
+                break;
+            }
+            let name = params.parse()?;
+            params.parse::<Token![:]>()?;
+            rest.push((name, params.parse()?));
+        }
+        input.parse::<Token![->]>()?;
+        let mut output = TokenStream::new();
+        while !input.peek(token::Brace) {
+            if input.is_empty() {
+                return Err(input.error("expected the instruction arms"));
+            }
+            output.extend([input.parse::<TokenTree>()?]);
+        }
+
+        let content;
+        braced!(content in input);
+        let mut arms = Vec::new();
+        let fallback = loop {
+            if content.is_empty() {
+                return Err(content.error("expected a `_ => ...` arm for words that match nothing"));
+            }
+            if content.peek(Token![_]) {
+                content.parse::<Token![_]>()?;
+                content.parse::<Token![=>]>()?;
+                let fallback = tokens_until_comma(&content)?;
+                if content.peek(Token![,]) {
+                    content.parse::<Token![,]>()?;
+                }
+                if !content.is_empty() {
+                    return Err(content.error("the `_` arm must be the last"));
+                }
+                break fallback;
+            }
+            arms.push(content.parse()?);
+        };
+        Ok(Decoder {
+            attrs,
+            profile: profile
+                .into_iter()
+                .map(|Weight(name, weight)| (name, weight))
+                .collect(),
+            jump_table,
+            encodings,
+            vis,
+            name,
+            receiver,
+            word,
+            word_type,
+            params: rest,
+            output,
+            arms,
+            fallback,
+        })
+    }
+}
+
+/// `name = weight`
+struct Weight(Ident, LitInt);
+
+impl Parse for Weight {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let name = input.parse()?;
+        input.parse::<Token![=]>()?;
+        Ok(Weight(name, input.parse()?))
+    }
+}
+
+impl Parse for Arm {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let name = input.parse()?;
+        let operands = if input.peek(token::Paren) {
+            let content;
+            parenthesized!(content in input);
+            let operands = content.parse_terminated(Ident::parse, Token![,])?;
+            Some(operands.into_iter().collect())
+        } else {
+            None
+        };
+        input.parse::<Token![:]>()?;
+        let mut constraints = Vec::new();
+        loop {
+            let field = input.parse()?;
+            input.parse::<Token![==]>()?;
+            let value = input.parse()?;
+            constraints.push(Constraint { field, value });
+            if !input.peek(Token![&&]) {
+                break;
+            }
+            input.parse::<Token![&&]>()?;
+        }
+        let guard = if input.peek(Token![if]) {
+            input.parse::<Token![if]>()?;
+            let mut guard = TokenStream::new();
+            while !input.peek(Token![=>]) {
+                if input.is_empty() {
+                    return Err(input.error("expected `=>`"));
+                }
+                guard.extend([input.parse::<TokenTree>()?]);
+            }
+            Some(guard)
+        } else {
+            None
+        };
+        input.parse::<Token![=>]>()?;
+        let body = tokens_until_comma(input)?;
+        if !input.is_empty() {
+            input.parse::<Token![,]>()?;
+        }
+        Ok(Arm {
+            name,
+            operands,
+            constraints,
+            guard,
+            body,
+        })
+    }
+}
+
+// Bodies end at the first comma outside a group, so a body with a top level
+// comma must be wrapped in braces.
+fn tokens_until_comma(input: ParseStream) -> Result<TokenStream> {
+    let mut tokens = TokenStream::new();
+    while !input.is_empty() && !input.peek(Token![,]) {
+        tokens.extend([input.parse::<TokenTree>()?]);
+    }
+    if tokens.is_empty() {
+        return Err(input.error("expected an expression"));
+    }
+    Ok(tokens)
+}
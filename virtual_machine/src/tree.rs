@@ -0,0 +1,197 @@
+// Decision trees over the fields of an instruction word
+//
+// A node either matches on a field, which counts as one comparison however
+// many cases the match has, or tests its instructions one after another with
+// a masked compare each. The planner searches every order of fields for the
+// tree reaching instructions in the fewest comparisons, weighting each
+// instruction by how often it runs.
+
+use std::collections::{BTreeMap, HashMap};
+
+/// The bits an instruction constrains and their values.
+#[derive(Clone, Copy)]
+pub struct Pattern {
+    pub mask: u64,
+    pub value: u64,
+}
+
+impl Pattern {
+    /// Returns a word matching both patterns, if there is one.
+    pub fn overlap(self, other: Pattern) -> Option<u64> {
+        let both = (self.value ^ other.value) & self.mask & other.mask == 0;
+        both.then_some(self.value | other.value)
+    }
+}
+
+pub enum Tree {
+    /// Matches on a field, the values are the bits of the word within the
+    /// field's mask.
+    Switch {
+        field: usize,
+        cases: Vec<(u64, Tree)>,
+    },
+    /// Tests each instruction in turn.
+    Sequence(Vec<usize>),
+}
+
+pub struct Planner<'a> {
+    /// The mask of each field, or `None` for fields that cannot be matched.
+    fields: &'a [Option<u64>],
+    patterns: &'a [Pattern],
+    weights: &'a [u64],
+    /// The cheapest cost and field to match on for a set of instructions
+    /// and the bits already tested.
+    costs: HashMap<(Vec<usize>, u64), (u64, Option<usize>)>,
+}
+
+impl<'a> Planner<'a> {
+    pub fn new(fields: &'a [Option<u64>], patterns: &'a [Pattern], weights: &'a [u64]) -> Self {
+        Planner {
+            fields,
+            patterns,
+            weights,
+            costs: HashMap::new(),
+        }
+    }
+
+    /// The cheapest tree telling `arms` apart, the patterns of which must not
+    /// overlap.
+    pub fn plan(&mut self, arms: &[usize], tested: u64) -> Tree {
+        match self.cost(arms, tested).1 {
+            Some(field) => self.switch(arms, tested, field),
+            None => Tree::Sequence(self.sequence(arms)),
+        }
+    }
+
+    /// The cheapest tree that starts by matching on a field.
+    pub fn plan_switch(&mut self, arms: &[usize]) -> Option<Tree> {
+        let (_, field) = self.best_switch(arms, 0, u64::MAX)?;
+        Some(self.switch(arms, 0, field))
+    }
+
+    fn switch(&mut self, arms: &[usize], tested: u64, field: usize) -> Tree {
+        let mask = self.fields[field].unwrap();
+        let cases = self
+            .groups(arms, mask)
+            .into_iter()
+            .map(|(value, group)| (value, self.plan(&group, tested | mask)))
+            .collect();
+        Tree::Switch { field, cases }
+    }
+
+    fn cost(&mut self, arms: &[usize], tested: u64) -> (u64, Option<usize>) {
+        let key = (arms.to_vec(), tested);
+        if let Some(&cost) = self.costs.get(&key) {
+            return cost;
+        }
+        let sequence = self.sequence_cost(arms, tested);
+        let cost = match self.best_switch(arms, tested, sequence) {
+            Some((cost, field)) => (cost, Some(field)),
+            None => (sequence, None),
+        };
+        self.costs.insert(key, cost);
+        cost
+    }
+
+    // The cheapest field to match on, if any costs less than `limit`.
+    fn best_switch(&mut self, arms: &[usize], tested: u64, limit: u64) -> Option<(u64, usize)> {
+        let mut best = None;
+        let mut limit = limit;
+        let weight: u64 = arms.iter().map(|&arm| self.weights[arm]).sum();
+        for (field, mask) in self.fields.iter().enumerate() {
+            let Some(mask) = *mask else { continue };
+            // matching on a field some instruction ignores would copy that
+            // instruction into every case
+            if mask & tested != 0
+                || arms
+                    .iter()
+                    .any(|&arm| self.patterns[arm].mask & mask != mask)
+            {
+                continue;
+            }
+            let groups = self.groups(arms, mask);
+            if groups.len() < 2 {
+                continue;
+            }
+            let mut cost = weight;
+            for group in groups.values() {
+                cost = cost.saturating_add(self.cost(group, tested | mask).0);
+                if cost >= limit {
+                    break;
+                }
+            }
+            if cost < limit {
+                limit = cost;
+                best = Some((cost, field));
+            }
+        }
+        best
+    }
+
+    fn groups(&self, arms: &[usize], mask: u64) -> BTreeMap<u64, Vec<usize>> {
+        let mut groups = BTreeMap::<u64, Vec<usize>>::new();
+        for &arm in arms {
+            groups
+                .entry(self.patterns[arm].value & mask)
+                .or_default()
+                .push(arm);
+        }
+        groups
+    }
+
+    // The most frequent instructions are tested first.
+    fn sequence(&self, arms: &[usize]) -> Vec<usize> {
+        let mut arms = arms.to_vec();
+        arms.sort_by_key(|&arm| std::cmp::Reverse(self.weights[arm]));
+        arms
+    }
+
+    fn sequence_cost(&self, arms: &[usize], tested: u64) -> u64 {
+        let mut comparisons = 0;
+        let mut cost = 0;
+        for arm in self.sequence(arms) {
+            if self.patterns[arm].mask & !tested != 0 {
+                comparisons += 1;
+            }
+            cost += comparisons * self.weights[arm];
+        }
+        cost
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Four instructions told apart by the low two bits, each also fixing bit
+    // 2, so every one still takes a comparison after the match
+    const FIELDS: [Option<u64>; 1] = [Some(0b11)];
+    const PATTERNS: [Pattern; 4] = [
+        pattern(0b100),
+        pattern(0b101),
+        pattern(0b110),
+        pattern(0b111),
+    ];
+    const ARMS: [usize; 4] = [0, 1, 2, 3];
+
+    const fn pattern(value: u64) -> Pattern {
+        Pattern { mask: 0b111, value }
+    }
+
+    #[test]
+    fn test_weights_choose_split() {
+        // evenly weighted, matching on the field saves comparisons
+        let mut planner = Planner::new(&FIELDS, &PATTERNS, &[1, 1, 1, 1]);
+        assert!(matches!(
+            planner.plan(&ARMS, 0),
+            Tree::Switch { field: 0, .. }
+        ));
+
+        // one instruction dominates, testing it first is cheaper
+        let mut planner = Planner::new(&FIELDS, &PATTERNS, &[1, 1, 100, 1]);
+        match planner.plan(&ARMS, 0) {
+            Tree::Sequence(order) => assert_eq!(order[0], 2),
+            Tree::Switch { .. } => panic!("expected the instructions to be tested in turn"),
+        }
+    }
+}